@@ -0,0 +1,284 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    config::{Config, HostCloak},
+    types::Channel,
+    Iris,
+};
+
+/// Builds an `Iris` one setting at a time.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use iris_lib::Iris;
+///
+/// let iris = Iris::builder()
+///     .listen([0, 0, 0, 0].into(), 6667)
+///     .server_name("irc.example.com")
+///     .max_idle(Duration::from_secs(300))
+///     .build();
+/// iris.start().expect("failed to start iris");
+/// ```
+#[derive(Debug, Clone)]
+pub struct IrisBuilder {
+    ip_address: IpAddr,
+    port: u16,
+    config: Config,
+    host_cloak: Option<Arc<dyn HostCloak>>,
+}
+
+impl Default for IrisBuilder {
+    fn default() -> Self {
+        Self {
+            ip_address: [127, 0, 0, 1].into(),
+            port: 6991,
+            config: Config::default(),
+            host_cloak: None,
+        }
+    }
+}
+
+impl IrisBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Listen for IRC connections on `ip_address:port`. Defaults to `127.0.0.1:6991`.
+    pub fn listen(mut self, ip_address: IpAddr, port: u16) -> Self {
+        self.ip_address = ip_address;
+        self.port = port;
+        self
+    }
+
+    /// Start from an existing `Config`, replacing any settings made so far.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// See `Config::server_name`.
+    pub fn server_name(mut self, server_name: impl Into<String>) -> Self {
+        self.config.server_name = Some(server_name.into());
+        self
+    }
+
+    /// See `Config::detect_server_name`.
+    pub fn detect_server_name(mut self, detect: bool) -> Self {
+        self.config.detect_server_name = detect;
+        self
+    }
+
+    /// See `Config::max_idle`.
+    pub fn max_idle(mut self, max_idle: Duration) -> Self {
+        self.config.max_idle = Some(max_idle);
+        self
+    }
+
+    /// See `Config::ping_interval`.
+    pub fn ping_interval(mut self, interval: Duration) -> Self {
+        self.config.ping_interval = Some(interval);
+        self
+    }
+
+    /// See `Config::ping_timeout`.
+    pub fn ping_timeout(mut self, timeout: Duration) -> Self {
+        self.config.ping_timeout = Some(timeout);
+        self
+    }
+
+    /// See `Config::max_channels`.
+    pub fn max_channels(mut self, max_channels: usize) -> Self {
+        self.config.max_channels = Some(max_channels);
+        self
+    }
+
+    /// See `Config::topic_cooldown`.
+    pub fn topic_cooldown(mut self, cooldown: Duration) -> Self {
+        self.config.topic_cooldown = Some(cooldown);
+        self
+    }
+
+    /// Greet everyone who joins `channel` with `greeting`. See `Config::join_greetings`.
+    pub fn join_greeting(mut self, channel: Channel, greeting: impl Into<String>) -> Self {
+        self.config.join_greetings.insert(channel, greeting.into());
+        self
+    }
+
+    /// See `Config::broadcast_warn_threshold`.
+    pub fn broadcast_warn_threshold(mut self, threshold: usize) -> Self {
+        self.config.broadcast_warn_threshold = Some(threshold);
+        self
+    }
+
+    /// See `Config::banner`.
+    pub fn banner(mut self, banner: bool) -> Self {
+        self.config.banner = banner;
+        self
+    }
+
+    /// See `Config::unique_user_per_ip`.
+    pub fn unique_user_per_ip(mut self, unique: bool) -> Self {
+        self.config.unique_user_per_ip = unique;
+        self
+    }
+
+    /// See `Config::password`.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.config.password = Some(password.into());
+        self
+    }
+
+    /// Let `name` become a server operator with the password hashed as `password_hash`.
+    /// See `Config::opers`.
+    pub fn oper(mut self, name: impl Into<String>, password_hash: impl Into<String>) -> Self {
+        self.config.opers.insert(name.into(), password_hash.into());
+        self
+    }
+
+    /// See `Config::channel_grace`.
+    pub fn channel_grace(mut self, grace: Duration) -> Self {
+        self.config.channel_grace = grace;
+        self
+    }
+
+    /// See `Config::oper_override`.
+    pub fn oper_override(mut self, oper_override: bool) -> Self {
+        self.config.oper_override = oper_override;
+        self
+    }
+
+    /// See `Config::restrict_channel_creation`.
+    pub fn restrict_channel_creation(mut self, restrict: bool) -> Self {
+        self.config.restrict_channel_creation = restrict;
+        self
+    }
+
+    /// See `Config::require_channel_membership`.
+    pub fn require_channel_membership(mut self, require: bool) -> Self {
+        self.config.require_channel_membership = require;
+        self
+    }
+
+    /// See `Config::max_nick_len`.
+    pub fn nick_len(mut self, nick_len: usize) -> Self {
+        self.config.max_nick_len = Some(nick_len);
+        self
+    }
+
+    /// See `Config::nick_hold`.
+    pub fn nick_hold(mut self, nick_hold: Duration) -> Self {
+        self.config.nick_hold = nick_hold;
+        self
+    }
+
+    /// See `Config::cloak_secret`.
+    pub fn cloak_secret(mut self, secret: impl Into<String>) -> Self {
+        self.config.cloak_secret = Some(secret.into());
+        self
+    }
+
+    /// See `Config::privmsg_dedup_window`.
+    pub fn privmsg_dedup_window(mut self, window: Duration) -> Self {
+        self.config.privmsg_dedup_window = Some(window);
+        self
+    }
+
+    /// See `Config::network_name`.
+    pub fn network_name(mut self, network_name: impl Into<String>) -> Self {
+        self.config.network_name = Some(network_name.into());
+        self
+    }
+
+    /// See `Config::motd_path`.
+    pub fn motd_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.motd_path = Some(path.into());
+        self
+    }
+
+    /// See `Config::metrics_address`.
+    pub fn metrics_address(mut self, address: SocketAddr) -> Self {
+        self.config.metrics_address = Some(address);
+        self
+    }
+
+    /// Hide client addresses with `host_cloak` rather than the default `HmacCloak`.
+    pub fn host_cloak(mut self, host_cloak: impl HostCloak + 'static) -> Self {
+        self.host_cloak = Some(Arc::new(host_cloak));
+        self
+    }
+
+    pub fn build(self) -> Iris {
+        let mut iris = Iris::with_config(self.ip_address, self.port, self.config);
+        if let Some(host_cloak) = self.host_cloak {
+            iris.host_cloak = host_cloak;
+        }
+        iris
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::{config::HmacCloak, types::Nick};
+
+    #[derive(Debug)]
+    struct PlainCloak;
+
+    impl HostCloak for PlainCloak {
+        fn cloak(&self, _ip: IpAddr, nick: &Nick) -> String {
+            format!("{nick}.users.example.com")
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let iris = Iris::builder()
+            .listen([0, 0, 0, 0].into(), 6667)
+            .server_name("irc.example.com")
+            .max_idle(Duration::from_secs(300))
+            .max_channels(10)
+            .join_greeting(Channel("#welcome".to_string()), "Hello!")
+            .restrict_channel_creation(true)
+            .nick_hold(Duration::from_secs(30))
+            .nick_len(30)
+            .host_cloak(PlainCloak)
+            .build();
+
+        assert_eq!(iris.ip_address, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(iris.port, 6667);
+        assert_eq!(iris.server.name, "irc.example.com");
+        assert_eq!(iris.config.max_idle, Some(Duration::from_secs(300)));
+        assert_eq!(iris.config.max_channels, Some(10));
+        assert_eq!(
+            iris.config
+                .join_greetings
+                .get(&Channel("#WELCOME".to_string())),
+            Some(&"Hello!".to_string())
+        );
+        assert!(iris.config.restrict_channel_creation);
+        assert_eq!(iris.config.nick_len(), 30);
+        assert!(!iris.config.banner);
+        let alice = Nick("alice".to_string());
+        assert_eq!(
+            iris.host_cloak().cloak(Ipv4Addr::LOCALHOST.into(), &alice),
+            "alice.users.example.com"
+        );
+
+        let defaults = Iris::builder().cloak_secret("hunter2").build();
+        assert_eq!(defaults.ip_address, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(defaults.port, 6991);
+        assert_eq!(
+            defaults
+                .host_cloak()
+                .cloak(Ipv4Addr::LOCALHOST.into(), &alice),
+            HmacCloak::new("hunter2").cloak(Ipv4Addr::LOCALHOST.into(), &alice)
+        );
+    }
+}
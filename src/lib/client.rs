@@ -1,43 +1,559 @@
 use std::{
-    collections::HashMap,
-    sync::{mpsc::Sender, Arc, Mutex},
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc::Sender, Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
 };
 
+use log::LevelFilter;
+
 use crate::{
+    config::{verify_password, Config},
     connect::{ConnectionError, ConnectionRead},
     errors::LoopControlError,
     events::IrcEvent,
     handler::Handler,
+    metrics::Metrics,
+    set_log_level,
     types::{
-        Channel, ErrorType, JoinMsg, JoinReply, Message, Nick, NickMsg, ParsedMessage, PartMsg,
-        PartReply, PrivMsg, PrivReply, QuitMsg, QuitReply, Reply, Target, UnparsedMessage, UserMsg,
-        WelcomeReply,
+        chanmodes_token, is_empty_command, looks_like_probe, mask_matches, normalize_mask,
+        prefix_token, split_timed_mask, statusmsg_token, AwayMsg, AwayReply, BanListReply, CapMsg,
+        CapReply, Channel, ChannelModeReply, ChannelTopicReply, Command, CreatedReply, DebugMsg,
+        EndOfBanListReply, EndOfNamesReply, EndOfWhoReply, EndOfWhoisReply, ErrorReply, ErrorType,
+        ISupportReply, InviteMsg, InviteReply, InvitingReply, IsonMsg, IsonReply, JoinMsg,
+        JoinReply, KickMsg, KickReply, KillMsg, ListMsg, ListReply, Message, ModeChange, ModeMsg,
+        ModeReply, MotdReply, NamesMsg, NamesReply, Nick, NickMsg, NickReply, NoticeMsg,
+        NoticeReply, OperMsg, ParsedMessage, PartMsg, PartReply, PassMsg, Prefix, PrivMsg,
+        PrivReply, QuitMsg, QuitReply, RemoveMsg, Reply, ServerNoticeReply, Target, TopicMsg,
+        TopicReply, UModeIsReply, UnparsedMessage, UserMsg, UserhostEntry, UserhostMsg,
+        UserhostReply, UseripMsg, WallopsMsg, WallopsReply, WelcomeReply, WhoMsg, WhoReply,
+        WhoisMsg, WhoisReply, CHANNEL_MODES, MAX_CHANNEL_LEN, MEMBER_STATUSES, SERVER_NAME,
+        SUPPORTED_CAPS, USER_MODES,
     },
 };
 
+/// What other clients can see of a logged in client.
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub sender: Sender<IrcEvent>,
+    pub user: String,
+    pub real_name: String,
+    /// Where the client connected from, source port included.
+    pub addr: SocketAddr,
+    /// When the client registered.
+    pub signon: SystemTime,
+    /// When the client last sent a PRIVMSG, or registered if it hasn't.
+    pub last_message: Instant,
+    /// Why the client is away, if it is.
+    pub away: Option<String>,
+    /// Whether the client is a server operator.
+    pub oper: bool,
+    /// Whether the client receives WALLOPS (user mode `+w`).
+    pub wallops: bool,
+    /// Whether the client is hidden from those who share no channel with it (user mode `+i`).
+    pub invisible: bool,
+    /// Why an operator killed the client, if one did. Its own connection reaps it with this.
+    pub killed: Option<String>,
+}
+
+impl ClientInfo {
+    /// The flags WHO shows for this client: `H` for here or `G` for gone (away),
+    /// then `*` if it is a server operator.
+    pub fn flags(&self) -> String {
+        let mut flags = match self.away {
+            Some(_) => String::from("G"),
+            None => String::from("H"),
+        };
+        if self.oper {
+            flags.push('*');
+        }
+        flags
+    }
+
+    /// The prefix of what the client called `nick` sends.
+    pub fn prefix(&self, nick: &Nick) -> Prefix {
+        Prefix {
+            nick: nick.clone(),
+            user: self.user.clone(),
+            host: self.addr.ip().to_string(),
+        }
+    }
+
+    /// Whether the user mode `mode`, one of `USER_MODES`, is set.
+    pub fn has_mode(&self, mode: char) -> bool {
+        match mode {
+            'i' => self.invisible,
+            'o' => self.oper,
+            'w' => self.wallops,
+            _ => false,
+        }
+    }
+
+    /// Set or unset the user mode `mode`. Returns whether anything changed.
+    pub fn set_mode(&mut self, mode: char, set: bool) -> bool {
+        let flag = match mode {
+            'i' => &mut self.invisible,
+            'o' => &mut self.oper,
+            'w' => &mut self.wallops,
+            _ => return false,
+        };
+        std::mem::replace(flag, set) != set
+    }
+
+    /// The user modes that are set, e.g. `+iw`.
+    pub fn modes(&self) -> String {
+        let modes = USER_MODES
+            .chars()
+            .filter(|mode| self.has_mode(*mode))
+            .collect::<String>();
+        format!("+{modes}")
+    }
+}
+
+/// What a parse `error` for `message` is about: the command for unknown commands and missing
+/// parameters, or the nickname for a bad one. Other errors aren't about anything in particular.
+fn error_subject(error: ErrorType, message: &str) -> Option<String> {
+    let mut words = message
+        .trim_end_matches(['\r', '\n'])
+        .split(' ')
+        .filter(|word| !word.is_empty())
+        .skip_while(|word| word.starts_with(':'));
+    match error {
+        ErrorType::UnknownCommand | ErrorType::NeedMoreParams => words.next(),
+        ErrorType::ErroneousNickname => words.nth(1),
+        _ => None,
+    }
+    .map(|word| word.trim_start_matches(':').to_string())
+}
+
+/// Whether `message` is a NOTICE, going by its command alone.
+fn is_notice(message: &str) -> bool {
+    message
+        .split(' ')
+        .filter(|word| !word.is_empty())
+        .find(|word| !word.starts_with(':'))
+        .is_some_and(|verb| {
+            verb.trim_end_matches(['\r', '\n'])
+                .eq_ignore_ascii_case("NOTICE")
+        })
+}
+
+/// Every logged in client, keyed by nickname.
+pub type Clients = Arc<Mutex<HashMap<Nick, ClientInfo>>>;
+
+/// A nickname kept for a client that dropped its connection without quitting.
+#[derive(Debug, Clone, Copy)]
+pub struct NickHold {
+    pub ip: IpAddr,
+    pub until: Instant,
+}
+
+/// Nicknames being held after an unclean disconnect, see `Config::nick_hold`.
+pub type HeldNicks = Arc<Mutex<HashMap<Nick, NickHold>>>;
+
+/// Someone in a channel, and their status there.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub sender: Sender<IrcEvent>,
+    /// Whether the member is a channel operator, who may change the channel's modes.
+    pub op: bool,
+    /// Whether the member may speak in a moderated channel.
+    pub voice: bool,
+}
+
+impl Member {
+    pub fn new(sender: Sender<IrcEvent>) -> Self {
+        Self {
+            sender,
+            op: false,
+            voice: false,
+        }
+    }
+
+    /// Whether the member has the status given by `mode`, one of `MEMBER_STATUSES`.
+    pub fn has_status(&self, mode: char) -> bool {
+        match mode {
+            'o' => self.op,
+            'v' => self.voice,
+            _ => false,
+        }
+    }
+
+    /// Give or take away the status given by `mode`. Returns whether anything changed.
+    pub fn set_status(&mut self, mode: char, set: bool) -> bool {
+        let status = match mode {
+            'o' => &mut self.op,
+            'v' => &mut self.voice,
+            _ => return false,
+        };
+        std::mem::replace(status, set) != set
+    }
+
+    /// Whether the member has the status shown by `prefix` or a higher one,
+    /// e.g. operators count for `+`.
+    pub fn has_status_at_least(&self, prefix: char) -> bool {
+        let Some(rank) = MEMBER_STATUSES
+            .iter()
+            .position(|(_, status)| *status == prefix)
+        else {
+            return false;
+        };
+        MEMBER_STATUSES[..=rank]
+            .iter()
+            .any(|(mode, _)| self.has_status(*mode))
+    }
+
+    /// The prefix for the member's highest status, shown before their nick, e.g. `@`.
+    pub fn prefix(&self) -> Option<char> {
+        MEMBER_STATUSES
+            .iter()
+            .find(|(mode, _)| self.has_status(*mode))
+            .map(|(_, prefix)| *prefix)
+    }
+
+    /// What to show before the member's nick: the prefix for every status they have
+    /// if `multi_prefix`, e.g. `@+`, otherwise only the highest one's.
+    pub fn prefixes(&self, multi_prefix: bool) -> String {
+        if multi_prefix {
+            MEMBER_STATUSES
+                .iter()
+                .filter(|(mode, _)| self.has_status(*mode))
+                .map(|(_, prefix)| prefix)
+                .collect()
+        } else {
+            self.prefix().map(String::from).unwrap_or_default()
+        }
+    }
+}
+
+/// Everything the server keeps about a channel while it exists.
+#[derive(Debug)]
+pub struct ChannelState {
+    pub topic: Option<String>,
+    /// When the topic was last changed, for `Config::topic_cooldown`.
+    pub topic_set: Option<Instant>,
+    pub members: HashMap<Nick, Member>,
+    /// Whether only members may send to the channel (`+n`). On for new channels.
+    pub no_external_messages: bool,
+    /// Whether only operators and voiced members may send to the channel (`+m`).
+    pub moderated: bool,
+    /// Whether only those invited may join (`+i`).
+    pub invite_only: bool,
+    /// Masks of those who may not join, or speak unless voiced (`+b`).
+    pub bans: Vec<String>,
+    /// Masks of those who may not speak unless voiced, but may join (`+q`).
+    pub mutes: Vec<String>,
+    /// When each timed mute in `mutes` is lifted.
+    pub mute_expiries: HashMap<String, Instant>,
+    /// The key needed to join (`+k`), if there is one.
+    pub key: Option<String>,
+    /// The most members the channel may have (`+l`), if there is a limit.
+    pub limit: Option<usize>,
+    /// Those invited to the channel who haven't joined it yet.
+    pub invites: HashSet<Nick>,
+    /// When the last member left, while the channel is kept for `Config::channel_grace`.
+    pub emptied: Option<Instant>,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            topic: None,
+            topic_set: None,
+            members: HashMap::new(),
+            no_external_messages: true,
+            moderated: false,
+            invite_only: false,
+            invites: HashSet::new(),
+            bans: Vec::new(),
+            mutes: Vec::new(),
+            mute_expiries: HashMap::new(),
+            key: None,
+            limit: None,
+            emptied: None,
+        }
+    }
+}
+
+impl ChannelState {
+    /// Whether the channel has been empty for longer than `grace`, and should be deleted.
+    /// The first time it is found empty starts the grace period.
+    pub fn should_delete(&mut self, grace: Duration) -> bool {
+        if !self.members.is_empty() {
+            self.emptied = None;
+            return false;
+        }
+        self.emptied.get_or_insert_with(Instant::now).elapsed() >= grace
+    }
+
+    /// Whether the flag given by `mode`, one of the last group of `CHANNEL_MODES`, is set.
+    pub fn has_flag(&self, mode: char) -> bool {
+        match mode {
+            'i' => self.invite_only,
+            'n' => self.no_external_messages,
+            'm' => self.moderated,
+            _ => false,
+        }
+    }
+
+    /// Set or unset the flag given by `mode`. Returns whether anything changed.
+    pub fn set_flag(&mut self, mode: char, set: bool) -> bool {
+        let flag = match mode {
+            'i' => &mut self.invite_only,
+            'n' => &mut self.no_external_messages,
+            'm' => &mut self.moderated,
+            _ => return false,
+        };
+        std::mem::replace(flag, set) != set
+    }
+
+    /// The entries of the list given by `mode`, one of the first group of `CHANNEL_MODES`.
+    pub fn list(&self, mode: char) -> &[String] {
+        match mode {
+            'b' => &self.bans,
+            'q' => &self.mutes,
+            _ => &[],
+        }
+    }
+
+    /// Add `entry` to, or remove it from, the list given by `mode`.
+    /// Returns whether anything changed.
+    pub fn set_list_entry(&mut self, mode: char, entry: &str, set: bool) -> bool {
+        let list = match mode {
+            'b' => &mut self.bans,
+            'q' => &mut self.mutes,
+            _ => return false,
+        };
+        let position = list
+            .iter()
+            .position(|existing| existing.eq_ignore_ascii_case(entry));
+        match (set, position) {
+            (true, None) => list.push(entry.to_string()),
+            (false, Some(position)) => {
+                let removed = list.remove(position);
+                self.mute_expiries.remove(&removed);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Whether `hostmask`, as `nick!user@host`, matches any of the channel's bans.
+    pub fn is_banned(&self, hostmask: &str) -> bool {
+        self.bans.iter().any(|ban| mask_matches(ban, hostmask))
+    }
+
+    /// Whether `hostmask`, as `nick!user@host`, matches any of the channel's mutes.
+    pub fn is_muted(&self, hostmask: &str) -> bool {
+        self.mutes.iter().any(|mute| mask_matches(mute, hostmask))
+    }
+
+    /// Lift the timed mutes that have run out, returning their masks.
+    pub fn expire_mutes(&mut self) -> Vec<String> {
+        let now = Instant::now();
+        let expired = self
+            .mute_expiries
+            .iter()
+            .filter(|(_, expiry)| **expiry <= now)
+            .map(|(mask, _)| mask.clone())
+            .collect::<Vec<_>>();
+        for mask in &expired {
+            self.set_list_entry('q', mask, false);
+        }
+        expired
+    }
+
+    /// The value of the setting given by `mode`, one of the middle groups of `CHANNEL_MODES`,
+    /// if it is set.
+    pub fn setting(&self, mode: char) -> Option<String> {
+        match mode {
+            'k' => self.key.clone(),
+            'l' => self.limit.map(|limit| limit.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Set the setting given by `mode` to `value`, or unset it if `None`.
+    /// Returns whether anything changed.
+    pub fn set_setting(&mut self, mode: char, value: Option<String>) -> bool {
+        match mode {
+            'k' => std::mem::replace(&mut self.key, value.clone()) != value,
+            // a limit that isn't a number changes nothing
+            'l' => match value.map(|value| value.parse()).transpose() {
+                Ok(limit) => std::mem::replace(&mut self.limit, limit) != limit,
+                Err(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// The channel's modes as shown by RPL_CHANNELMODEIS, e.g. `+ik secret`.
+    /// The values of settings are left off unless `with_values`, as a key is only for members.
+    pub fn modes(&self, with_values: bool) -> String {
+        let flags = CHANNEL_MODES[3].chars().filter(|mode| self.has_flag(*mode));
+        let settings = CHANNEL_MODES[1..3]
+            .iter()
+            .flat_map(|modes| modes.chars())
+            .filter_map(|mode| self.setting(mode).map(|value| (mode, value)))
+            .collect::<Vec<_>>();
+
+        let mut modes = std::iter::once('+')
+            .chain(flags)
+            .chain(settings.iter().map(|(mode, _)| *mode))
+            .collect::<String>();
+        if with_values {
+            for (_, value) in settings {
+                modes.push(' ');
+                modes.push_str(&value);
+            }
+        }
+        modes
+    }
+}
+
+/// Every channel and its state.
+pub type Channels = Arc<Mutex<HashMap<Channel, ChannelState>>>;
+
+/// Lock `mutex`, shared between every client, recovering it if a thread panicked while holding it.
+/// The panicking client is reaped on its own, so the rest can carry on with the data as it was left.
+pub(crate) fn lock<'a, T>(mutex: &'a Mutex<T>, name: &str) -> MutexGuard<'a, T> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        log::warn!("Recovering poisoned {name} lock");
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Delete the channels that have been empty for longer than `grace`.
+/// See `Config::channel_grace`.
+pub fn reap_channels(channels: &Channels, grace: Duration) {
+    lock(channels, "channels").retain(|channel, state| {
+        let delete = state.should_delete(grace);
+        if delete {
+            log::info!("Channel {channel} stayed empty... deleting");
+        }
+        !delete
+    });
+}
+
+/// Lift the timed mutes that have run out, telling each channel as if the server
+/// had unset them with MODE.
+pub fn expire_mutes(channels: &Channels, server_name: &str) {
+    let expired = lock(channels, "channels")
+        .iter_mut()
+        .filter_map(|(channel, state)| {
+            let masks = state.expire_mutes();
+            let recipients = state
+                .members
+                .values()
+                .map(|member| member.sender.clone())
+                .collect::<Vec<_>>();
+            (!masks.is_empty()).then(|| (channel.clone(), masks, recipients))
+        })
+        .collect::<Vec<_>>();
+
+    for (channel, masks, recipients) in expired {
+        log::info!("Mutes on {channel} expired: {}", masks.join(" "));
+        let message = Reply::Mode(ModeReply {
+            sender: None,
+            target: Target::Channel(channel),
+            changes: masks
+                .into_iter()
+                .map(|mask| ModeChange {
+                    set: false,
+                    mode: 'q',
+                    param: Some(mask),
+                })
+                .collect(),
+        })
+        .format(server_name);
+        for sender in recipients {
+            // anyone gone is pruned by the next broadcast that notices
+            let _ = sender.send(IrcEvent::Send(message.clone()));
+        }
+    }
+}
+
+/// Facts about the server, fixed once it has been created.
+#[derive(Debug, Clone)]
+pub struct ServerInfo {
+    /// The name the server introduces itself as, see `Config::resolve_server_name`.
+    pub name: String,
+    /// When the server was created, for RPL_CREATED.
+    pub created: SystemTime,
+    /// The message of the day, one entry per line. Empty if there is none.
+    pub motd: Vec<String>,
+}
+
+impl Default for ServerInfo {
+    fn default() -> Self {
+        Self {
+            name: SERVER_NAME.to_string(),
+            created: SystemTime::now(),
+            motd: Vec::new(),
+        }
+    }
+}
+
 pub struct Client {
     pub nick: Option<Nick>,
     pub user: Option<String>,
+    pub real_name: Option<String>,
+    /// The password given with PASS, checked against `Config::password` on registering.
+    password: Option<String>,
+    /// Whether the client is a server operator, see OPER.
+    pub is_oper: bool,
+    /// Whether the client left with a QUIT, rather than losing its connection.
+    quit: bool,
+    last_activity: Instant,
+    /// When the client was sent a keepalive PING that it hasn't answered yet.
+    ping_sent: Option<Instant>,
+    /// The last message sent to each target, and when, for `privmsg_dedup_window`.
+    recent_messages: HashMap<Target, (String, Instant)>,
+    /// The capabilities enabled with CAP REQ, out of `SUPPORTED_CAPS`.
+    caps: HashSet<String>,
     conn_read: ConnectionRead,
     conn_write: Sender<IrcEvent>,
-    clients: Arc<Mutex<HashMap<Nick, Sender<IrcEvent>>>>,
-    channels: Arc<Mutex<HashMap<Channel, HashMap<Nick, Sender<IrcEvent>>>>>,
+    config: Arc<Config>,
+    metrics: Arc<Metrics>,
+    clients: Clients,
+    channels: Channels,
+    held_nicks: HeldNicks,
+    server: Arc<ServerInfo>,
 }
 
 impl Client {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         conn_read: ConnectionRead,
         conn_write: Sender<IrcEvent>,
-        clients: Arc<Mutex<HashMap<Nick, Sender<IrcEvent>>>>,
-        channels: Arc<Mutex<HashMap<Channel, HashMap<Nick, Sender<IrcEvent>>>>>,
+        config: Arc<Config>,
+        metrics: Arc<Metrics>,
+        clients: Clients,
+        channels: Channels,
+        held_nicks: HeldNicks,
+        server: Arc<ServerInfo>,
     ) -> Self {
         Self {
             conn_read,
             conn_write,
+            config,
+            metrics,
             clients,
             channels,
+            held_nicks,
+            server,
             nick: None,
             user: None,
+            real_name: None,
+            password: None,
+            is_oper: false,
+            quit: false,
+            last_activity: Instant::now(),
+            ping_sent: None,
+            recent_messages: HashMap::new(),
+            caps: HashSet::new(),
         }
     }
 
@@ -45,28 +561,112 @@ impl Client {
         self.conn_read.id()
     }
 
+    fn lock_clients(&self) -> MutexGuard<'_, HashMap<Nick, ClientInfo>> {
+        lock(&self.clients, "clients")
+    }
+
+    fn lock_channels(&self) -> MutexGuard<'_, HashMap<Channel, ChannelState>> {
+        lock(&self.channels, "channels")
+    }
+
+    fn lock_held_nicks(&self) -> MutexGuard<'_, HashMap<Nick, NickHold>> {
+        lock(&self.held_nicks, "held nicks")
+    }
+
     pub fn send(&mut self, message: String) {
-        self.conn_write.send(IrcEvent::Send(message)).unwrap();
+        // the write loop stops when the connection fails, and the read loop follows soon after
+        if self.conn_write.send(IrcEvent::Send(message)).is_err() {
+            log::debug!("{}# Connection closed, dropping reply", self.rid());
+        }
+    }
+
+    /// Send `reply` to this client, from this server.
+    pub fn reply(&mut self, reply: Reply) {
+        let message = reply.format(&self.server.name);
+        self.send(message);
+    }
+
+    /// Send `error` to this client, about `subject` if there is one.
+    pub fn send_error(&mut self, error: ErrorType, subject: Option<String>) {
+        self.reply(Reply::Error(ErrorReply {
+            target_nick: self.nick.clone(),
+            error,
+            subject,
+        }));
     }
 
     pub fn terminate(&mut self) {
-        self.conn_write.send(IrcEvent::Terminate).unwrap();
+        // the write loop may already have stopped, e.g. when the server is shutting down
+        let _ = self.conn_write.send(IrcEvent::Terminate);
     }
 
     pub fn recv(&mut self) -> Result<String, LoopControlError> {
-        self.conn_read.read_message().map_err(|e| match e {
+        let message = self.conn_read.read_message().map_err(|e| match e {
             ConnectionError::ConnectionLost | ConnectionError::ConnectionClosed => {
                 log::error!("{}# Connection lost", self.rid());
                 LoopControlError::Break
             }
+            ConnectionError::Timeout => self.check_idle(),
+            ConnectionError::MessageInvalidUtf8 if !self.is_registered() => self.reject_probe(),
             _ => {
                 log::error!("{}# Invalid message received... ignoring", self.rid());
                 LoopControlError::Continue
             }
-        })
+        })?;
+
+        self.metrics.record_in(&message);
+        // anything at all shows the client is still there
+        self.last_activity = Instant::now();
+        self.ping_sent = None;
+        Ok(message)
+    }
+
+    /// Disconnect something that isn't an IRC client, e.g. a browser or port scanner.
+    fn reject_probe(&mut self) -> LoopControlError {
+        log::info!("{}# Not an IRC client... disconnecting", self.rid());
+        self.reply(Reply::Disconnect(String::from("This is an IRC server")));
+        LoopControlError::Break
+    }
+
+    /// Disconnect a registered client that has been idle for longer than `max_idle`.
+    /// Likewise disconnect one that hasn't answered a keepalive PING within `ping_timeout`,
+    /// or send it one if it has been quiet for `ping_interval`.
+    fn check_idle(&mut self) -> LoopControlError {
+        match self.config.max_idle {
+            Some(max_idle) if self.is_registered() && self.last_activity.elapsed() >= max_idle => {
+                log::info!("{}# Idle timeout", self.rid());
+                self.reply(Reply::Disconnect(String::from("Idle timeout")));
+                return LoopControlError::Break;
+            }
+            _ => {}
+        }
+
+        let Some(interval) = self.config.ping_interval.filter(|_| self.is_registered()) else {
+            return LoopControlError::Continue;
+        };
+        match self.ping_sent {
+            Some(ping_sent)
+                if ping_sent.elapsed() >= self.config.ping_timeout.unwrap_or(interval) =>
+            {
+                log::info!("{}# Ping timeout", self.rid());
+                self.reply(Reply::Disconnect(String::from("Ping timeout")));
+                LoopControlError::Break
+            }
+            None if self.last_activity.elapsed() >= interval => {
+                self.reply(Reply::Ping(self.server.name.clone()));
+                self.ping_sent = Some(Instant::now());
+                LoopControlError::Continue
+            }
+            _ => LoopControlError::Continue,
+        }
     }
 
     pub fn parse(&mut self, message: String) -> Result<ParsedMessage, LoopControlError> {
+        if is_empty_command(&message) {
+            log::debug!("{}# Empty message... ignoring", self.rid());
+            return Err(LoopControlError::Continue);
+        }
+
         ParsedMessage::try_from(UnparsedMessage {
             message: &message,
             // use a dummy nickname if client not logged in yet
@@ -75,8 +675,15 @@ impl Client {
                 .clone()
                 .unwrap_or_else(|| Nick(String::from("Person"))),
         })
+        .inspect(|parsed_message| {
+            self.metrics
+                .record_command(parsed_message.message.command())
+        })
         .map_err(|e| {
-            self.send(format!("{e}\r\n"));
+            // a NOTICE never gets an automatic reply, not even to say it was malformed
+            if !is_notice(&message) {
+                self.send_error(e, error_subject(e, &message));
+            }
             log::error!("{}# {e}", self.rid());
             LoopControlError::Continue
         })
@@ -90,10 +697,33 @@ impl Client {
             Message::Nick(nick_msg) => self.handle(nick_msg),
             Message::User(user_msg) => self.handle(user_msg),
             Message::PrivMsg(priv_msg) => self.handle(priv_msg),
+            Message::Notice(notice_msg) => self.handle(notice_msg),
             Message::Ping(s) => self.handle(s),
+            // only needs to arrive, see `recv`
+            Message::Pong(_) => {}
             Message::Join(join_msg) => self.handle(join_msg),
             Message::Part(part_msg) => self.handle(part_msg),
             Message::Quit(quit_msg) => self.handle(quit_msg),
+            Message::Topic(topic_msg) => self.handle(topic_msg),
+            Message::Names(names_msg) => self.handle(names_msg),
+            Message::List(list_msg) => self.handle(list_msg),
+            Message::Whois(whois_msg) => self.handle(whois_msg),
+            Message::Who(who_msg) => self.handle(who_msg),
+            Message::Away(away_msg) => self.handle(away_msg),
+            Message::Motd => self.send_motd(),
+            Message::Mode(message) => self.handle(message),
+            Message::Kick(message) => self.handle(message),
+            Message::Invite(message) => self.handle(message),
+            Message::Pass(_) => self.send_error(ErrorType::AlreadyRegistered, None),
+            Message::Oper(message) => self.handle(message),
+            Message::Kill(message) => self.handle(message),
+            Message::Ison(message) => self.handle(message),
+            Message::Userhost(message) => self.handle(message),
+            Message::Wallops(message) => self.handle(message),
+            Message::Debug(message) => self.handle(message),
+            Message::Remove(message) => self.handle(message),
+            Message::Userip(message) => self.handle(message),
+            Message::Cap(message) => self.handle(message),
         }
 
         if let Message::Quit(_) = parsed_message.message {
@@ -103,244 +733,1481 @@ impl Client {
         }
     }
 
-    pub fn login(&mut self) -> Option<Nick> {
-        loop {
-            // wait for message
-            let message = match self.recv() {
-                Ok(message) => message,
-                Err(LoopControlError::Break) => break,
-                Err(LoopControlError::Continue) => continue,
-            };
+    pub fn is_registered(&self) -> bool {
+        self.nick.is_some() && self.user.is_some()
+    }
 
-            log::info!("{}# Received message: {message}", self.rid());
+    /// Read, parse and handle a single message from the connection.
+    /// Until the client is registered, only PASS, NICK, USER, CAP and QUIT are handled.
+    pub fn step(&mut self) -> Result<(), LoopControlError> {
+        // wait for message
+        let message = self.recv()?;
 
-            // parse the received message
-            let parsed_message = match self.parse(message) {
-                Ok(parsed_message) => parsed_message,
-                Err(LoopControlError::Break) => break, // should not happen
-                Err(LoopControlError::Continue) => continue,
-            };
+        if self.is_killed() {
+            // anything still buffered after a KILL is dropped
+            return Err(LoopControlError::Break);
+        }
 
-            // handle parsed message
-            match parsed_message.message {
-                Message::Nick(nick_msg) => self.handle(nick_msg),
-                Message::User(user_msg) => self.handle(user_msg),
-                Message::Quit(_) => self.terminate(),
-                _ => {
-                    // self.send("Expected NICK or USER command... ignoring\r\n".to_string());
-                    log::warn!("{}# Expected NICK or USER command... ignoring", self.rid());
-                }
-            }
+        log::info!("{}# Received message: {message}", self.rid());
 
-            // check if logged in
-            if self.nick.is_some() && self.user.is_some() {
-                self.welcome();
-                return Some(self.nick.as_ref().unwrap().clone());
+        if !self.is_registered() && looks_like_probe(&message) {
+            return Err(self.reject_probe());
+        }
+
+        // parse the received message
+        let parsed_message = self.parse(message)?;
+
+        // handle parsed message
+        if self.is_registered() {
+            self.handle_message(parsed_message)
+        } else {
+            self.handle_login_message(parsed_message)
+        }
+    }
+
+    fn handle_login_message(
+        &mut self,
+        parsed_message: ParsedMessage,
+    ) -> Result<(), LoopControlError> {
+        match parsed_message.message {
+            Message::Nick(nick_msg) => self.handle(nick_msg),
+            Message::User(user_msg) => self.handle(user_msg),
+            Message::Pass(pass_msg) => self.handle(pass_msg),
+            Message::Cap(cap_msg) => self.handle(cap_msg),
+            Message::Quit(_) => return Err(LoopControlError::Break),
+            _ => {
+                // self.send("Expected NICK or USER command... ignoring\r\n".to_string());
+                log::warn!("{}# Expected NICK or USER command... ignoring", self.rid());
             }
         }
 
-        None
+        // check if logged in
+        if self.is_registered() {
+            self.register()?;
+        }
+
+        Ok(())
     }
 
-    fn welcome(&mut self) {
-        // send welcome message
-        self.send(
-            Reply::Welcome(WelcomeReply {
-                target_nick: self.nick.clone().unwrap(),
-                message: format!("Hi {}, welcome to IRC", self.user.clone().unwrap()),
-            })
-            .to_string(),
-        );
+    /// Claim the nickname and make the client visible to others, under a single lock.
+    fn register(&mut self) -> Result<(), LoopControlError> {
+        let nick = self.nick.clone().unwrap();
+        let user = self.user.clone().unwrap();
+        let real_name = self.real_name.clone().unwrap();
+        let ip = self.conn_read.addr().ip();
 
-        log::info!(
-            "{}# {} ({}) joined",
-            self.rid(),
-            self.user.clone().unwrap(),
-            self.nick.clone().unwrap()
+        if self
+            .config
+            .password
+            .as_ref()
+            .is_some_and(|password| self.password.as_ref() != Some(password))
+        {
+            // forget the nick, so nobody else's is released when the connection closes
+            log::info!("{}# Wrong password from {nick}", self.rid());
+            self.nick = None;
+            self.send_error(ErrorType::PasswdMismatch, None);
+            self.reply(Reply::Disconnect(String::from("Password incorrect")));
+            return Err(LoopControlError::Break);
+        }
+
+        let mut clients = self.lock_clients();
+
+        if clients.contains_key(&nick) || self.is_held(&nick) {
+            // somebody else registered this nickname first
+            drop(clients);
+            log::info!("Nickname already taken: {nick}");
+            self.nick = None;
+            self.send_error(ErrorType::NicknameInUse, Some(nick.to_string()));
+            return Ok(());
+        }
+
+        if self.config.unique_user_per_ip
+            && clients
+                .values()
+                .any(|client| client.user == user && client.addr.ip() == ip)
+        {
+            drop(clients);
+            log::info!("{}# {user} is already connected from {ip}", self.rid());
+            self.reply(Reply::Disconnect(format!(
+                "{user} is already connected from this address"
+            )));
+            return Err(LoopControlError::Break);
+        }
+
+        clients.insert(
+            nick.clone(),
+            ClientInfo {
+                sender: self.conn_write.clone(),
+                user,
+                real_name,
+                addr: self.conn_read.addr(),
+                signon: SystemTime::now(),
+                last_message: Instant::now(),
+                away: None,
+                oper: false,
+                wallops: false,
+                invisible: false,
+                killed: None,
+            },
         );
-    }
-}
+        drop(clients);
 
-impl Handler<NickMsg> for Client {
-    type Result = ();
+        // a client reclaiming its own held nick no longer needs the hold
+        self.lock_held_nicks().remove(&nick);
 
-    fn handle(&mut self, message: NickMsg) -> Self::Result {
-        if self.clients.lock().unwrap().contains_key(&message.nick) {
-            log::info!("Nickname already taken: {}", message.nick);
-            self.send(format!("{}\r\n", ErrorType::NickCollision.to_string()));
-        } else {
-            if self.nick.is_none() {
-                self.nick = Some(message.nick);
+        self.welcome();
+        Ok(())
+    }
 
-                log::debug!(
-                    "{}# Nickname set: {}",
-                    self.rid(),
-                    self.nick.clone().unwrap()
-                );
+    pub fn login(&mut self) -> Option<Nick> {
+        while !self.is_registered() {
+            if let Err(LoopControlError::Break) = self.step() {
+                return None; // connection lost during login
             }
         }
-    }
-}
 
-impl Handler<UserMsg> for Client {
-    type Result = ();
+        self.nick.clone()
+    }
 
-    fn handle(&mut self, message: UserMsg) -> Self::Result {
-        if self.user.is_none() {
-            self.user = Some(message.real_name);
+    /// Handle messages until the client quits or the connection is lost,
+    /// then release its nickname and channel memberships.
+    /// A panic while handling is caught and turned into a disconnect.
+    pub fn run(&mut self) {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            if self.login().is_some() {
+                while !matches!(self.step(), Err(LoopControlError::Break)) {}
+            }
+        }));
 
-            log::debug!(
-                "{}# Username set: {}",
-                self.rid(),
-                self.user.clone().unwrap()
+        if result.is_err() {
+            log::error!(
+                "{}# Panicked while handling messages... disconnecting",
+                self.rid()
             );
         }
-    }
-}
 
-// Ping
-impl Handler<String> for Client {
-    type Result = ();
+        self.reap();
+        self.terminate();
+    }
 
-    fn handle(&mut self, message: String) -> Self::Result {
-        self.send(Reply::Pong(message).to_string());
+    /// Whether an operator has killed this client, see KILL.
+    fn is_killed(&self) -> bool {
+        self.nick.as_ref().is_some_and(|nick| {
+            self.lock_clients()
+                .get(nick)
+                .is_some_and(|info| info.killed.is_some())
+        })
     }
-}
 
-impl Handler<PrivMsg> for Client {
-    type Result = ();
+    /// Whether `nick` is held for a client that disconnected from another address.
+    fn is_held(&self, nick: &Nick) -> bool {
+        let now = Instant::now();
+        let mut held_nicks = self.lock_held_nicks();
+        held_nicks.retain(|_, hold| hold.until > now);
 
-    fn handle(&mut self, message: PrivMsg) -> Self::Result {
-        match message.target.clone() {
-            Target::User(nick) => {
-                // pm to user
-                if let Some(client) = self.clients.clone().lock().unwrap().get_mut(&nick) {
-                    client
-                        .send(IrcEvent::Send(
-                            Reply::PrivMsg(PrivReply {
-                                message,
-                                sender_nick: self.nick.clone().unwrap(),
-                            })
-                            .to_string(),
-                        ))
-                        .unwrap();
-                } else {
-                    // no such nick
-                    self.send(format!("{}\r\n", ErrorType::NoSuchNick.to_string()));
-                };
-            }
-            Target::Channel(channel) => {
-                // pm to channel
-                if let Some(members) = self.channels.clone().lock().unwrap().get(&channel) {
-                    members.into_iter().for_each(|(nick, sender)| {
-                        if *nick != self.nick.clone().unwrap() {
-                            sender
-                                .send(IrcEvent::Send(
-                                    Reply::PrivMsg(PrivReply {
-                                        message: message.clone(),
-                                        sender_nick: self.nick.clone().unwrap(),
-                                    })
-                                    .to_string(),
-                                ))
-                                .unwrap();
-                        }
-                    });
-                } else {
-                    // no such channel
-                    self.send(format!("{}\r\n", ErrorType::NoSuchChannel.to_string()));
-                };
-            }
-        }
+        held_nicks
+            .get(nick)
+            .is_some_and(|hold| hold.ip != self.conn_read.addr().ip())
     }
-}
-
-impl Handler<JoinMsg> for Client {
-    type Result = ();
 
-    fn handle(&mut self, message: JoinMsg) -> Self::Result {
-        self.channels
-            .lock()
-            .unwrap()
-            .entry(message.channel.clone())
-            .and_modify(|members| {
-                // channel exists
-                members.insert(self.nick.clone().unwrap(), self.conn_write.clone());
-            })
-            .or_insert_with(|| {
-                // new channel
-                log::info!("New channel created: {}", message.channel);
-                let mut new_members = HashMap::new();
-                new_members.insert(self.nick.clone().unwrap(), self.conn_write.clone());
-                new_members
-            });
+    /// Remove every trace of this client from the shared maps,
+    /// and tell whoever is left in its channels that it quit.
+    /// This may run after a panic, so poisoned locks are recovered and failed sends ignored.
+    fn reap(&mut self) {
+        let (true, Some(nick)) = (self.is_registered(), self.nick.clone()) else {
+            return;
+        };
 
-        log::info!(
-            "User {} joined channel {}",
-            self.nick.clone().unwrap(),
-            message.channel
-        );
+        let killed = self
+            .lock_clients()
+            .get(&nick)
+            .and_then(|info| info.killed.clone());
 
-        if let Some(channel) = self.channels.lock().unwrap().get(&message.channel) {
-            channel.into_iter().for_each(|(_, sender)| {
-                sender
-                    .send(IrcEvent::Send(
-                        Reply::Join(JoinReply {
-                            message: message.clone(),
-                            sender_nick: self.nick.clone().unwrap(),
-                        })
-                        .to_string(),
-                    ))
-                    .unwrap();
-            })
+        // a killed client is not coming back, so its nickname is not held either
+        if !self.quit && killed.is_none() && !self.config.nick_hold.is_zero() {
+            self.lock_held_nicks().insert(
+                nick.clone(),
+                NickHold {
+                    ip: self.conn_read.addr().ip(),
+                    until: Instant::now() + self.config.nick_hold,
+                },
+            );
         }
 
-        log::debug!("Channels: {:?}", self.channels);
+        let message = killed.unwrap_or_else(|| String::from("Connection lost"));
+        self.remove_user(self.prefix(), message);
+        log::info!("{}# Reaped {nick}", self.rid());
     }
-}
-
-impl Handler<PartMsg> for Client {
-    type Result = ();
 
-    fn handle(&mut self, message: PartMsg) -> Self::Result {
-        if let Some(channel) = self.channels.lock().unwrap().get_mut(&message.channel) {
-            if let Some(_) = channel.remove(&self.nick.clone().unwrap()) {
-                // channel exists & user was in channel
-                // send message to other users
-                channel.into_iter().for_each(|(_, sender)| {
-                    sender
-                        .send(IrcEvent::Send(
-                            Reply::Part(PartReply {
-                                message: message.clone(),
-                                sender_nick: self.nick.clone().unwrap(),
-                            })
-                            .to_string(),
-                        ))
-                        .unwrap();
-                });
+    /// Remove `sender` from the clients and from its channels,
+    /// and tell whoever is left in them that it quit with `message`.
+    fn remove_user(&self, sender: Prefix, message: String) {
+        let nick = &sender.nick;
+        self.lock_clients().remove(nick);
 
-                log::info!(
-                    "User {} left channel {}",
-                    self.nick.clone().unwrap(),
-                    message.channel
+        // keyed by nick so someone sharing several channels hears about it once
+        let mut recipients = HashMap::new();
+        self.lock_channels().retain(|_, channel| {
+            if channel.members.remove(nick).is_some() {
+                recipients.extend(
+                    channel
+                        .members
+                        .iter()
+                        .map(|(nick, member)| (nick.clone(), member.sender.clone())),
                 );
             }
-        }
+            !channel.should_delete(self.config.channel_grace)
+        });
 
-        // remove channel if no more members
-        let mut guard = self.channels.lock().unwrap();
-        if let None = guard.get(&message.channel).and_then(|channel| {
-            if channel.is_empty() {
-                None
-            } else {
-                Some(channel)
-            }
-        }) {
-            log::info!("Deleting channel: {}", message.channel);
-            guard.remove(&message.channel);
-        }
+        let message = Reply::Quit(QuitReply {
+            message: QuitMsg {
+                message: Some(message),
+            },
+            sender,
+        })
+        .format(&self.server.name);
+        recipients.into_values().for_each(|sender| {
+            let _ = sender.send(IrcEvent::Send(message.clone()));
+        });
+    }
 
-        drop(guard);
-        log::debug!("Channels: {:?}", self.channels);
+    /// Send `reply` to every member of `channel`, except `exclude` if given.
+    /// Members are snapshotted first so no lock is held while sending.
+    /// Returns whether the channel exists.
+    ///
+    /// Each recipient's queue is FIFO and a client handles its messages one at a time,
+    /// so everything one client sends to a channel reaches each member in the order it was sent.
+    fn broadcast_to_channel(
+        &self,
+        channel: &Channel,
+        reply: Reply,
+        exclude: Option<&Nick>,
+    ) -> bool {
+        self.broadcast_to_members(channel, None, reply, exclude)
+    }
+
+    /// Like `broadcast_to_channel`, but when `status` is given only members with
+    /// that status prefix or a higher one receive the reply.
+    fn broadcast_to_members(
+        &self,
+        channel: &Channel,
+        status: Option<char>,
+        reply: Reply,
+        exclude: Option<&Nick>,
+    ) -> bool {
+        let Some(recipients) = self.lock_channels().get(channel).map(|channel| {
+            channel
+                .members
+                .iter()
+                .filter(|(nick, _)| Some(*nick) != exclude)
+                .filter(|(_, member)| {
+                    status.is_none_or(|status| member.has_status_at_least(status))
+                })
+                .map(|(nick, member)| (nick.clone(), member.sender.clone()))
+                .collect::<Vec<_>>()
+        }) else {
+            return false;
+        };
+
+        if self
+            .config
+            .broadcast_warn_threshold
+            .is_some_and(|threshold| recipients.len() > threshold)
+        {
+            log::warn!(
+                "Large broadcast to {channel}: {} recipients",
+                recipients.len()
+            );
+        }
+
+        let message = reply.format(&self.server.name);
+        let dead = recipients
+            .into_iter()
+            .filter(|(_, sender)| sender.send(IrcEvent::Send(message.clone())).is_err())
+            .map(|(nick, _)| nick)
+            .collect::<Vec<_>>();
+        if !dead.is_empty() {
+            self.prune_members(channel, dead);
+        }
+
+        true
+    }
+
+    /// Whether this client may send messages to `channel`, or the channel doesn't exist.
+    /// Only members may, unless the channel allows external messages (`-n`)
+    /// and the server doesn't require membership regardless.
+    /// In a moderated channel (`+m`), or if banned, only operators and voiced members may.
+    fn may_send_to(&self, channel: &Channel) -> bool {
+        let Some(nick) = self.nick.as_ref() else {
+            return false;
+        };
+        let hostmask = self.prefix().to_string();
+        self.lock_channels().get(channel).is_none_or(|state| {
+            let restricted =
+                state.moderated || state.is_banned(&hostmask) || state.is_muted(&hostmask);
+            match state.members.get(nick) {
+                Some(member) => !restricted || member.op || member.voice,
+                None => {
+                    !restricted
+                        && !state.no_external_messages
+                        && !self.config.require_channel_membership
+                }
+            }
+        })
+    }
+
+    /// Show or change this client's own user modes, for MODE.
+    /// Operator status can be given up, but only OPER grants it.
+    fn change_user_modes(&mut self, nick: Nick, message: ModeMsg) {
+        let mut clients = self.lock_clients();
+        let Some(info) = clients.get_mut(&nick) else {
+            return;
+        };
+
+        if message.modes.is_none() {
+            let modes = info.modes();
+            drop(clients);
+            self.reply(Reply::UModeIs(UModeIsReply {
+                target_nick: nick,
+                modes,
+            }));
+            return;
+        }
+
+        let mut applied = Vec::new();
+        let mut unknown = false;
+        for change in message.changes() {
+            if !USER_MODES.contains(change.mode) {
+                unknown = true;
+            } else if !(change.mode == 'o' && change.set) && info.set_mode(change.mode, change.set)
+            {
+                applied.push(ModeChange {
+                    param: None,
+                    ..change
+                });
+            }
+        }
+        let is_oper = info.oper;
+        drop(clients);
+        self.is_oper = is_oper;
+
+        if unknown {
+            self.send_error(ErrorType::UModeUnknownFlag, None);
+        }
+        if !applied.is_empty() {
+            self.reply(Reply::Mode(ModeReply {
+                sender: Some(self.prefix()),
+                target: Target::User(nick),
+                changes: applied,
+            }));
+        }
+    }
+
+    /// This client as the prefix of what it sends, also matched against bans.
+    /// The host is the connecting address.
+    fn prefix(&self) -> Prefix {
+        Prefix {
+            nick: self.nick.clone().unwrap_or_else(|| Nick(String::from("*"))),
+            user: self.user.clone().unwrap_or_else(|| String::from("*")),
+            host: self.conn_read.addr().ip().to_string(),
+        }
+    }
+
+    /// Remove members of `channel` whose connections have gone away without parting.
+    fn prune_members(&self, channel: &Channel, dead: Vec<Nick>) {
+        let mut channels = self.lock_channels();
+        let Some(state) = channels.get_mut(channel) else {
+            return;
+        };
+        for nick in dead {
+            if state.members.remove(&nick).is_some() {
+                log::warn!("{}# Pruned dead member {nick} from {channel}", self.rid());
+            }
+        }
+        if state.should_delete(self.config.channel_grace) {
+            channels.remove(channel);
+        }
+    }
+
+    /// Rename a registered client everywhere it is known,
+    /// and tell it and everyone sharing a channel with it.
+    fn change_nick(&mut self, message: NickMsg) {
+        let old_nick = self.nick.clone().unwrap();
+        let new_nick = message.nick.clone();
+        // the change is seen as coming from the old nick
+        let sender = self.prefix();
+
+        // renaming to exactly the current nick changes nothing
+        if new_nick.0 == old_nick.0 {
+            return;
+        }
+
+        let mut clients = self.lock_clients();
+        // a nick equal to our own only differs in case, so isn't taken by someone else
+        if new_nick != old_nick && (clients.contains_key(&new_nick) || self.is_held(&new_nick)) {
+            drop(clients);
+            log::info!("Nickname already taken: {new_nick}");
+            self.send_error(ErrorType::NicknameInUse, Some(new_nick.to_string()));
+            return;
+        }
+        if let Some(client) = clients.remove(&old_nick) {
+            clients.insert(new_nick.clone(), client);
+        }
+        drop(clients);
+
+        // keyed by nick so someone sharing several channels hears about it once
+        let mut recipients = HashMap::from([(new_nick.clone(), self.conn_write.clone())]);
+        let mut channels = self.lock_channels();
+        channels.values_mut().for_each(|channel| {
+            if let Some(member) = channel.members.remove(&old_nick) {
+                channel.members.insert(new_nick.clone(), member);
+                recipients.extend(
+                    channel
+                        .members
+                        .iter()
+                        .map(|(nick, member)| (nick.clone(), member.sender.clone())),
+                );
+            }
+        });
+        drop(channels);
+
+        self.nick = Some(new_nick.clone());
+        log::info!("{}# {old_nick} is now known as {new_nick}", self.rid());
+
+        let message = Reply::Nick(NickReply { message, sender }).format(&self.server.name);
+        recipients.into_iter().for_each(|(nick, sender)| {
+            if sender.send(IrcEvent::Send(message.clone())).is_err() {
+                log::debug!("{}# {nick} is gone, skipping", self.rid());
+            }
+        });
+    }
+
+    /// Whether `message` repeats the last message to its target within `privmsg_dedup_window`.
+    /// If not, it becomes the last message to that target.
+    fn is_duplicate(&mut self, message: &PrivMsg) -> bool {
+        let Some(window) = self.config.privmsg_dedup_window else {
+            return false;
+        };
+
+        // forget anything outside the window so the cache stays small
+        let now = Instant::now();
+        self.recent_messages
+            .retain(|_, (_, sent_at)| now.duration_since(*sent_at) < window);
+
+        if self
+            .recent_messages
+            .get(&message.target)
+            .is_some_and(|(text, _)| *text == message.message)
+        {
+            return true;
+        }
+
+        self.recent_messages
+            .insert(message.target.clone(), (message.message.clone(), now));
+        false
+    }
+
+    /// Join a single channel, creating it if need be.
+    /// Fails with `Break` if no more channels should be joined from the same JOIN.
+    fn join_channel(
+        &mut self,
+        nick: &Nick,
+        channel: Channel,
+        key: Option<&str>,
+    ) -> Result<(), LoopControlError> {
+        let hostmask = self.prefix().to_string();
+        let mut channels = self.lock_channels();
+
+        if !channels.contains_key(&channel) {
+            // only existing channels may be joined in restricted mode, except by operators
+            if self.config.restrict_channel_creation && !self.is_oper {
+                drop(channels);
+                log::info!("Channel creation restricted, not creating: {channel}");
+                self.send_error(ErrorType::NoSuchChannel, Some(channel.to_string()));
+                return Err(LoopControlError::Continue);
+            }
+
+            // refuse to create a new channel once the server is at capacity;
+            // the rest of the JOIN can still join channels that exist
+            if self
+                .config
+                .max_channels
+                .is_some_and(|max_channels| channels.len() >= max_channels)
+            {
+                drop(channels);
+                log::info!("Channel limit reached, not creating: {channel}");
+                self.send_error(ErrorType::TooManyChannels, Some(channel.to_string()));
+                return Err(LoopControlError::Break);
+            }
+        }
+
+        let state = channels.entry(channel.clone()).or_insert_with(|| {
+            log::info!("New channel created: {channel}");
+            ChannelState::default()
+        });
+
+        // joining a channel we're already in is a no-op
+        if state.members.contains_key(nick) {
+            return Ok(());
+        }
+        if state.is_banned(&hostmask) && !self.oper_override("+b", &channel) {
+            drop(channels);
+            log::info!("User {nick} is banned from {channel}");
+            self.send_error(ErrorType::BannedFromChan, Some(channel.to_string()));
+            return Err(LoopControlError::Continue);
+        }
+        if state.invite_only && !state.invites.contains(nick) && !self.oper_override("+i", &channel)
+        {
+            drop(channels);
+            log::info!("User {nick} not invited to {channel}");
+            self.send_error(ErrorType::InviteOnlyChan, Some(channel.to_string()));
+            return Err(LoopControlError::Continue);
+        }
+        if state.key.is_some() && state.key.as_deref() != key && !self.oper_override("+k", &channel)
+        {
+            drop(channels);
+            log::info!("User {nick} gave the wrong key for {channel}");
+            self.send_error(ErrorType::BadChannelKey, Some(channel.to_string()));
+            return Err(LoopControlError::Continue);
+        }
+        // checked under the same lock as the member is added, so simultaneous joins can't overfill
+        if state
+            .limit
+            .is_some_and(|limit| state.members.len() >= limit)
+            && !self.oper_override("+l", &channel)
+        {
+            drop(channels);
+            log::info!("Channel full, {nick} not joining: {channel}");
+            self.send_error(ErrorType::ChannelIsFull, Some(channel.to_string()));
+            return Err(LoopControlError::Continue);
+        }
+        // an invite is good for one join
+        state.invites.remove(nick);
+
+        // whoever creates a channel runs it
+        let mut member = Member::new(self.conn_write.clone());
+        member.op = state.members.is_empty();
+        state.members.insert(nick.clone(), member);
+        state.emptied = None;
+
+        log::info!("User {nick} joined channel {channel}");
+        log::debug!("Channels: {channels:?}");
+        drop(channels);
+
+        // the joiner sees their own JOIN too
+        self.broadcast_to_channel(
+            &channel,
+            Reply::Join(JoinReply {
+                channel: channel.clone(),
+                sender: self.prefix(),
+            }),
+            None,
+        );
+
+        self.send_names(&channel);
+        self.send_end_of_names(Some(channel.clone()));
+
+        if let Some(greeting) = self.config.join_greetings.get(&channel).cloned() {
+            self.reply(Reply::ServerNotice(ServerNoticeReply {
+                target_nick: nick.clone(),
+                message: greeting,
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Whether this client, as a server operator, may get past `restriction` on `channel`.
+    /// See `Config::oper_override`. Each time it does is logged.
+    fn oper_override(&self, restriction: &str, channel: &Channel) -> bool {
+        let overrides = self.is_oper && self.config.oper_override;
+        if overrides {
+            let nick = self.prefix().nick;
+            log::warn!("Oper override: {nick} got past {restriction} on {channel}");
+        }
+        overrides
+    }
+
+    /// Send the list of `channel`'s members, if it exists. Doesn't end the list.
+    fn send_names(&mut self, channel: &Channel) {
+        let multi_prefix = self.caps.contains("multi-prefix");
+        let Some(mut members) = self.lock_channels().get(channel).map(|channel| {
+            channel
+                .members
+                .iter()
+                .map(|(nick, member)| (nick.clone(), member.prefixes(multi_prefix)))
+                .collect::<Vec<_>>()
+        }) else {
+            return;
+        };
+        members.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        let names = members
+            .into_iter()
+            .map(|(nick, prefixes)| format!("{prefixes}{nick}"))
+            .collect();
+
+        self.reply(Reply::Names(NamesReply {
+            target_nick: self.nick.clone().unwrap(),
+            channel: channel.clone(),
+            names,
+        }));
+    }
+
+    /// End a list of channel members, for one channel or (if `None`) all of them.
+    fn send_end_of_names(&mut self, channel: Option<Channel>) {
+        self.reply(Reply::EndOfNames(EndOfNamesReply {
+            target_nick: self.nick.clone().unwrap(),
+            channel,
+        }));
+    }
+
+    fn welcome(&mut self) {
+        let target_nick = self.nick.clone().unwrap();
+
+        // send welcome message
+        self.reply(Reply::Welcome(WelcomeReply {
+            target_nick: target_nick.clone(),
+            message: format!("Hi {}, welcome to IRC", self.real_name.clone().unwrap()),
+        }));
+        self.reply(Reply::YourHost(target_nick.clone()));
+        self.reply(Reply::Created(CreatedReply {
+            target_nick: target_nick.clone(),
+            created: self.server.created,
+        }));
+        self.reply(Reply::MyInfo(target_nick.clone()));
+        self.send_isupport();
+
+        if self.config.banner {
+            self.send_banner();
+        }
+        self.send_motd();
+
+        log::info!(
+            "{}# {} ({}) joined",
+            self.rid(),
+            self.real_name.clone().unwrap(),
+            self.nick.clone().unwrap()
+        );
+    }
+
+    /// Advertise what the server supports (RPL_ISUPPORT).
+    fn send_isupport(&mut self) {
+        let network = self
+            .config
+            .network_name
+            .clone()
+            .unwrap_or_else(|| self.server.name.clone());
+        let tokens = vec![
+            String::from("CHANTYPES=#&"),
+            prefix_token(),
+            statusmsg_token(),
+            chanmodes_token(),
+            format!("NICKLEN={}", self.config.nick_len()),
+            format!("CHANNELLEN={MAX_CHANNEL_LEN}"),
+            String::from("CASEMAPPING=rfc1459"),
+            format!("NETWORK={network}"),
+        ];
+
+        self.reply(Reply::ISupport(ISupportReply {
+            target_nick: self.nick.clone().unwrap(),
+            tokens,
+        }));
+    }
+
+    /// Send the message of the day, or 422 if there isn't one.
+    fn send_motd(&mut self) {
+        if self.server.motd.is_empty() {
+            self.send_error(ErrorType::NoMotd, None);
+            return;
+        }
+
+        self.reply(Reply::Motd(MotdReply {
+            target_nick: self.nick.clone().unwrap(),
+            lines: self.server.motd.clone(),
+        }));
+    }
+
+    /// Set the log level to `level`, if given and valid, for DEBUG LOGLEVEL. Says what it is now.
+    fn change_log_level(&self, level: Option<&str>) -> Vec<String> {
+        if let Some(level) = level {
+            match level.parse::<LevelFilter>() {
+                Ok(level) => set_log_level(level),
+                Err(_) => {
+                    return vec![format!(
+                        "Unknown log level {level}, try off, error, warn, info, debug or trace"
+                    )]
+                }
+            }
+        }
+        vec![format!("Log level: {}", log::max_level())]
+    }
+
+    /// A summary of the server's clients, channels and traffic, one line per entry, for DEBUG STATE.
+    fn state_summary(&self) -> Vec<String> {
+        let clients = self.lock_clients().len();
+        let mut channels = self
+            .lock_channels()
+            .iter()
+            .map(|(name, channel)| (name.to_string(), channel.members.len()))
+            .collect::<Vec<_>>();
+        channels.sort();
+
+        let metrics = self.metrics.snapshot();
+        let commands = metrics
+            .commands
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(command, count)| format!("{command}={count}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut lines = vec![
+            format!("Clients: {clients}"),
+            format!("Channels: {}", channels.len()),
+        ];
+        lines.extend(
+            channels
+                .into_iter()
+                .map(|(name, members)| format!("Channel {name}: {members} members")),
+        );
+        lines.extend([
+            format!("Uptime: {}s", metrics.uptime.as_secs()),
+            format!(
+                "Received: {} messages, {} bytes",
+                metrics.messages_in, metrics.bytes_in
+            ),
+            format!(
+                "Sent: {} messages, {} bytes",
+                metrics.messages_out, metrics.bytes_out
+            ),
+            format!("Commands: {commands}"),
+            String::from("End of state"),
+        ]);
+        lines
+    }
+
+    /// Tell clients without CAP what this server supports.
+    fn send_banner(&mut self) {
+        let commands = Command::ALL
+            .iter()
+            .map(Command::to_string)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let channel_limit = self
+            .config
+            .max_channels
+            .map_or(String::from("unlimited"), |max_channels| {
+                max_channels.to_string()
+            });
+
+        [
+            format!("Maximum nickname length: {}", self.config.nick_len()),
+            format!("Maximum number of channels: {channel_limit}"),
+            format!("Available commands: {commands}"),
+        ]
+        .into_iter()
+        .for_each(|message| {
+            self.reply(Reply::ServerNotice(ServerNoticeReply {
+                target_nick: self.nick.clone().unwrap(),
+                message,
+            }))
+        });
+    }
+}
+
+impl Handler<NickMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: NickMsg) -> Self::Result {
+        if message.nick.to_string().len() > self.config.nick_len() {
+            log::info!("Nickname too long: {}", message.nick);
+            self.send_error(ErrorType::ErroneousNickname, Some(message.nick.to_string()));
+        } else if self.is_registered() {
+            self.change_nick(message);
+        } else if self.lock_clients().contains_key(&message.nick) || self.is_held(&message.nick) {
+            log::info!("Nickname already taken: {}", message.nick);
+            self.send_error(ErrorType::NicknameInUse, Some(message.nick.to_string()));
+        } else {
+            if self.nick.is_none() {
+                self.nick = Some(message.nick);
+
+                log::debug!(
+                    "{}# Nickname set: {}",
+                    self.rid(),
+                    self.nick.clone().unwrap()
+                );
+            }
+        }
+    }
+}
+
+impl Handler<UserMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: UserMsg) -> Self::Result {
+        if self.is_registered() {
+            self.send_error(ErrorType::AlreadyRegistered, None);
+        } else if self.user.is_none() {
+            log::debug!(
+                "{}# Username set: {} ({})",
+                self.rid(),
+                message.username,
+                message.real_name
+            );
+
+            self.user = Some(message.username);
+            self.real_name = Some(message.real_name);
+        }
+    }
+}
+
+impl Handler<PassMsg> for Client {
+    type Result = ();
+
+    /// Only a PASS before NICK and USER counts; later ones are ignored.
+    fn handle(&mut self, message: PassMsg) -> Self::Result {
+        if self.nick.is_some() || self.user.is_some() {
+            log::warn!("{}# PASS after NICK or USER... ignoring", self.rid());
+        } else {
+            self.password = Some(message.password);
+        }
+    }
+}
+
+impl Handler<CapMsg> for Client {
+    type Result = ();
+
+    /// A REQ is all or nothing: if any capability in it isn't supported, none are changed.
+    fn handle(&mut self, message: CapMsg) -> Self::Result {
+        let (subcommand, caps) = match message.subcommand.as_str() {
+            "LS" => ("LS", SUPPORTED_CAPS.map(String::from).to_vec()),
+            "LIST" => {
+                let mut caps = self.caps.iter().cloned().collect::<Vec<_>>();
+                caps.sort();
+                ("LIST", caps)
+            }
+            "REQ" => {
+                let supported = message
+                    .caps
+                    .iter()
+                    .all(|cap| SUPPORTED_CAPS.contains(&cap.strip_prefix('-').unwrap_or(cap)));
+                if supported {
+                    for cap in &message.caps {
+                        match cap.strip_prefix('-') {
+                            Some(cap) => self.caps.remove(cap),
+                            None => self.caps.insert(cap.clone()),
+                        };
+                    }
+                }
+                (if supported { "ACK" } else { "NAK" }, message.caps)
+            }
+            // registration isn't held up for negotiation, so there is nothing to end
+            "END" => return,
+            subcommand => {
+                self.send_error(ErrorType::InvalidCapCmd, Some(subcommand.to_string()));
+                return;
+            }
+        };
+        log::debug!("{}# CAP {subcommand} {}", self.rid(), caps.join(" "));
+
+        self.reply(Reply::Cap(CapReply {
+            target_nick: self.nick.clone().filter(|_| self.is_registered()),
+            subcommand,
+            caps,
+        }));
+    }
+}
+
+impl Handler<OperMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: OperMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        let valid = self
+            .config
+            .opers
+            .get(&message.name)
+            .is_some_and(|hash| verify_password(&message.password, hash));
+        if !valid {
+            log::info!("User {nick} failed to OPER as {}", message.name);
+            self.send_error(ErrorType::PasswdMismatch, None);
+            return;
+        }
+
+        log::info!("User {nick} is now a server operator as {}", message.name);
+        self.is_oper = true;
+        if let Some(info) = self.lock_clients().get_mut(&nick) {
+            info.oper = true;
+        }
+        self.reply(Reply::YoureOper(nick.clone()));
+        self.reply(Reply::Mode(ModeReply {
+            sender: Some(self.prefix()),
+            target: Target::User(nick),
+            changes: vec![ModeChange {
+                set: true,
+                mode: 'o',
+                param: None,
+            }],
+        }));
+    }
+}
+
+impl Handler<KillMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: KillMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        if !self.is_oper {
+            self.send_error(ErrorType::NoPrivileges, None);
+            return;
+        }
+
+        let reason = format!(
+            "Killed ({nick} ({}))",
+            message.reason.as_deref().unwrap_or(&nick.0)
+        );
+        let Some(sender) = self
+            .lock_clients()
+            .get_mut(&message.target)
+            .filter(|info| info.killed.is_none())
+            .map(|info| {
+                info.killed = Some(reason.clone());
+                info.sender.clone()
+            })
+        else {
+            self.send_error(ErrorType::NoSuchNick, Some(message.target.to_string()));
+            return;
+        };
+        log::info!("User {nick} killed {}: {reason}", message.target);
+
+        // its own connection closes, then releases its channels and nickname with `reason`
+        let _ = sender.send(IrcEvent::Send(
+            Reply::Disconnect(reason).format(&self.server.name),
+        ));
+        let _ = sender.send(IrcEvent::Terminate);
+    }
+}
+
+impl Handler<IsonMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: IsonMsg) -> Self::Result {
+        let target_nick = self.nick.clone().unwrap();
+        // each as it was registered, rather than as it was asked about
+        let clients = self.lock_clients();
+        let nicks = message
+            .nicks
+            .iter()
+            .filter_map(|nick| clients.get_key_value(nick).map(|(nick, _)| nick.clone()))
+            .collect();
+        drop(clients);
+
+        self.reply(Reply::Ison(IsonReply { target_nick, nicks }));
+    }
+}
+
+impl Handler<UserhostMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: UserhostMsg) -> Self::Result {
+        let target_nick = self.nick.clone().unwrap();
+        // unknown nicknames are left out
+        let clients = self.lock_clients();
+        let users = message
+            .nicks
+            .iter()
+            .filter_map(|nick| clients.get_key_value(nick))
+            .map(|(nick, info)| UserhostEntry {
+                nick: nick.clone(),
+                user: info.user.clone(),
+                host: info.addr.ip().to_string(),
+                oper: info.oper,
+                away: info.away.is_some(),
+            })
+            .collect();
+        drop(clients);
+
+        self.reply(Reply::Userhost(UserhostReply { target_nick, users }));
+    }
+}
+
+impl Handler<UseripMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: UseripMsg) -> Self::Result {
+        let target_nick = self.nick.clone().unwrap();
+        if !self.is_oper {
+            self.send_error(ErrorType::NoPrivileges, None);
+            return;
+        }
+
+        // unknown nicknames are left out
+        let clients = self.lock_clients();
+        let users = message
+            .nicks
+            .iter()
+            .filter_map(|nick| clients.get_key_value(nick))
+            .map(|(nick, info)| UserhostEntry {
+                nick: nick.clone(),
+                user: info.user.clone(),
+                host: info.addr.ip().to_string(),
+                oper: info.oper,
+                away: info.away.is_some(),
+            })
+            .collect();
+        drop(clients);
+        log::info!(
+            "{}# Oper {target_nick} looked up IPs with USERIP",
+            self.rid()
+        );
+
+        self.reply(Reply::Userip(UserhostReply { target_nick, users }));
+    }
+}
+
+impl Handler<WallopsMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: WallopsMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        if !self.is_oper {
+            self.send_error(ErrorType::NoPrivileges, None);
+            return;
+        }
+
+        let recipients = self
+            .lock_clients()
+            .values()
+            .filter(|info| info.wallops)
+            .map(|info| info.sender.clone())
+            .collect::<Vec<_>>();
+        log::info!("User {nick} sent WALLOPS to {} users", recipients.len());
+
+        let reply = Reply::Wallops(WallopsReply {
+            message,
+            sender: self.prefix(),
+        })
+        .format(&self.server.name);
+        for sender in recipients {
+            let _ = sender.send(IrcEvent::Send(reply.clone()));
+        }
+    }
+}
+
+impl Handler<DebugMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: DebugMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        if !self.is_oper {
+            self.send_error(ErrorType::NoPrivileges, None);
+            return;
+        }
+
+        let lines = match message.subject.as_str() {
+            "STATE" => self.state_summary(),
+            "LOGLEVEL" => self.change_log_level(message.argument.as_deref()),
+            subject => vec![format!(
+                "Unknown DEBUG subject {subject}, try STATE or LOGLEVEL"
+            )],
+        };
+        log::info!("User {nick} asked for DEBUG {}", message.subject);
+
+        lines.into_iter().for_each(|message| {
+            self.reply(Reply::ServerNotice(ServerNoticeReply {
+                target_nick: nick.clone(),
+                message,
+            }))
+        });
+    }
+}
+
+// Ping
+impl Handler<String> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: String) -> Self::Result {
+        self.reply(Reply::Pong(message));
+    }
+}
+
+impl Handler<PrivMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: PrivMsg) -> Self::Result {
+        if self.is_duplicate(&message) {
+            log::info!(
+                "{}# Dropping duplicate message to {}",
+                self.rid(),
+                message.target
+            );
+            self.reply(Reply::ServerNotice(ServerNoticeReply {
+                target_nick: self.nick.clone().unwrap(),
+                message: format!("Duplicate message to {} dropped", message.target),
+            }));
+            return;
+        }
+
+        if let Some(client) = self.lock_clients().get_mut(self.nick.as_ref().unwrap()) {
+            client.last_message = Instant::now();
+        }
+
+        match message.target.clone() {
+            Target::User(nick) => {
+                // pm to user
+                let delivered = self
+                    .lock_clients()
+                    .get_key_value(&nick)
+                    .map(|(nick, client)| {
+                        let reply = Reply::PrivMsg(PrivReply {
+                            message,
+                            sender: self.prefix(),
+                        });
+                        if client
+                            .sender
+                            .send(IrcEvent::Send(reply.format(&self.server.name)))
+                            .is_err()
+                        {
+                            log::debug!("{}# {nick} is gone, skipping", self.rid());
+                        }
+                        (nick.clone(), client.away.clone())
+                    });
+                match delivered {
+                    Some((nick, Some(message))) => {
+                        // let the sender know nobody may be reading
+                        self.reply(Reply::Away(AwayReply {
+                            target_nick: self.nick.clone().unwrap(),
+                            nick,
+                            message,
+                        }));
+                    }
+                    Some((_, None)) => {}
+                    // no such nick
+                    None => self.send_error(ErrorType::NoSuchNick, Some(nick.to_string())),
+                }
+            }
+            Target::Channel(channel) | Target::ChannelStatus(_, channel) => {
+                // pm to channel, or to those of its members with a given status
+                let status = message.target.status();
+                if !self.may_send_to(&channel) {
+                    log::info!("{}# Not allowed to send to {channel}", self.rid());
+                    self.send_error(ErrorType::CannotSendToChan, Some(channel.to_string()));
+                    return;
+                }
+                let sender_nick = self.nick.clone().unwrap();
+                let reply = Reply::PrivMsg(PrivReply {
+                    message,
+                    sender: self.prefix(),
+                });
+
+                if !self.broadcast_to_members(&channel, status, reply, Some(&sender_nick)) {
+                    // no such channel
+                    self.send_error(ErrorType::NoSuchChannel, Some(channel.to_string()));
+                };
+            }
+        }
+    }
+}
+
+impl Handler<NoticeMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: NoticeMsg) -> Self::Result {
+        let sender_nick = self.nick.clone().unwrap();
+        let target = message.target.clone();
+        let status = target.status();
+        let reply = Reply::Notice(NoticeReply {
+            message,
+            sender: self.prefix(),
+        });
+
+        // unlike PRIVMSG, an unknown target is silently ignored
+        match target {
+            Target::User(nick) => {
+                if let Some(client) = self.lock_clients().get(&nick) {
+                    if client
+                        .sender
+                        .send(IrcEvent::Send(reply.format(&self.server.name)))
+                        .is_err()
+                    {
+                        log::debug!("{}# {nick} is gone, skipping", self.rid());
+                    }
+                }
+            }
+            Target::Channel(channel) | Target::ChannelStatus(_, channel) => {
+                if self.may_send_to(&channel) {
+                    self.broadcast_to_members(&channel, status, reply, Some(&sender_nick));
+                }
+            }
+        }
+    }
+}
+
+impl Handler<JoinMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: JoinMsg) -> Self::Result {
+        let Some(nick) = self.nick.clone() else {
+            return;
+        };
+
+        // once the server is at capacity, other new channels in the same JOIN are skipped
+        // rather than each refused with another 405
+        let mut at_capacity = false;
+        for (i, channel) in message.channels.into_iter().enumerate() {
+            if at_capacity && !self.lock_channels().contains_key(&channel) {
+                continue;
+            }
+            let key = message.keys.get(i).map(String::as_str);
+            if let Err(LoopControlError::Break) = self.join_channel(&nick, channel, key) {
+                at_capacity = true;
+            }
+        }
+    }
+}
+
+impl Handler<PartMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: PartMsg) -> Self::Result {
+        let Some(nick) = self.nick.clone() else {
+            return;
+        };
+
+        for channel_name in message.channels {
+            let mut channels = self.lock_channels();
+
+            let Some(channel) = channels.get_mut(&channel_name) else {
+                drop(channels);
+                self.send_error(ErrorType::NoSuchChannel, Some(channel_name.to_string()));
+                continue;
+            };
+            if channel.members.remove(&nick).is_none() {
+                drop(channels);
+                self.send_error(ErrorType::NotOnChannel, Some(channel_name.to_string()));
+                continue;
+            }
+
+            log::info!("User {nick} left channel {channel_name}");
+
+            // remove channel if no more members
+            if channel.should_delete(self.config.channel_grace) {
+                log::info!("Deleting channel: {channel_name}");
+                channels.remove(&channel_name);
+            }
+
+            log::debug!("Channels: {channels:?}");
+            drop(channels);
+
+            // send message to the remaining users, and the user who left
+            let reply = Reply::Part(PartReply {
+                channel: channel_name.clone(),
+                message: message.message.clone(),
+                sender: self.prefix(),
+            });
+            self.broadcast_to_channel(&channel_name, reply.clone(), None);
+            self.reply(reply);
+        }
+    }
+}
+
+impl Handler<NamesMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: NamesMsg) -> Self::Result {
+        // without any channels, list them all under a single end of list
+        if message.channels.is_empty() {
+            let mut channels = self.lock_channels().keys().cloned().collect::<Vec<_>>();
+            channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+            channels.iter().for_each(|channel| self.send_names(channel));
+            self.send_end_of_names(None);
+            return;
+        }
+
+        for channel in message.channels {
+            self.send_names(&channel);
+            self.send_end_of_names(Some(channel));
+        }
+    }
+}
+
+impl Handler<ListMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: ListMsg) -> Self::Result {
+        let target_nick = self.nick.clone().unwrap();
+        let channels = self.lock_channels();
+        let listed = match message.channels {
+            Some(names) => names
+                .into_iter()
+                .filter_map(|name| channels.get_key_value(&name))
+                .collect::<Vec<_>>(),
+            None => {
+                let mut all = channels.iter().collect::<Vec<_>>();
+                all.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+                all
+            }
+        };
+        let replies = listed
+            .into_iter()
+            .map(|(channel, state)| ListReply {
+                target_nick: target_nick.clone(),
+                channel: channel.clone(),
+                users: state.members.len(),
+                topic: state.topic.clone(),
+            })
+            .collect::<Vec<_>>();
+        drop(channels);
+
+        self.reply(Reply::ListStart(target_nick.clone()));
+        for reply in replies {
+            self.reply(Reply::List(reply));
+        }
+        self.reply(Reply::ListEnd(target_nick));
+    }
+}
+
+impl Handler<WhoisMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: WhoisMsg) -> Self::Result {
+        let target_nick = self.nick.clone().unwrap();
+
+        // take everything needed before sending, so the reply is consistent
+        // even when the client is asking about itself
+        let found = self
+            .lock_clients()
+            .get_key_value(&message.target)
+            .map(|(nick, info)| (nick.clone(), info.clone()));
+        let Some((nick, info)) = found else {
+            self.send_error(ErrorType::NoSuchNick, Some(message.target.to_string()));
+            self.reply(Reply::EndOfWhois(EndOfWhoisReply {
+                target_nick,
+                nick: message.target,
+            }));
+            return;
+        };
+        let multi_prefix = self.caps.contains("multi-prefix");
+        let mut channels = self
+            .lock_channels()
+            .iter()
+            .filter_map(|(channel, state)| {
+                let member = state.members.get(&nick)?;
+                Some((channel.clone(), member.prefixes(multi_prefix)))
+            })
+            .collect::<Vec<_>>();
+        channels.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+        let channels = channels
+            .into_iter()
+            .map(|(channel, prefixes)| format!("{prefixes}{channel}"))
+            .collect();
+
+        // 311, 319, 312, 301, 317, 313 and 338 in that order, as clients expect, then 318 last
+        self.reply(Reply::Whois(WhoisReply {
+            target_nick: target_nick.clone(),
+            nick: nick.clone(),
+            user: info.user,
+            host: info.addr.ip().to_string(),
+            real_name: info.real_name,
+            channels,
+            away: info.away,
+            oper: info.oper,
+            idle: info.last_message.elapsed().as_secs(),
+            signon: info
+                .signon
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |signon| signon.as_secs()),
+            actually: self.is_oper.then_some(info.addr),
+        }));
+        self.reply(Reply::EndOfWhois(EndOfWhoisReply { target_nick, nick }));
+    }
+}
+
+impl Handler<WhoMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: WhoMsg) -> Self::Result {
+        let target_nick = self.nick.clone().unwrap();
+
+        // a channel mask lists its members, anything else is matched against nicknames
+        let channel = Channel::try_from(message.mask.clone()).ok();
+        let multi_prefix = self.caps.contains("multi-prefix");
+        let members = channel.as_ref().map(|channel| {
+            self.lock_channels()
+                .get(channel)
+                .map(|channel| {
+                    channel
+                        .members
+                        .iter()
+                        .map(|(nick, member)| (nick.clone(), member.prefixes(multi_prefix)))
+                        .collect::<HashMap<_, _>>()
+                })
+                .unwrap_or_default()
+        });
+
+        // invisible users (+i) are only listed to themselves and those sharing a channel with them
+        let neighbours = self
+            .lock_channels()
+            .values()
+            .filter(|channel| channel.members.contains_key(&target_nick))
+            .flat_map(|channel| channel.members.keys().cloned())
+            .collect::<HashSet<_>>();
+
+        let clients = self.lock_clients();
+        let mut replies = clients
+            .iter()
+            .filter(|(nick, _)| match &members {
+                Some(members) => members.contains_key(nick),
+                None => mask_matches(&message.mask, &nick.0),
+            })
+            .filter(|(nick, info)| {
+                !info.invisible || **nick == target_nick || neighbours.contains(*nick)
+            })
+            .map(|(nick, info)| {
+                // seen through a channel, the member's status there follows their presence
+                let mut flags = info.flags();
+                if let Some(members) = &members {
+                    flags.push_str(&members[nick]);
+                }
+                WhoReply {
+                    target_nick: target_nick.clone(),
+                    channel: channel.clone(),
+                    user: info.user.clone(),
+                    host: info.addr.ip().to_string(),
+                    nick: nick.clone(),
+                    flags,
+                    real_name: info.real_name.clone(),
+                }
+            })
+            .collect::<Vec<_>>();
+        drop(clients);
+        replies.sort_by(|a, b| a.nick.0.cmp(&b.nick.0));
+
+        for reply in replies {
+            self.reply(Reply::Who(reply));
+        }
+        self.reply(Reply::EndOfWho(EndOfWhoReply {
+            target_nick,
+            mask: message.mask,
+        }));
+    }
+}
+
+impl Handler<AwayMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: AwayMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        if let Some(client) = self.lock_clients().get_mut(&nick) {
+            client.away = message.message.clone();
+        }
+
+        let reply = match message.message {
+            Some(_) => Reply::NowAway(nick),
+            None => Reply::UnAway(nick),
+        };
+        self.reply(reply);
     }
 }
 
@@ -348,45 +2215,901 @@ impl Handler<QuitMsg> for Client {
     type Result = ();
 
     fn handle(&mut self, message: QuitMsg) -> Self::Result {
-        let mut empty_channels = Vec::new();
+        let Some(nick) = self.nick.clone() else {
+            return;
+        };
+        self.quit = true;
 
-        self.channels
-            .lock()
-            .unwrap()
-            .iter_mut()
-            .for_each(|(channel_name, channel)| {
-                if let Some(_) = channel.get(&self.nick.clone().unwrap()) {
-                    // user is leaving this channel
-                    channel.iter().for_each(|(_, sender)| {
-                        sender
-                            .send(IrcEvent::Send(
-                                Reply::Quit(QuitReply {
-                                    message: message.clone(),
-                                    sender_nick: self.nick.clone().unwrap(),
-                                })
-                                .to_string(),
-                            ))
-                            .unwrap();
-                    });
+        let joined_channels = self
+            .lock_channels()
+            .iter()
+            .filter(|(_, channel)| channel.members.contains_key(&nick))
+            .map(|(channel, _)| channel.clone())
+            .collect::<Vec<_>>();
 
-                    channel.remove(&self.nick.clone().unwrap());
-                    if channel.is_empty() {
-                        empty_channels.push(channel_name.clone());
-                    }
+        // everyone in the user's channels sees the QUIT, including the user
+        let reply = Reply::Quit(QuitReply {
+            message,
+            sender: self.prefix(),
+        });
+        joined_channels.iter().for_each(|channel| {
+            self.broadcast_to_channel(channel, reply.clone(), None);
+        });
+
+        let mut channels = self.lock_channels();
+        joined_channels.iter().for_each(|channel_name| {
+            let Some(channel) = channels.get_mut(channel_name) else {
+                return;
+            };
+            channel.members.remove(&nick);
+            log::info!("User {nick} quit and left channel {channel_name}");
+
+            if channel.should_delete(self.config.channel_grace) {
+                log::info!("Channel {channel_name} is now empty... deleting");
+                channels.remove(channel_name);
+            }
+        });
+
+        log::debug!("Channels: {channels:?}");
+    }
+}
+
+impl Handler<TopicMsg> for Client {
+    type Result = ();
 
-                    log::info!(
-                        "User {} quit and left channel {}",
-                        self.nick.clone().unwrap(),
-                        channel_name
-                    );
+    fn handle(&mut self, message: TopicMsg) -> Self::Result {
+        let Some(nick) = self.nick.clone() else {
+            return;
+        };
+        let mut channels = self.lock_channels();
+
+        let Some(channel) = channels.get_mut(&message.channel) else {
+            drop(channels);
+            self.send_error(ErrorType::NoSuchChannel, Some(message.channel.to_string()));
+            return;
+        };
+
+        // only reply to the requester when viewing the topic
+        let Some(topic) = message.topic.clone() else {
+            let topic = channel.topic.clone();
+            drop(channels);
+            self.reply(Reply::ChannelTopic(ChannelTopicReply {
+                target_nick: nick,
+                channel: message.channel,
+                topic,
+            }));
+            return;
+        };
+
+        if !channel.members.contains_key(&nick)
+            && !self.oper_override("membership", &message.channel)
+        {
+            drop(channels);
+            self.send_error(ErrorType::NotOnChannel, Some(message.channel.to_string()));
+            return;
+        }
+
+        let cooling_down = self
+            .config
+            .topic_cooldown
+            .zip(channel.topic_set)
+            .is_some_and(|(cooldown, set)| set.elapsed() < cooldown);
+        if cooling_down && !self.is_oper {
+            drop(channels);
+            log::info!(
+                "User {nick} changed the topic of {} too soon",
+                message.channel
+            );
+            self.reply(Reply::ServerNotice(ServerNoticeReply {
+                target_nick: nick,
+                message: format!(
+                    "The topic of {} was changed too recently, try again later",
+                    message.channel
+                ),
+            }));
+            return;
+        }
+
+        // an empty topic clears it
+        channel.topic = Some(topic).filter(|topic| !topic.is_empty());
+        channel.topic_set = Some(Instant::now());
+        log::info!("User {nick} set the topic of {}", message.channel);
+        drop(channels);
+
+        self.broadcast_to_channel(
+            &message.channel,
+            Reply::Topic(TopicReply {
+                message: message.clone(),
+                sender: self.prefix(),
+            }),
+            None,
+        );
+    }
+}
+
+impl Handler<ModeMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: ModeMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        let channel = match message.target.clone() {
+            Target::Channel(channel) => channel,
+            Target::ChannelStatus(..) => {
+                return self.send_error(ErrorType::NoSuchChannel, Some(message.target.to_string()))
+            }
+            Target::User(target) if target == nick => return self.change_user_modes(nick, message),
+            Target::User(_) => return self.send_error(ErrorType::UsersDontMatch, None),
+        };
+        let mut channels = self.lock_channels();
+
+        let Some(state) = channels.get_mut(&channel) else {
+            drop(channels);
+            self.send_error(ErrorType::NoSuchChannel, Some(channel.to_string()));
+            return;
+        };
+
+        // anyone may view the modes
+        if message.modes.is_none() {
+            let modes = state.modes(state.members.contains_key(&nick));
+            drop(channels);
+            self.reply(Reply::ChannelModeIs(ChannelModeReply {
+                target_nick: nick,
+                channel,
+                modes,
+            }));
+            return;
+        }
+
+        // only operators on the channel may change them, but anyone may view the lists
+        let changes = message.changes();
+        let viewing = changes
+            .iter()
+            .all(|change| change.param.is_none() && CHANNEL_MODES[0].contains(change.mode));
+        if !viewing {
+            match state.members.get(&nick) {
+                Some(member) if member.op => {}
+                _ if self.oper_override("channel operator status", &channel) => {}
+                None => {
+                    drop(channels);
+                    self.send_error(ErrorType::NotOnChannel, Some(channel.to_string()));
+                    return;
+                }
+                Some(_) => {
+                    drop(channels);
+                    self.send_error(ErrorType::ChanOPrivsNeeded, Some(channel.to_string()));
+                    return;
+                }
+            }
+        }
+
+        let mut applied = Vec::new();
+        let mut errors = Vec::new();
+        let mut list = None;
+        for change in changes {
+            if CHANNEL_MODES[0].contains(change.mode) {
+                let Some(param) = change.param.as_deref() else {
+                    list = Some((change.mode, state.list(change.mode).to_vec()));
+                    continue;
+                };
+                let (duration, mask) = match change.mode {
+                    'q' => split_timed_mask(param),
+                    _ => (None, param),
+                };
+                let mask = normalize_mask(mask);
+                if state.set_list_entry(change.mode, &mask, change.set) {
+                    if let Some(duration) = duration.filter(|_| change.set) {
+                        state
+                            .mute_expiries
+                            .insert(mask.clone(), Instant::now() + duration);
+                    }
+                    applied.push(ModeChange {
+                        param: Some(mask),
+                        ..change
+                    });
+                }
+            } else if MEMBER_STATUSES.iter().any(|(mode, _)| *mode == change.mode) {
+                let Some(target) = change.param.clone() else {
+                    errors.push((ErrorType::NeedMoreParams, Command::Mode.to_string()));
+                    continue;
+                };
+                match state.members.get_mut(&Nick(target.clone())) {
+                    Some(member) => {
+                        if member.set_status(change.mode, change.set) {
+                            applied.push(change);
+                        }
+                    }
+                    None => {
+                        errors.push((ErrorType::UserNotInChannel, format!("{target} {channel}")))
+                    }
+                }
+            } else if CHANNEL_MODES[1..3]
+                .iter()
+                .any(|modes| modes.contains(change.mode))
+            {
+                // unsetting needs no value, but the change is still shown with one if the mode
+                // always takes one
+                let mut change = change;
+                let value = if change.set {
+                    let Some(value) = change.param.clone() else {
+                        errors.push((ErrorType::NeedMoreParams, Command::Mode.to_string()));
+                        continue;
+                    };
+                    Some(value)
+                } else {
+                    if CHANNEL_MODES[1].contains(change.mode) {
+                        change.param.get_or_insert_with(|| String::from("*"));
+                    }
+                    None
+                };
+                if state.set_setting(change.mode, value) {
+                    applied.push(change);
+                }
+            } else if CHANNEL_MODES[3].contains(change.mode) {
+                if state.set_flag(change.mode, change.set) {
+                    applied.push(change);
                 }
+            } else {
+                errors.push((ErrorType::UnknownMode, change.mode.to_string()));
+            }
+        }
+        drop(channels);
+
+        for (error, subject) in errors {
+            self.send_error(error, Some(subject));
+        }
+        if let Some((mode, masks)) = list {
+            for mask in masks {
+                let entry = BanListReply {
+                    target_nick: nick.clone(),
+                    channel: channel.clone(),
+                    mask,
+                };
+                self.reply(match mode {
+                    'q' => Reply::MuteList(entry),
+                    _ => Reply::BanList(entry),
+                });
+            }
+            let end = EndOfBanListReply {
+                target_nick: nick.clone(),
+                channel: channel.clone(),
+            };
+            self.reply(match mode {
+                'q' => Reply::EndOfMuteList(end),
+                _ => Reply::EndOfBanList(end),
             });
+        }
+        if !applied.is_empty() {
+            log::info!("User {nick} changed the modes of {channel}");
+            self.broadcast_to_channel(
+                &channel.clone(),
+                Reply::Mode(ModeReply {
+                    sender: Some(self.prefix()),
+                    target: Target::Channel(channel),
+                    changes: applied,
+                }),
+                None,
+            );
+        }
+    }
+}
+
+impl Client {
+    /// Take `target` out of `channel` for KICK or REMOVE, if this client is an operator there.
+    /// Returns everyone who was in the channel, `target` included, to be told about it.
+    fn eject_member(
+        &mut self,
+        channel: &Channel,
+        target: &Nick,
+    ) -> Option<Vec<(Nick, Sender<IrcEvent>)>> {
+        let nick = self.nick.clone().unwrap();
+        let mut channels = self.lock_channels();
+
+        let Some(state) = channels.get_mut(channel) else {
+            drop(channels);
+            self.send_error(ErrorType::NoSuchChannel, Some(channel.to_string()));
+            return None;
+        };
+        let error = match state.members.get(&nick) {
+            Some(member) if member.op => None,
+            _ if self.oper_override("channel operator status", channel) => None,
+            None => Some((ErrorType::NotOnChannel, channel.to_string())),
+            Some(_) => Some((ErrorType::ChanOPrivsNeeded, channel.to_string())),
+        }
+        .or_else(|| {
+            (!state.members.contains_key(target))
+                .then(|| (ErrorType::UserNotInChannel, format!("{target} {channel}")))
+        });
+        if let Some((error, subject)) = error {
+            drop(channels);
+            self.send_error(error, Some(subject));
+            return None;
+        }
+
+        let recipients = state
+            .members
+            .iter()
+            .map(|(nick, member)| (nick.clone(), member.sender.clone()))
+            .collect::<Vec<_>>();
+        state.members.remove(target);
+
+        // remove channel if no more members
+        if state.should_delete(self.config.channel_grace) {
+            log::info!("Deleting channel: {channel}");
+            channels.remove(channel);
+        }
+        Some(recipients)
+    }
+
+    /// Send `reply` to each of `recipients`, skipping any that have gone.
+    fn send_to_all(&self, recipients: Vec<(Nick, Sender<IrcEvent>)>, reply: Reply) {
+        let reply = reply.format(&self.server.name);
+        recipients.into_iter().for_each(|(nick, sender)| {
+            if sender.send(IrcEvent::Send(reply.clone())).is_err() {
+                log::debug!("{}# {nick} is gone, skipping", self.rid());
+            }
+        });
+    }
+}
+
+impl Handler<KickMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: KickMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        let Some(recipients) = self.eject_member(&message.channel, &message.target) else {
+            return;
+        };
+        log::info!(
+            "User {nick} kicked {} from {}",
+            message.target,
+            message.channel
+        );
+
+        // the whole channel sees the kick, the one kicked included
+        let reply = Reply::Kick(KickReply {
+            message,
+            sender: self.prefix(),
+        });
+        self.send_to_all(recipients, reply);
+    }
+}
+
+impl Handler<RemoveMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: RemoveMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        let Some(sender) = self
+            .lock_clients()
+            .get(&message.target)
+            .map(|info| info.prefix(&message.target))
+        else {
+            self.send_error(ErrorType::NoSuchNick, Some(message.target.to_string()));
+            return;
+        };
+        let Some(recipients) = self.eject_member(&message.channel, &message.target) else {
+            return;
+        };
+        log::info!(
+            "User {nick} removed {} from {}",
+            message.target,
+            message.channel
+        );
+
+        // the whole channel sees the one removed part, as if they had left on their own
+        let reason = match message.reason {
+            Some(reason) => format!("Removed by {nick}: {reason}"),
+            None => format!("Removed by {nick}"),
+        };
+        let reply = Reply::Part(PartReply {
+            channel: message.channel,
+            message: Some(reason),
+            sender,
+        });
+        self.send_to_all(recipients, reply);
+    }
+}
+
+impl Handler<InviteMsg> for Client {
+    type Result = ();
+
+    fn handle(&mut self, message: InviteMsg) -> Self::Result {
+        let nick = self.nick.clone().unwrap();
+        let channel = message.channel.clone();
+        let mut channels = self.lock_channels();
+
+        let Some(state) = channels.get_mut(&channel) else {
+            drop(channels);
+            self.send_error(ErrorType::NoSuchChannel, Some(channel.to_string()));
+            return;
+        };
+        // anyone on the channel may invite, unless it is invite-only
+        let error = match state.members.get(&nick) {
+            None => Some((ErrorType::NotOnChannel, channel.to_string())),
+            Some(member)
+                if state.invite_only
+                    && !member.op
+                    && !self.oper_override("channel operator status", &channel) =>
+            {
+                Some((ErrorType::ChanOPrivsNeeded, channel.to_string()))
+            }
+            Some(_) if state.members.contains_key(&message.target) => Some((
+                ErrorType::UserOnChannel,
+                format!("{} {channel}", message.target),
+            )),
+            Some(_) => None,
+        };
+        if let Some((error, subject)) = error {
+            drop(channels);
+            self.send_error(error, Some(subject));
+            return;
+        }
+
+        let Some((target, sender)) = self
+            .lock_clients()
+            .get_key_value(&message.target)
+            .map(|(nick, client)| (nick.clone(), client.sender.clone()))
+        else {
+            drop(channels);
+            self.send_error(ErrorType::NoSuchNick, Some(message.target.to_string()));
+            return;
+        };
+        state.invites.insert(target.clone());
+        drop(channels);
+        log::info!("User {nick} invited {target} to {channel}");
 
-        empty_channels.into_iter().for_each(|ch| {
-            log::info!("Channel {ch} is now empty... deleting");
-            self.channels.lock().unwrap().remove(&ch);
+        let invite = Reply::Invite(InviteReply {
+            message: message.clone(),
+            sender: self.prefix(),
         });
+        if sender
+            .send(IrcEvent::Send(invite.format(&self.server.name)))
+            .is_err()
+        {
+            log::debug!("{}# {target} is gone, skipping", self.rid());
+        }
+        self.reply(Reply::Inviting(InvitingReply {
+            target_nick: nick,
+            nick: target,
+            channel,
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+
+    use super::*;
+    use crate::connect::ConnectionRead;
+
+    /// A client reading `input` from memory, sharing `clients` and `channels`.
+    fn test_client(
+        input: &str,
+        clients: &Clients,
+        channels: &Channels,
+    ) -> (Client, Receiver<IrcEvent>) {
+        let (tx, rx) = mpsc::channel();
+        let client = Client::new(
+            ConnectionRead::from_bytes(input, ([127, 0, 0, 1], 6991).into()),
+            tx,
+            Arc::new(Config::default()),
+            Arc::new(Metrics::default()),
+            clients.clone(),
+            channels.clone(),
+            HeldNicks::default(),
+            Arc::default(),
+        );
+        (client, rx)
+    }
+
+    /// Every line sent to a client so far.
+    fn replies(rx: &Receiver<IrcEvent>) -> Vec<String> {
+        rx.try_iter()
+            .filter_map(|event| match event {
+                IrcEvent::Send(message) => Some(message),
+                IrcEvent::Terminate => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_step() {
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nPRIVMSG #a :hi bob\r\n",
+            &clients,
+            &channels,
+        );
+        let (mut bob, bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+            &clients,
+            &channels,
+        );
+
+        assert_eq!(alice.login(), Some(Nick("alice".to_string())));
+        assert_eq!(bob.login(), Some(Nick("bob".to_string())));
+        assert_eq!(
+            replies(&alice_rx)[0],
+            ":iris-server 001 alice :Hi Alice, welcome to IRC\r\n"
+        );
+        assert_eq!(
+            replies(&bob_rx)[0],
+            ":iris-server 001 bob :Hi Bob, welcome to IRC\r\n"
+        );
+        assert!(alice.step().is_ok());
+        assert!(bob.step().is_ok());
+        assert!(alice.step().is_ok());
+        assert!(matches!(alice.step(), Err(LoopControlError::Break)));
+
+        assert_eq!(
+            replies(&alice_rx),
+            vec![
+                ":alice!alice@127.0.0.1 JOIN #a\r\n",
+                ":iris-server 353 alice = #a :@alice\r\n",
+                ":iris-server 366 alice #a :End of /NAMES list\r\n",
+                ":bob!bob@127.0.0.1 JOIN #a\r\n",
+            ]
+        );
+        assert_eq!(
+            replies(&bob_rx),
+            vec![
+                ":bob!bob@127.0.0.1 JOIN #a\r\n",
+                ":iris-server 353 bob = #a :@alice bob\r\n",
+                ":iris-server 366 bob #a :End of /NAMES list\r\n",
+                ":alice!alice@127.0.0.1 PRIVMSG #a :hi bob\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_channel_case_insensitive() {
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #Chan\r\n",
+            &clients,
+            &channels,
+        );
+        let (mut bob, _bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #chan\r\nPRIVMSG #CHAN :hi alice\r\n",
+            &clients,
+            &channels,
+        );
+
+        alice.login();
+        bob.login();
+        replies(&alice_rx);
+        assert!(alice.step().is_ok());
+        assert!(bob.step().is_ok());
+        assert!(bob.step().is_ok());
+
+        assert_eq!(channels.lock().unwrap().len(), 1);
+        assert_eq!(
+            replies(&alice_rx),
+            [
+                ":alice!alice@127.0.0.1 JOIN #Chan\r\n",
+                ":iris-server 353 alice = #Chan :@alice\r\n",
+                ":iris-server 366 alice #Chan :End of /NAMES list\r\n",
+                ":bob!bob@127.0.0.1 JOIN #chan\r\n",
+                ":bob!bob@127.0.0.1 PRIVMSG #CHAN :hi alice\r\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_panic_reaps_client() {
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, _alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nPRIVMSG #a :hi\r\n",
+            &clients,
+            &channels,
+        );
+        let (mut bob, bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+            &clients,
+            &channels,
+        );
+
+        alice.login();
+        bob.login();
+        assert!(alice.step().is_ok());
+        assert!(bob.step().is_ok());
+
+        // bob's connection going away makes sending to him panic
+        drop(bob_rx);
+        alice.run();
+
+        let alice_nick = Nick("alice".to_string());
+        assert!(!clients.lock().unwrap().contains_key(&alice_nick));
+        assert!(channels
+            .lock()
+            .unwrap()
+            .values()
+            .all(|channel| !channel.members.contains_key(&alice_nick)));
+    }
+
+    #[test]
+    fn test_channel_delivery_order() {
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let messages = (0..100)
+            .map(|i| format!("PRIVMSG #a :message {i}\r\n"))
+            .collect::<String>();
+        let (mut alice, _alice_rx) = test_client(
+            &format!("NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\n{messages}"),
+            &clients,
+            &channels,
+        );
+        let (mut bob, bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+            &clients,
+            &channels,
+        );
+
+        alice.login();
+        bob.login();
+        assert!(alice.step().is_ok());
+        assert!(bob.step().is_ok());
+        replies(&bob_rx);
+        alice.run();
+
+        assert_eq!(
+            replies(&bob_rx),
+            (0..100)
+                .map(|i| format!(":alice!alice@127.0.0.1 PRIVMSG #a :message {i}\r\n"))
+                .chain([String::from(
+                    ":alice!alice@127.0.0.1 QUIT :Connection lost\r\n"
+                )])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_keepalive() {
+        use std::{io::Write, net::TcpListener, net::TcpStream, thread, time::Duration};
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut conn_read, _conn_write) =
+            crate::connect::ConnectionManager::from_listener(listener)
+                .accept_new_connection()
+                .unwrap();
+
+        let config = Config {
+            ping_interval: Some(Duration::from_millis(50)),
+            ping_timeout: Some(Duration::from_millis(100)),
+            ..Config::default()
+        };
+        conn_read.set_read_timeout(config.read_timeout());
+        let (tx, rx) = mpsc::channel();
+        let mut client = Client::new(
+            conn_read,
+            tx,
+            Arc::new(config),
+            Arc::new(Metrics::default()),
+            Clients::default(),
+            Channels::default(),
+            HeldNicks::default(),
+            Arc::default(),
+        );
+
+        socket
+            .write_all(b"NICK alice\r\nUSER alice 0 * :Alice\r\n")
+            .unwrap();
+        client.login();
+        replies(&rx);
+
+        // quiet for a while, so pinged
+        thread::sleep(Duration::from_millis(50));
+        assert!(matches!(client.step(), Err(LoopControlError::Continue)));
+        assert_eq!(replies(&rx), ["PING :iris-server\r\n"]);
+
+        // answering clears the ping
+        socket.write_all(b"PONG :iris-server\r\n").unwrap();
+        assert!(client.step().is_ok());
+        assert!(client.ping_sent.is_none());
+
+        // pinged again, and this time no answer
+        thread::sleep(Duration::from_millis(50));
+        assert!(matches!(client.step(), Err(LoopControlError::Continue)));
+        assert_eq!(replies(&rx), ["PING :iris-server\r\n"]);
+        thread::sleep(Duration::from_millis(100));
+        assert!(matches!(client.step(), Err(LoopControlError::Break)));
+        assert_eq!(replies(&rx), ["ERROR :Ping timeout\r\n"]);
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        use std::{io::Write, net::TcpListener, net::TcpStream, time::Duration};
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut socket = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut conn_read, _conn_write) =
+            crate::connect::ConnectionManager::from_listener(listener)
+                .accept_new_connection()
+                .unwrap();
+
+        // wake up often, but only backdated activity is old enough to count as idle
+        let max_idle = Duration::from_secs(3600);
+        let config = Config {
+            max_idle: Some(max_idle),
+            ..Config::default()
+        };
+        conn_read.set_read_timeout(Some(Duration::from_millis(10)));
+        let (tx, rx) = mpsc::channel();
+        let mut client = Client::new(
+            conn_read,
+            tx,
+            Arc::new(config),
+            Arc::new(Metrics::default()),
+            Clients::default(),
+            Channels::default(),
+            HeldNicks::default(),
+            Arc::default(),
+        );
+
+        // a nickname alone isn't registration, so isn't held to the limit
+        socket.write_all(b"NICK alice\r\n").unwrap();
+        assert!(client.step().is_ok());
+        client.last_activity = Instant::now() - max_idle;
+        assert!(matches!(client.step(), Err(LoopControlError::Continue)));
+        assert!(replies(&rx).is_empty());
+
+        socket.write_all(b"USER alice 0 * :Alice\r\n").unwrap();
+        assert!(client.step().is_ok());
+        replies(&rx);
+        client.last_activity = Instant::now() - max_idle;
+        assert!(matches!(client.step(), Err(LoopControlError::Break)));
+        assert_eq!(replies(&rx), ["ERROR :Idle timeout\r\n"]);
+    }
 
-        log::debug!("Channels: {:?}", self.channels);
+    #[test]
+    fn test_reject_http_probe() {
+        let (mut client, rx) = test_client(
+            "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            &Clients::default(),
+            &Channels::default(),
+        );
+
+        assert!(matches!(client.step(), Err(LoopControlError::Break)));
+        assert_eq!(replies(&rx), vec!["ERROR :This is an IRC server\r\n"]);
+    }
+
+    #[test]
+    fn test_broadcast_to_channel_exclude() {
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\n",
+            &clients,
+            &channels,
+        );
+        let (mut bob, bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+            &clients,
+            &channels,
+        );
+        alice.login();
+        bob.login();
+        assert!(alice.step().is_ok());
+        assert!(bob.step().is_ok());
+        replies(&alice_rx);
+        replies(&bob_rx);
+
+        let channel = Channel("#a".to_string());
+        let reply = Reply::Ping("test".to_string());
+        assert!(alice.broadcast_to_channel(&channel, reply.clone(), None));
+        assert_eq!(replies(&alice_rx).len(), 1);
+        assert_eq!(replies(&bob_rx).len(), 1);
+
+        assert!(alice.broadcast_to_channel(&channel, reply.clone(), alice.nick.as_ref()));
+        assert!(replies(&alice_rx).is_empty());
+        assert_eq!(replies(&bob_rx).len(), 1);
+
+        assert!(!alice.broadcast_to_channel(&Channel("#b".to_string()), reply, None));
+    }
+
+    #[test]
+    fn test_poisoned_lock_recovery() {
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\n",
+            &clients,
+            &channels,
+        );
+        alice.login();
+        replies(&alice_rx);
+
+        // another client panics while holding the lock
+        let poisoner = channels.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poisoning channels");
+        })
+        .join();
+        assert!(channels.is_poisoned());
+
+        assert!(alice.step().is_ok());
+        assert!(!channels.is_poisoned());
+        assert_eq!(replies(&alice_rx)[0], ":alice!alice@127.0.0.1 JOIN #a\r\n");
+    }
+
+    #[test]
+    fn test_broadcast_prunes_dead_members() {
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nPRIVMSG #a :hi\r\n",
+            &clients,
+            &channels,
+        );
+        let (mut bob, bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+            &clients,
+            &channels,
+        );
+        alice.login();
+        bob.login();
+        assert!(alice.step().is_ok());
+        assert!(bob.step().is_ok());
+        replies(&alice_rx);
+
+        // bob's connection goes away without him parting
+        drop(bob_rx);
+        assert!(alice.step().is_ok());
+
+        let channel = Channel("#a".to_string());
+        let members = channels.lock().unwrap()[&channel].members.clone();
+        assert_eq!(
+            members.into_keys().collect::<Vec<_>>(),
+            [Nick("alice".to_string())]
+        );
+    }
+    #[test]
+    fn test_disconnect_reaps_client() {
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, _alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nJOIN #b\r\n",
+            &clients,
+            &channels,
+        );
+        let (mut bob, bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nJOIN #b\r\n",
+            &clients,
+            &channels,
+        );
+        alice.login();
+        bob.login();
+        assert!(alice.step().is_ok());
+        assert!(alice.step().is_ok());
+        assert!(bob.step().is_ok());
+        assert!(bob.step().is_ok());
+        replies(&bob_rx);
+
+        // alice's connection closes without a QUIT
+        alice.run();
+
+        assert_eq!(
+            replies(&bob_rx),
+            vec![":alice!alice@127.0.0.1 QUIT :Connection lost\r\n"]
+        );
+        assert!(channels
+            .lock()
+            .unwrap()
+            .values()
+            .all(|channel| !channel.members.contains_key(&Nick("alice".to_string()))));
+
+        // the nickname is free again
+        let (mut alice, _alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\n",
+            &clients,
+            &channels,
+        );
+        assert_eq!(alice.login(), Some(Nick("alice".to_string())));
     }
 }
@@ -0,0 +1,331 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+use blake2::Blake2s256;
+use hmac::{Mac, SimpleHmac};
+
+use crate::types::{Channel, Nick, MAX_NICK_LEN, SERVER_NAME};
+
+/// Server-wide settings shared by every connection.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// The name the server introduces itself as.
+    /// If unset, see `detect_server_name`.
+    pub server_name: Option<String>,
+
+    /// Use the machine's hostname as the server name when `server_name` is unset,
+    /// falling back to `SERVER_NAME` if it can't be determined.
+    pub detect_server_name: bool,
+
+    /// Disconnect registered clients that have not sent anything for this long.
+    /// `None` (the default) never disconnects idle clients.
+    pub max_idle: Option<Duration>,
+
+    /// PING registered clients that have not sent anything for this long, to check they're still there.
+    /// `None` (the default) never pings.
+    pub ping_interval: Option<Duration>,
+
+    /// How long a pinged client has to answer before it is disconnected.
+    /// `None` (the default) waits as long as `ping_interval`.
+    pub ping_timeout: Option<Duration>,
+
+    /// The longest nickname accepted, also advertised as ISUPPORT's NICKLEN.
+    /// `None` (the default) uses `MAX_NICK_LEN`.
+    pub max_nick_len: Option<usize>,
+
+    /// The most channels that may exist at once. `None` (the default) is unlimited.
+    pub max_channels: Option<usize>,
+
+    /// How long a channel that everyone has left is kept, with its topic and modes,
+    /// in case someone comes back. Zero (the default) deletes it at once.
+    pub channel_grace: Duration,
+
+    /// The least time between two changes to a channel's topic, against topic wars.
+    /// Server operators may change it sooner. `None` (the default) has no limit.
+    pub topic_cooldown: Option<Duration>,
+
+    /// A NOTICE the server sends to everyone who joins the given channel.
+    pub join_greetings: HashMap<Channel, String>,
+
+    /// Log a warning when a single channel broadcast reaches more than this many members.
+    pub broadcast_warn_threshold: Option<usize>,
+
+    /// Send a NOTICE summarising the server's limits and commands after registration.
+    pub banner: bool,
+
+    /// Refuse to register a client whose USER is already connected from the same IP.
+    pub unique_user_per_ip: bool,
+
+    /// The password clients must give with PASS before registering.
+    /// `None` (the default) lets anyone register, and PASS is ignored.
+    pub password: Option<String>,
+
+    /// Those who may become server operators with OPER, by name, with their passwords hashed
+    /// by `hash_password`. Each hash is a PHC string, e.g. `$argon2id$v=19$m=19456,t=2,p=1$...`,
+    /// holding its own salt and parameters, so it can be made once and kept in configuration.
+    pub opers: HashMap<String, String>,
+
+    /// Let server operators get past channel restrictions: join channels they are banned from,
+    /// not invited to, don't have the key for or that are full, and act as channel operators
+    /// anywhere. Each time they do is logged.
+    pub oper_override: bool,
+
+    /// Only allow joining channels that already exist.
+    /// Server operators may still create them.
+    pub restrict_channel_creation: bool,
+
+    /// Only let members send to a channel, even one that allows external messages (`-n`).
+    pub require_channel_membership: bool,
+
+    /// How long a nickname stays reserved for its address after an unclean disconnect,
+    /// so the same user can reconnect and reclaim it. Zero (the default) frees it at once.
+    pub nick_hold: Duration,
+
+    /// The secret `HmacCloak` keys its hashes with, so cloaks stay the same across restarts.
+    /// `None` (the default) picks a random one each time the server is created.
+    pub cloak_secret: Option<String>,
+
+    /// Drop a PRIVMSG that repeats the sender's last message to the same target within this long.
+    /// `None` (the default) delivers every message.
+    pub privmsg_dedup_window: Option<Duration>,
+
+    /// The network name advertised to clients. Defaults to the server name.
+    pub network_name: Option<String>,
+
+    /// A file holding the message of the day, sent to every client after registration.
+    /// It is read once, when the server is created.
+    pub motd_path: Option<PathBuf>,
+
+    /// Serve Prometheus metrics over HTTP at `/metrics` on this address.
+    /// `None` (the default) serves no metrics.
+    pub metrics_address: Option<SocketAddr>,
+}
+
+impl Config {
+    /// The longest nickname accepted, see `max_nick_len`.
+    pub fn nick_len(&self) -> usize {
+        self.max_nick_len.unwrap_or(MAX_NICK_LEN)
+    }
+
+    /// How often a connection must stop waiting for input so idle and unresponsive clients
+    /// can be dealt with, or `None` if they never need to be.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        let ping_timeout = self
+            .ping_interval
+            .map(|interval| self.ping_timeout.unwrap_or(interval));
+        [self.max_idle, self.ping_interval, ping_timeout]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    /// Work out the server name: the configured one, else the detected hostname
+    /// (if enabled), else `SERVER_NAME`.
+    pub fn resolve_server_name(&self, hostname_provider: &dyn HostnameProvider) -> String {
+        self.server_name
+            .clone()
+            .or_else(|| {
+                self.detect_server_name
+                    .then(|| hostname_provider.hostname())
+                    .flatten()
+            })
+            .unwrap_or_else(|| SERVER_NAME.to_string())
+    }
+}
+
+/// Hash an operator password for `Config::opers` with Argon2id and a random salt.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("the default Argon2 parameters accept any password")
+        .to_string()
+}
+
+/// Whether `password` is the one `hash` was made from by `hash_password`.
+/// The hashes are compared in constant time, and a malformed hash matches nothing.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Somewhere to find out the machine's hostname.
+pub trait HostnameProvider {
+    fn hostname(&self) -> Option<String>;
+}
+
+/// Asks the operating system for the hostname.
+pub struct SystemHostname;
+
+impl HostnameProvider for SystemHostname {
+    fn hostname(&self) -> Option<String> {
+        gethostname::gethostname()
+            .into_string()
+            .ok()
+            .filter(|hostname| !hostname.is_empty())
+    }
+}
+
+/// A way to hide a client's address behind a stand-in host.
+pub trait HostCloak: fmt::Debug + Send + Sync {
+    fn cloak(&self, ip: IpAddr, nick: &Nick) -> String;
+}
+
+/// Cloaks an address with an HMAC of it, keyed by `Config::cloak_secret`.
+/// The same address always gets the same cloak, whatever the nickname,
+/// but the address can't be worked out from it without the secret.
+pub struct HmacCloak {
+    secret: Vec<u8>,
+}
+
+impl HmacCloak {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    /// Keyed by `config.cloak_secret`, or a random secret if it is unset.
+    pub fn from_config(config: &Config) -> Self {
+        match &config.cloak_secret {
+            Some(secret) => Self::new(secret.as_bytes()),
+            None => {
+                let mut secret = [0; 32];
+                OsRng.fill_bytes(&mut secret);
+                Self::new(secret)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for HmacCloak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HmacCloak").finish_non_exhaustive()
+    }
+}
+
+impl HostCloak for HmacCloak {
+    fn cloak(&self, ip: IpAddr, _nick: &Nick) -> String {
+        let mut mac = SimpleHmac::<Blake2s256>::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(ip.to_string().as_bytes());
+        let hash = mac.finalize().into_bytes();
+        let hex = hash[..8]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        format!("{hex}.cloak")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeHostname(Option<&'static str>);
+
+    impl HostnameProvider for FakeHostname {
+        fn hostname(&self) -> Option<String> {
+            self.0.map(str::to_string)
+        }
+    }
+
+    #[test]
+    fn test_resolve_server_name() {
+        let detect = Config {
+            detect_server_name: true,
+            ..Config::default()
+        };
+        assert_eq!(
+            detect.resolve_server_name(&FakeHostname(Some("irc.example.com"))),
+            "irc.example.com"
+        );
+        assert_eq!(detect.resolve_server_name(&FakeHostname(None)), SERVER_NAME);
+
+        let configured = Config {
+            server_name: Some("irc.example.org".to_string()),
+            ..detect
+        };
+        assert_eq!(
+            configured.resolve_server_name(&FakeHostname(Some("irc.example.com"))),
+            "irc.example.org"
+        );
+
+        assert_eq!(
+            Config::default().resolve_server_name(&FakeHostname(Some("irc.example.com"))),
+            SERVER_NAME
+        );
+    }
+
+    #[test]
+    fn test_hash_password() {
+        let hash = hash_password("hunter2");
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+
+        // salted, so the same password never hashes the same way twice
+        assert_ne!(hash_password("hunter2"), hash);
+        assert!(!verify_password("hunter2", "not a hash"));
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let secs = Duration::from_secs;
+        assert_eq!(Config::default().read_timeout(), None);
+
+        let config = Config {
+            max_idle: Some(secs(300)),
+            ping_interval: Some(secs(120)),
+            ..Config::default()
+        };
+        assert_eq!(config.read_timeout(), Some(secs(120)));
+
+        let config = Config {
+            ping_timeout: Some(secs(30)),
+            ..config
+        };
+        assert_eq!(config.read_timeout(), Some(secs(30)));
+
+        // without pings, a ping timeout means nothing
+        let config = Config {
+            ping_interval: None,
+            ..config
+        };
+        assert_eq!(config.read_timeout(), Some(secs(300)));
+    }
+
+    #[test]
+    fn test_hmac_cloak() {
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let alice = Nick("alice".to_string());
+        let cloak = HmacCloak::from_config(&Config {
+            cloak_secret: Some("hunter2".to_string()),
+            ..Config::default()
+        });
+
+        // stable for an address, whoever is using it
+        let cloaked = cloak.cloak(ip, &alice);
+        assert_eq!(cloaked, HmacCloak::new("hunter2").cloak(ip, &alice));
+        assert_eq!(cloaked, cloak.cloak(ip, &Nick("bob".to_string())));
+        assert!(!cloaked.contains("192"));
+
+        assert_ne!(cloaked, cloak.cloak(IpAddr::from([192, 0, 2, 2]), &alice));
+        assert_ne!(cloaked, HmacCloak::new("hunter3").cloak(ip, &alice));
+    }
+}
@@ -1,51 +1,101 @@
 use std::{
-    io::{Read, Write},
-    net::{IpAddr, SocketAddr, TcpListener, TcpStream}, error::Error, fmt::{Display, Debug},
+    io::{self, Cursor, Read, Write},
+    net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream}, error::Error, fmt::{Display, Debug},
+    time::Duration,
 };
 
+/// Something new connections can be accepted from.
+pub trait Listener: Send {
+    fn accept(&self) -> io::Result<(TcpStream, SocketAddr)>;
+
+    /// The address connections are accepted on, if there is one.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        TcpListener::accept(self)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        TcpListener::local_addr(self)
+    }
+}
+
 pub struct ConnectionManager {
-    listener: TcpListener,
+    listener: Box<dyn Listener>,
 }
 
 impl ConnectionManager {
-    pub fn launch(address: impl Into<IpAddr>, port: u16) -> Self {
-        let address = address.into();
-        let listener = TcpListener::bind((address, port))
-            .unwrap_or_else(|_| panic!("failed to bind to {address}:{port}"));
-
-        Self { listener }
-    }
-
-    pub fn accept_new_connection(&mut self) -> (ConnectionRead, ConnectionWrite) {
-        loop {
-            match self.listener.accept() {
-                Ok((socket, addr)) => {
-                    let (socket_read, socket_write) = (
-                        match socket.try_clone() {
-                            Ok(socket) => socket,
-                            Err(err) => {
-                                eprintln!("[WARN] Failed to clone socket: {err}");
-                                continue;
-                            }
-                        },
-                        socket
-                    );
-
-                    return (
-                        ConnectionRead::from_socket(socket_read, addr),
-                        ConnectionWrite::from_socket(socket_write, addr)
-                    );
-                }
-                Err(err) => {
-                    eprintln!("[WARN] failed to connect to client: {err}");
-                }
-            }
+    pub fn launch(address: impl Into<IpAddr>, port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((address.into(), port))?;
+
+        Ok(Self::from_listener(listener))
+    }
+
+    /// A manager accepting connections from `listener` rather than a bound socket.
+    pub fn from_listener(listener: impl Listener + 'static) -> Self {
+        Self {
+            listener: Box::new(listener),
         }
     }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn accept_new_connection(&mut self) -> io::Result<(ConnectionRead, ConnectionWrite)> {
+        let (socket, addr) = self.listener.accept()?;
+        let socket_read = socket.try_clone()?;
+
+        Ok((
+            ConnectionRead::from_socket(socket_read, addr),
+            ConnectionWrite::from_socket(socket, addr),
+        ))
+    }
 }
 
+/// Whether accepting a connection failed for a reason that may go away by itself,
+/// such as the client hanging up early or the process running out of file descriptors.
+pub fn is_transient_accept_error(err: &io::Error) -> bool {
+    use std::io::ErrorKind;
+
+    // EMFILE and ENFILE, which have no `ErrorKind` of their own
+    #[cfg(unix)]
+    if matches!(err.raw_os_error(), Some(23 | 24)) {
+        return true;
+    }
+
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+            | ErrorKind::TimedOut
+            | ErrorKind::OutOfMemory
+    )
+}
+
+/// Something a connection's messages can be read from.
+pub trait ReadSource: Read + Send {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ReadSource for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl ReadSource for Cursor<Vec<u8>> {}
+
 pub struct ConnectionRead {
-    socket: TcpStream,
+    socket: Box<dyn ReadSource>,
     socket_addr: SocketAddr,
     buffer: Box<[u8; 512]>,
     buflen: usize,
@@ -62,6 +112,7 @@ pub enum ConnectionError {
     ConnectionClosed,
     MessageTooLong,
     MessageInvalidUtf8,
+    Timeout,
 }
 
 impl Display for ConnectionError {
@@ -74,6 +125,16 @@ impl Error for ConnectionError {}
 
 impl ConnectionRead {
     fn from_socket(socket: TcpStream, socket_addr: SocketAddr) -> Self {
+        Self::from_source(Box::new(socket), socket_addr)
+    }
+
+    /// A connection that reads from `bytes` in memory rather than a socket,
+    /// so a `Client` can be driven synchronously (e.g. in tests).
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>, socket_addr: SocketAddr) -> Self {
+        Self::from_source(Box::new(Cursor::new(bytes.into())), socket_addr)
+    }
+
+    fn from_source(socket: Box<dyn ReadSource>, socket_addr: SocketAddr) -> Self {
         Self {
             socket,
             socket_addr,
@@ -102,6 +163,10 @@ impl ConnectionRead {
                         match err.kind() {
                             // Retry `read` if interrupted...
                             ErrorKind::Interrupted => continue,
+                            // Read timeout elapsed with nothing to read...
+                            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                                return Err(ConnectionError::Timeout)
+                            }
                             _ => return Err(ConnectionError::ConnectionLost),
                         }
                     }
@@ -124,7 +189,7 @@ impl ConnectionRead {
         let after_crlf = end + 2;
 
         self.buffer.copy_within(after_crlf..self.buflen, 0);
-        self.buflen = self.buflen - after_crlf;
+        self.buflen -= after_crlf;
 
         let message = String::from_utf8(bytes).map_err(|_| ConnectionError::MessageInvalidUtf8)?;
 
@@ -134,6 +199,17 @@ impl ConnectionRead {
     pub fn id(&self) -> String {
         self.socket_addr.to_string()
     }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.socket_addr
+    }
+
+    /// Make `read_message` give up with `ConnectionError::Timeout` after `timeout`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        if let Err(err) = self.socket.set_read_timeout(timeout) {
+            eprintln!("[WARN] Failed to set read timeout: {err}");
+        }
+    }
 }
 
 impl ConnectionWrite {
@@ -156,4 +232,9 @@ impl ConnectionWrite {
     pub fn id(&self) -> String {
         self.socket_addr.to_string()
     }
+
+    /// Close the connection in both directions, waking up anything blocked reading from it.
+    pub fn shutdown(&self) {
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
 }
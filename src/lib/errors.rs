@@ -1,4 +1,38 @@
+use std::{fmt, io, net::SocketAddr};
+
 pub enum LoopControlError {
     Break,
     Continue,
 }
+
+/// Why the server could not be started.
+#[derive(Debug)]
+pub enum IrisError {
+    /// A listening socket could not be bound.
+    Bind {
+        address: SocketAddr,
+        source: io::Error,
+    },
+    /// The configuration can't be used as given.
+    Config(String),
+}
+
+impl fmt::Display for IrisError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IrisError::Bind { address, source } => {
+                write!(fmt, "failed to bind to {address}: {source}")
+            }
+            IrisError::Config(reason) => write!(fmt, "invalid configuration: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for IrisError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            IrisError::Bind { source, .. } => Some(source),
+            IrisError::Config(_) => None,
+        }
+    }
+}
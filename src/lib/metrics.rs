@@ -0,0 +1,282 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::types::Command;
+
+/// Traffic counters shared by every connection.
+/// Updated with relaxed atomics so counting never takes a lock.
+#[derive(Debug)]
+pub struct Metrics {
+    started: Instant,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    messages_in: AtomicU64,
+    messages_out: AtomicU64,
+    commands: [AtomicU64; Command::ALL.len()],
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            bytes_in: AtomicU64::default(),
+            bytes_out: AtomicU64::default(),
+            messages_in: AtomicU64::default(),
+            messages_out: AtomicU64::default(),
+            commands: Default::default(),
+        }
+    }
+}
+
+/// A point-in-time copy of `Metrics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub uptime: Duration,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub messages_in: u64,
+    pub messages_out: u64,
+    pub commands: Vec<(Command, u64)>,
+}
+
+impl Metrics {
+    pub fn record_in(&self, message: &str) {
+        self.messages_in.fetch_add(1, Ordering::Relaxed);
+        self.bytes_in
+            .fetch_add(message.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_out(&self, message: &str) {
+        self.messages_out.fetch_add(1, Ordering::Relaxed);
+        self.bytes_out
+            .fetch_add(message.len() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_command(&self, command: Command) {
+        self.commands[command as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            uptime: self.started.elapsed(),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            messages_in: self.messages_in.load(Ordering::Relaxed),
+            messages_out: self.messages_out.load(Ordering::Relaxed),
+            commands: Command::ALL
+                .into_iter()
+                .map(|command| {
+                    (
+                        command,
+                        self.commands[command as usize].load(Ordering::Relaxed),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl MetricsSnapshot {
+    /// Render the snapshot, along with the current number of `clients` and `channels`,
+    /// in the Prometheus text exposition format.
+    pub fn to_prometheus(&self, clients: usize, channels: usize) -> String {
+        let mut body = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            let _ = write!(
+                body,
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+            );
+        };
+
+        metric(
+            "iris_uptime_seconds",
+            "gauge",
+            "Time since the server started.",
+            self.uptime.as_secs_f64().to_string(),
+        );
+        metric(
+            "iris_clients",
+            "gauge",
+            "Registered clients.",
+            clients.to_string(),
+        );
+        metric(
+            "iris_channels",
+            "gauge",
+            "Channels that exist.",
+            channels.to_string(),
+        );
+        metric(
+            "iris_messages_received_total",
+            "counter",
+            "Lines received from clients.",
+            self.messages_in.to_string(),
+        );
+        metric(
+            "iris_messages_sent_total",
+            "counter",
+            "Lines sent to clients.",
+            self.messages_out.to_string(),
+        );
+        metric(
+            "iris_bytes_received_total",
+            "counter",
+            "Bytes received from clients.",
+            self.bytes_in.to_string(),
+        );
+        metric(
+            "iris_bytes_sent_total",
+            "counter",
+            "Bytes sent to clients.",
+            self.bytes_out.to_string(),
+        );
+
+        body.push_str("# HELP iris_commands_total Commands handled, by command.\n");
+        body.push_str("# TYPE iris_commands_total counter\n");
+        self.commands.iter().for_each(|(command, count)| {
+            let _ = writeln!(body, "iris_commands_total{{command=\"{command}\"}} {count}");
+        });
+
+        body
+    }
+}
+
+/// Serve `GET /metrics` on `listener` with the body produced by `render`, one request at a time,
+/// until a connection arrives after `shutdown` is set.
+pub fn serve(listener: TcpListener, render: impl Fn() -> String, shutdown: &AtomicBool) {
+    for stream in listener.incoming() {
+        if shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let result = stream.and_then(|stream| respond(stream, &render));
+        if let Err(err) = result {
+            log::warn!("Failed to serve metrics: {err}");
+        }
+    }
+}
+
+/// Answer a single HTTP request on `stream`.
+fn respond(mut stream: TcpStream, render: &impl Fn() -> String) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // read the headers so the client sees our response rather than a reset
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let (status, body) = match request_line.split(' ').take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", render()),
+        _ => ("404 Not Found", String::from("Not Found\n")),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_counts() {
+        let metrics = Arc::new(Metrics::default());
+
+        let handles = (0..8)
+            .map(|_| {
+                let metrics = metrics.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        metrics.record_in("JOIN #a\r\n");
+                        metrics.record_command(Command::Join);
+                        metrics.record_out(":a JOIN #a\r\n");
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .for_each(|handle| handle.join().unwrap());
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.messages_in, 800);
+        assert_eq!(snapshot.bytes_in, 800 * 9);
+        assert_eq!(snapshot.messages_out, 800);
+        assert_eq!(snapshot.bytes_out, 800 * 12);
+        assert!(snapshot
+            .commands
+            .iter()
+            .all(|&(command, count)| count == if command == Command::Join { 800 } else { 0 }));
+    }
+
+    #[test]
+    fn test_serve_prometheus() {
+        let metrics = Metrics::default();
+        metrics.record_in("JOIN #a\r\n");
+        metrics.record_command(Command::Join);
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let scrape = |path: &str| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..2 {
+                    let (stream, _) = listener.accept().unwrap();
+                    respond(stream, &|| metrics.snapshot().to_prometheus(3, 2)).unwrap();
+                }
+            });
+
+            assert!(scrape("/").starts_with("HTTP/1.1 404"));
+            scrape("/metrics")
+        });
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+
+        let samples = body
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let (name, value) = line.rsplit_once(' ').unwrap();
+                (name, value.parse::<f64>().unwrap())
+            })
+            .collect::<Vec<_>>();
+        for (name, value) in [
+            ("iris_clients", 3.0),
+            ("iris_channels", 2.0),
+            ("iris_messages_received_total", 1.0),
+            ("iris_messages_sent_total", 0.0),
+            ("iris_commands_total{command=\"JOIN\"}", 1.0),
+        ] {
+            assert!(samples.contains(&(name, value)), "missing {name} {value}");
+        }
+        assert!(samples
+            .iter()
+            .any(|(name, _)| *name == "iris_uptime_seconds"));
+    }
+}
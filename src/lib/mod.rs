@@ -1,126 +1,566 @@
+pub mod builder;
 pub mod client;
+pub mod config;
 pub mod connect;
 pub mod errors;
 pub mod events;
 pub mod handler;
+pub mod metrics;
 pub mod types;
 
 use std::{
     collections::HashMap,
-    net::IpAddr,
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    path::Path,
     sync::{
+        atomic::{AtomicBool, Ordering},
         mpsc::{self, Sender},
         Arc, Mutex,
     },
     thread,
+    time::{Duration, SystemTime},
 };
 
-use client::Client;
+use builder::IrisBuilder;
+use client::{lock, Channels, Client, Clients, HeldNicks, ServerInfo};
+use config::{Config, HmacCloak, HostCloak, SystemHostname};
 use connect::{ConnectionRead, ConnectionWrite};
-use types::{Channel, Nick};
+use errors::IrisError;
+use log::LevelFilter;
+use metrics::{Metrics, MetricsSnapshot};
 
 use crate::{
-    connect::ConnectionManager, errors::LoopControlError, events::IrcEvent, types::SERVER_NAME,
+    connect::ConnectionManager,
+    events::IrcEvent,
+    types::{Channel, Nick, Reply, ServerNoticeReply},
 };
 
+/// An open connection, as listed by `Iris::connections`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// Where the connection comes from, source port included.
+    pub addr: SocketAddr,
+    /// The client on the connection, once it has registered.
+    pub nick: Option<Nick>,
+}
+
+/// How long to wait before accepting again after a transient failure.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How often to look for channels that have stayed empty past `Config::channel_grace`,
+/// and for timed mutes that have run out. Short, so stopping the server isn't held up.
+const CHANNEL_REAP_INTERVAL: Duration = Duration::from_millis(100);
+
 pub struct Iris {
     ip_address: IpAddr,
     port: u16,
-    clients: Arc<Mutex<HashMap<Nick, Sender<IrcEvent>>>>,
-    channels: Arc<Mutex<HashMap<Channel, HashMap<Nick, Sender<IrcEvent>>>>>,
+    config: Arc<Config>,
+    metrics: Arc<Metrics>,
+    clients: Clients,
+    channels: Channels,
+    held_nicks: HeldNicks,
+    server: Arc<ServerInfo>,
+    host_cloak: Arc<dyn HostCloak>,
+    /// Set by `stop` to make `start` return.
+    shutdown: AtomicBool,
+    /// The write half of every open connection, by where it comes from, so `stop` can close them.
+    connections: Mutex<HashMap<SocketAddr, Sender<IrcEvent>>>,
+    /// Where the server is listening for IRC connections and serving metrics, once started.
+    local_addr: Mutex<Option<SocketAddr>>,
+    metrics_local_addr: Mutex<Option<SocketAddr>>,
 }
 
 impl Iris {
+    /// Configure a server setting by setting. `new` and `with_config` are shortcuts for common cases.
+    pub fn builder() -> IrisBuilder {
+        IrisBuilder::new()
+    }
+
     pub fn new(ip_address: IpAddr, port: u16) -> Self {
+        Self::with_config(ip_address, port, Config::default())
+    }
+
+    pub fn with_config(ip_address: IpAddr, port: u16, config: Config) -> Self {
         Self {
             ip_address,
             port,
+            server: Arc::new(ServerInfo {
+                name: config.resolve_server_name(&SystemHostname),
+                created: SystemTime::now(),
+                motd: config
+                    .motd_path
+                    .as_deref()
+                    .map(load_motd)
+                    .unwrap_or_default(),
+            }),
+            host_cloak: Arc::new(HmacCloak::from_config(&config)),
+            config: Arc::new(config),
+            metrics: Arc::new(Metrics::default()),
             clients: Arc::new(Mutex::new(HashMap::new())),
             channels: Arc::new(Mutex::new(HashMap::new())),
+            held_nicks: Arc::new(Mutex::new(HashMap::new())),
+            shutdown: AtomicBool::new(false),
+            connections: Mutex::new(HashMap::new()),
+            local_addr: Mutex::new(None),
+            metrics_local_addr: Mutex::new(None),
         }
     }
 
-    pub fn start(&self) {
+    /// How client addresses are hidden. `HmacCloak` unless the builder was given another.
+    pub fn host_cloak(&self) -> &dyn HostCloak {
+        self.host_cloak.as_ref()
+    }
+
+    /// A snapshot of the server's traffic counters.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// The server's metrics in the Prometheus text format.
+    fn prometheus_metrics(&self) -> String {
+        let clients = lock(&self.clients, "clients").len();
+        let channels = lock(&self.channels, "channels").len();
+        self.metrics.snapshot().to_prometheus(clients, channels)
+    }
+
+    /// Send a raw `line` to every member of `channel`, returning how many members it was sent to.
+    /// Any CR/LF in `line` is stripped and a single CRLF is appended.
+    pub fn broadcast_channel(&self, channel: &Channel, line: &str) -> usize {
+        let line = format!("{}\r\n", line.replace(['\r', '\n'], ""));
+        let recipients = lock(&self.channels, "channels")
+            .get(channel)
+            .map(|channel| {
+                channel
+                    .members
+                    .values()
+                    .map(|member| member.sender.clone())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        recipients
+            .into_iter()
+            .filter(|sender| sender.send(IrcEvent::Send(line.clone())).is_ok())
+            .count()
+    }
+
+    /// Every open connection, with the client on it if it has registered.
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        let mut connections = lock(&self.connections, "connections")
+            .keys()
+            .map(|&addr| ConnectionInfo { addr, nick: None })
+            .collect::<Vec<_>>();
+
+        let clients = lock(&self.clients, "clients");
+        for connection in &mut connections {
+            connection.nick = clients
+                .iter()
+                .find(|(_, info)| info.addr == connection.addr)
+                .map(|(nick, _)| nick.clone());
+        }
+        connections
+    }
+
+    /// The channels `nick` is currently a member of.
+    pub fn user_channels(&self, nick: &Nick) -> Vec<Channel> {
+        lock(&self.channels, "channels")
+            .iter()
+            .filter(|(_, channel)| channel.members.contains_key(nick))
+            .map(|(channel, _)| channel.clone())
+            .collect()
+    }
+
+    /// The address the server is listening on, once it has started.
+    /// Useful when it was asked to listen on port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        *lock(&self.local_addr, "local address")
+    }
+
+    /// Run the server until it is stopped or stops listening for connections,
+    /// returning once every connection has been closed.
+    /// Returns an error, without serving anything, if it can't be started.
+    pub fn start(&self) -> Result<(), IrisError> {
+        let server_name = &self.server.name;
+        if server_name.is_empty() || server_name.contains([' ', ':']) {
+            return Err(IrisError::Config(format!(
+                "server name {server_name:?} must be non-empty and have no spaces or colons"
+            )));
+        }
+
+        let connection_manager =
+            ConnectionManager::launch(self.ip_address, self.port).map_err(|source| {
+                IrisError::Bind {
+                    address: (self.ip_address, self.port).into(),
+                    source,
+                }
+            })?;
+        let metrics_listener = self
+            .config
+            .metrics_address
+            .map(|address| {
+                TcpListener::bind(address).map_err(|source| IrisError::Bind { address, source })
+            })
+            .transpose()?;
+
+        *lock(&self.local_addr, "local address") = connection_manager.local_addr().ok();
+        *lock(&self.metrics_local_addr, "metrics address") = metrics_listener
+            .as_ref()
+            .and_then(|listener| listener.local_addr().ok());
+
         thread::scope(|scope| {
             log::info!(
-                "Launching {} at {}:{}",
-                SERVER_NAME,
+                "Launching {server_name} at {}:{}",
                 self.ip_address,
                 self.port
             );
 
+            if let Some(listener) = metrics_listener {
+                if let Ok(address) = listener.local_addr() {
+                    log::info!("Serving metrics at http://{address}/metrics");
+                }
+                scope.spawn(move || {
+                    metrics::serve(listener, || self.prometheus_metrics(), &self.shutdown)
+                });
+            }
+
+            scope.spawn(|| self.maintain_channels());
+
             // accept loop
-            scope.spawn(move || {
-                let mut connection_manager = ConnectionManager::launch(self.ip_address, self.port);
-                loop {
-                    let (conn_read, conn_write) = connection_manager.accept_new_connection();
+            scope.spawn(move || self.accept_connections(scope, connection_manager));
+        });
+
+        log::info!("{server_name} stopped");
+        Ok(())
+    }
+
+    /// Change how much is logged, for the whole server, without restarting it.
+    /// The same as an operator's `DEBUG LOGLEVEL <level>`.
+    pub fn set_log_level(&self, level: LevelFilter) {
+        set_log_level(level);
+    }
+
+    /// Make a running `start` return: stop accepting connections,
+    /// tell every client the server is shutting down, and close every connection.
+    pub fn stop(&self) {
+        log::info!("Shutting down");
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        for (nick, client) in lock(&self.clients, "clients").iter() {
+            let notice = Reply::ServerNotice(ServerNoticeReply {
+                target_nick: nick.clone(),
+                message: String::from("Server shutting down"),
+            });
+            let _ = client
+                .sender
+                .send(IrcEvent::Send(notice.format(&self.server.name)));
+        }
+        for sender in lock(&self.connections, "connections").values() {
+            let _ = sender.send(IrcEvent::Terminate);
+        }
+
+        // wake up the listeners blocked waiting for a connection, so they see `shutdown`
+        let listening = [&self.local_addr, &self.metrics_local_addr];
+        for mut address in listening
+            .into_iter()
+            .filter_map(|address| *lock(address, "listening address"))
+        {
+            if address.ip().is_unspecified() {
+                address.set_ip(Ipv4Addr::LOCALHOST.into());
+            }
+            let _ = TcpStream::connect(address);
+        }
+    }
+
+    /// Delete channels that have stayed empty past `Config::channel_grace` and lift
+    /// timed mutes that have run out, until stopped.
+    fn maintain_channels(&self) {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            thread::sleep(CHANNEL_REAP_INTERVAL);
+            client::expire_mutes(&self.channels, &self.server.name);
+            if !self.config.channel_grace.is_zero() {
+                client::reap_channels(&self.channels, self.config.channel_grace);
+            }
+        }
+    }
+
+    /// Accept connections until the listener fails for good, handling each in its own thread.
+    /// Transient failures are logged and retried after `ACCEPT_BACKOFF`.
+    fn accept_connections<'scope>(
+        &'scope self,
+        scope: &'scope thread::Scope<'scope, '_>,
+        mut connection_manager: ConnectionManager,
+    ) {
+        while !self.shutdown.load(Ordering::SeqCst) {
+            match connection_manager.accept_new_connection() {
+                Ok(_) if self.shutdown.load(Ordering::SeqCst) => break,
+                Ok((conn_read, conn_write)) => {
                     log::info!("{}# Connection established", conn_read.id());
                     scope.spawn(|| self.handle_connection(conn_read, conn_write));
                 }
-            });
-        });
+                Err(err) if connect::is_transient_accept_error(&err) => {
+                    log::warn!("Failed to accept connection: {err}");
+                    thread::sleep(ACCEPT_BACKOFF);
+                }
+                Err(err) => {
+                    log::error!("Failed to accept connection, no longer listening: {err}");
+                    break;
+                }
+            }
+        }
     }
 
-    fn handle_connection(&self, conn_read: ConnectionRead, mut conn_write: ConnectionWrite) {
+    fn handle_connection(&self, mut conn_read: ConnectionRead, mut conn_write: ConnectionWrite) {
+        if let Some(timeout) = self.config.read_timeout() {
+            // wake the read loop up periodically so idle clients can be detected
+            conn_read.set_read_timeout(Some(timeout));
+        }
+
+        let id = conn_read.id();
+        let addr = conn_read.addr();
         let (tx, rx) = mpsc::channel::<IrcEvent>();
+        lock(&self.connections, "connections").insert(addr, tx.clone());
+        if self.shutdown.load(Ordering::SeqCst) {
+            // `stop` may have missed this connection
+            let _ = tx.send(IrcEvent::Terminate);
+        }
+
         let mut client = Client::new(
             conn_read,
-            tx.clone(),
+            tx,
+            self.config.clone(),
+            self.metrics.clone(),
             self.clients.clone(),
             self.channels.clone(),
+            self.held_nicks.clone(),
+            self.server.clone(),
         );
-        let clients = self.clients.clone();
 
         // thread for reading and handling messages
         // messages are handled by sending (through a channel) a server reply to the write loop thread where the reply is sent
-        let read_loop_handle = thread::spawn(move || {
-            let nick = match client.login() {
-                Some(nick) => nick,
-                None => {
-                    client.terminate();
-                    return; // connection lost during login
-                }
-            };
-            clients.lock().unwrap().insert(nick, tx.clone());
-
-            loop {
-                // wait for message
-                let message = match client.recv() {
-                    Ok(message) => message,
-                    Err(LoopControlError::Break) => break,
-                    Err(LoopControlError::Continue) => continue,
-                };
-
-                log::info!("{}# Received message: {message}", client.rid());
-
-                // parse the received message
-                let parsed_message = match client.parse(message) {
-                    Ok(parsed_message) => parsed_message,
-                    Err(LoopControlError::Break) => break, // should not happen
-                    Err(LoopControlError::Continue) => continue,
-                };
-
-                // handle parsed message
-                if let Err(LoopControlError::Break) = client.handle_message(parsed_message) {
-                    break;
-                }
-            }
-
-            client.terminate();
-        });
+        let read_loop_handle = thread::spawn(move || client.run());
 
         // thread for sending server replies
+        let metrics = self.metrics.clone();
+        let write_id = id.clone();
         let write_loop_handle = thread::spawn(move || {
             while let Ok(event) = rx.recv() {
                 match event {
-                    IrcEvent::Send(message) => conn_write.write_message(&message).unwrap(),
+                    IrcEvent::Send(message) => {
+                        if let Err(err) = conn_write.write_message(&message) {
+                            log::warn!("{write_id}# Failed to write to connection: {err}");
+                            break;
+                        }
+                        metrics.record_out(&message);
+                    }
                     IrcEvent::Terminate => break,
                 }
             }
+            // let the read loop know too, if it's still waiting for input
+            conn_write.shutdown();
         });
 
-        read_loop_handle.join().unwrap();
-        write_loop_handle.join().unwrap();
+        if read_loop_handle.join().is_err() {
+            log::error!("{id}# Read loop panicked");
+        }
+        if write_loop_handle.join().is_err() {
+            log::error!("{id}# Write loop panicked");
+        }
+        lock(&self.connections, "connections").remove(&addr);
         log::debug!("Thread finished");
     }
 }
+
+/// Set the most verbose level that is logged. The logger's own filter still applies,
+/// so this can't bring back what e.g. `RUST_LOG` leaves out.
+pub(crate) fn set_log_level(level: LevelFilter) {
+    log::set_max_level(level);
+    log::info!("Log level set to {level}");
+}
+
+/// Read the message of the day from `path`, one entry per line.
+/// A file that can't be read is logged and treated as having no message.
+fn load_motd(path: &Path) -> Vec<String> {
+    match fs::read_to_string(path) {
+        Ok(motd) => motd.lines().map(str::to_string).collect(),
+        Err(err) => {
+            log::warn!("Failed to read MOTD from {}: {err}", path.display());
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{ChannelState, Member};
+
+    #[test]
+    fn test_user_channels() {
+        let iris = Iris::new([127, 0, 0, 1].into(), 0);
+        let (tx, _rx) = mpsc::channel();
+        let alice = Nick("alice".to_string());
+
+        let mut channels = iris.channels.lock().unwrap();
+        for channel in ["#a", "#b", "#c"] {
+            channels.insert(Channel(channel.to_string()), ChannelState::default());
+        }
+        for channel in ["#a", "#c"] {
+            channels
+                .get_mut(&Channel(channel.to_string()))
+                .unwrap()
+                .members
+                .insert(alice.clone(), Member::new(tx.clone()));
+        }
+        drop(channels);
+
+        let mut user_channels = iris.user_channels(&alice);
+        user_channels.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            user_channels,
+            vec![Channel("#a".to_string()), Channel("#c".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_broadcast_channel() {
+        let iris = Iris::new([127, 0, 0, 1].into(), 0);
+        let channel = Channel("#a".to_string());
+
+        let receivers = ["alice", "bob", "carol"]
+            .into_iter()
+            .map(|nick| {
+                let (tx, rx) = mpsc::channel();
+                iris.channels
+                    .lock()
+                    .unwrap()
+                    .entry(channel.clone())
+                    .or_default()
+                    .members
+                    .insert(Nick(nick.to_string()), Member::new(tx));
+                rx
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(iris.broadcast_channel(&channel, "NOTICE #a :hello\r\n"), 3);
+        for rx in receivers {
+            assert!(matches!(
+                rx.try_recv(),
+                Ok(IrcEvent::Send(line)) if line == "NOTICE #a :hello\r\n"
+            ));
+        }
+
+        assert_eq!(
+            iris.broadcast_channel(&Channel("#b".to_string()), "NOTICE #b :hello"),
+            0
+        );
+    }
+
+    /// A listener that fails with each of `errors` in turn.
+    struct FailingListener {
+        errors: Arc<Mutex<Vec<std::io::Error>>>,
+    }
+
+    impl connect::Listener for FailingListener {
+        fn accept(&self) -> std::io::Result<(std::net::TcpStream, std::net::SocketAddr)> {
+            Err(self.errors.lock().unwrap().remove(0))
+        }
+    }
+
+    #[test]
+    fn test_accept_error_recovery() {
+        use std::io::{Error, ErrorKind};
+
+        let iris = Iris::new([127, 0, 0, 1].into(), 0);
+        let errors = Arc::new(Mutex::new(vec![
+            Error::from(ErrorKind::ConnectionAborted),
+            Error::from_raw_os_error(24), // EMFILE
+            Error::from(ErrorKind::InvalidInput),
+            Error::from(ErrorKind::ConnectionAborted),
+        ]));
+        let connection_manager = ConnectionManager::from_listener(FailingListener {
+            errors: errors.clone(),
+        });
+
+        thread::scope(|scope| iris.accept_connections(scope, connection_manager));
+
+        // kept accepting through the transient errors, stopped at the fatal one
+        assert_eq!(errors.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_load_motd() {
+        let path = std::env::temp_dir().join(format!("iris-motd-{}", std::process::id()));
+        fs::write(&path, "Welcome to iris\nBe nice\n").unwrap();
+        let iris = Iris::builder().motd_path(&path).build();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(iris.server.motd, ["Welcome to iris", "Be nice"]);
+
+        // a missing file is just no MOTD
+        let iris = Iris::builder().motd_path(&path).build();
+        assert!(iris.server.motd.is_empty());
+    }
+
+    #[test]
+    fn test_start_errors() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(matches!(
+            Iris::new([127, 0, 0, 1].into(), port).start(),
+            Err(IrisError::Bind { address, .. }) if address.port() == port
+        ));
+
+        let config = Config {
+            server_name: Some("iris server".to_string()),
+            ..Config::default()
+        };
+        assert!(matches!(
+            Iris::with_config([127, 0, 0, 1].into(), 0, config).start(),
+            Err(IrisError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn test_stop() {
+        use std::io::{BufRead, BufReader, Read, Write};
+
+        let iris = Iris::builder().listen([127, 0, 0, 1].into(), 0).build();
+
+        thread::scope(|scope| {
+            let server = scope.spawn(|| iris.start());
+            let address = loop {
+                match iris.local_addr() {
+                    Some(address) => break address,
+                    None => thread::sleep(Duration::from_millis(10)),
+                }
+            };
+
+            let mut registered = TcpStream::connect(address).unwrap();
+            write!(registered, "NICK alice\r\nUSER alice 0 * :Alice\r\n").unwrap();
+            let mut reader = BufReader::new(registered.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert!(line.contains(" 001 alice "));
+            let mut unregistered = TcpStream::connect(address).unwrap();
+            // wait until the server is handling both
+            while iris.connections.lock().unwrap().len() < 2 {
+                thread::sleep(Duration::from_millis(10));
+            }
+            let mut connections = iris.connections();
+            connections.sort_by_key(|connection| connection.nick.is_none());
+            assert_eq!(connections[0].addr, registered.local_addr().unwrap());
+            assert_eq!(connections[0].nick, Some(Nick("alice".to_string())));
+            assert_eq!(connections[1].addr, unregistered.local_addr().unwrap());
+            assert_eq!(connections[1].nick, None);
+
+            iris.stop();
+            assert!(server.join().unwrap().is_ok());
+
+            let mut rest = String::new();
+            reader.read_to_string(&mut rest).unwrap();
+            assert!(rest.ends_with("NOTICE alice :Server shutting down\r\n"));
+            assert_eq!(unregistered.read(&mut [0; 1]).unwrap(), 0);
+            assert!(iris.connections.lock().unwrap().is_empty());
+        });
+    }
+}
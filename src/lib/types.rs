@@ -1,56 +1,212 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
 /// All relevant IRC errors are listed here.
 /// See the assignment documentation for more information.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum ErrorType {
     NoNickNameGiven = 431,
     ErroneousNickname = 432,
+    NicknameInUse = 433,
     NickCollision = 436,
     NoRecipient = 411,
     NoTextToSend = 412,
     NoOrigin = 409,
     UnknownCommand = 421,
     NeedMoreParams = 461,
+    AlreadyRegistered = 462,
     NoSuchNick = 401,
     NoSuchChannel = 403,
+    TooManyChannels = 405,
+    InputTooLong = 417,
+    NotOnChannel = 442,
+    NoMotd = 422,
+    UserNotInChannel = 441,
+    UnknownMode = 472,
+    ChanOPrivsNeeded = 482,
+    UserOnChannel = 443,
+    InviteOnlyChan = 473,
+    BadChannelKey = 475,
+    ChannelIsFull = 471,
+    CannotSendToChan = 404,
+    BannedFromChan = 474,
+    PasswdMismatch = 464,
+    NoPrivileges = 481,
+    UModeUnknownFlag = 501,
+    UsersDontMatch = 502,
+    InvalidCapCmd = 410,
 }
 
 /// This is the name of your server, all messages originating from
 /// the server should be listed as from this name.
 pub const SERVER_NAME: &str = "iris-server";
 
+/// The server software and its version, as shown to clients.
+pub const VERSION: &str = concat!("iris-", env!("CARGO_PKG_VERSION"));
+
+/// The longest nickname the server accepts, unless configured otherwise.
+/// See `Config::max_nick_len`.
+pub const MAX_NICK_LEN: usize = 9;
+
+/// The longest channel name the server accepts, including the `#` or `&`.
+pub const MAX_CHANNEL_LEN: usize = 200;
+
+/// The IRCv3 capabilities a client may enable with CAP REQ.
+/// `multi-prefix` shows every status a member has in NAMES, WHO and WHOIS, e.g. `@+alice`.
+pub const SUPPORTED_CAPS: [&str; 1] = ["multi-prefix"];
+
+/// The longest line the server sends, including the CRLF.
+pub const MAX_LINE_LEN: usize = 512;
+
+/// The most parameters a command may have, as in RFC 1459.
+pub const MAX_PARAMS: usize = 15;
+
+/// The most comma-separated targets a single parameter may list.
+pub const MAX_TARGETS: usize = 20;
+
+/// The statuses a channel member can have, as their mode letter and the prefix
+/// shown before the member's nick, highest first.
+pub const MEMBER_STATUSES: [(char, char); 2] = [('o', '@'), ('v', '+')];
+
+/// The other channel modes, grouped as ISUPPORT's CHANMODES expects: lists,
+/// settings that always take a parameter, settings that only take one when set,
+/// and flags that never take one.
+pub const CHANNEL_MODES: [&str; 4] = ["bq", "k", "l", "imn"];
+
+/// The modes a user can have: invisible, operator and receiving WALLOPS.
+pub const USER_MODES: &str = "iow";
+
+/// Every channel mode letter, member statuses included, in alphabetical order.
+pub fn channel_mode_letters() -> String {
+    let mut letters = CHANNEL_MODES
+        .concat()
+        .chars()
+        .chain(MEMBER_STATUSES.iter().map(|(mode, _)| *mode))
+        .collect::<Vec<_>>();
+    letters.sort_unstable();
+    letters.into_iter().collect()
+}
+
+/// The ISUPPORT token advertising `MEMBER_STATUSES`, e.g. `PREFIX=(ov)@+`.
+pub fn prefix_token() -> String {
+    let (modes, prefixes): (String, String) = MEMBER_STATUSES.into_iter().unzip();
+    format!("PREFIX=({modes}){prefixes}")
+}
+
+/// The ISUPPORT token advertising which `MEMBER_STATUSES` a message can be
+/// addressed to, e.g. `STATUSMSG=@+`.
+pub fn statusmsg_token() -> String {
+    let prefixes: String = MEMBER_STATUSES.iter().map(|(_, prefix)| prefix).collect();
+    format!("STATUSMSG={prefixes}")
+}
+
+/// The ISUPPORT token advertising `CHANNEL_MODES`, e.g. `CHANMODES=b,k,l,imnt`.
+pub fn chanmodes_token() -> String {
+    format!("CHANMODES={}", CHANNEL_MODES.join(","))
+}
+
+impl ErrorType {
+    /// The error's three-digit numeric.
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+}
+
 impl std::fmt::Display for ErrorType {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match *self {
             ErrorType::NoNickNameGiven => {
-                write!(fmt, ":{SERVER_NAME} 431 :No nickname given.")
+                write!(fmt, "No nickname given.")
             }
             ErrorType::ErroneousNickname => {
                 // Typo is same as in RFC1459
-                write!(fmt, ":{SERVER_NAME} 432 :Erroneus nickname")
+                write!(fmt, "Erroneus nickname")
+            }
+            ErrorType::NicknameInUse => {
+                write!(fmt, "Nickname is already in use")
             }
             ErrorType::NoRecipient => {
-                write!(fmt, ":{SERVER_NAME} 411 :No recipient given")
+                write!(fmt, "No recipient given")
             }
             ErrorType::NoTextToSend => {
-                write!(fmt, ":{SERVER_NAME} 412 :No text to send")
+                write!(fmt, "No text to send")
             }
             ErrorType::NoOrigin => {
-                write!(fmt, ":{SERVER_NAME} 409 :No origin specified")
+                write!(fmt, "No origin specified")
             }
             ErrorType::UnknownCommand => {
-                write!(fmt, ":{SERVER_NAME} 421 :Unknown command")
+                write!(fmt, "Unknown command")
             }
             ErrorType::NeedMoreParams => {
-                write!(fmt, ":{SERVER_NAME} 461 :Not enough parameters")
+                write!(fmt, "Not enough parameters")
+            }
+            ErrorType::AlreadyRegistered => {
+                write!(fmt, "You may not reregister")
             }
             ErrorType::NoSuchNick => {
-                write!(fmt, ":{SERVER_NAME} 401 :No such nick/channel")
+                write!(fmt, "No such nick/channel")
             }
             ErrorType::NoSuchChannel => {
-                write!(fmt, ":{SERVER_NAME} 403 :No such channel")
+                write!(fmt, "No such channel")
             }
             ErrorType::NickCollision => {
-                write!(fmt, ":{SERVER_NAME} 436 :Nickname collision")
+                write!(fmt, "Nickname collision")
+            }
+            ErrorType::TooManyChannels => {
+                write!(fmt, "Too many channels")
+            }
+            ErrorType::InputTooLong => {
+                write!(fmt, "Input line was too long")
+            }
+            ErrorType::NotOnChannel => {
+                write!(fmt, "You're not on that channel")
+            }
+            ErrorType::NoMotd => {
+                write!(fmt, "MOTD File is missing")
+            }
+            ErrorType::UserNotInChannel => {
+                write!(fmt, "They aren't on that channel")
+            }
+            ErrorType::UnknownMode => {
+                write!(fmt, "is unknown mode char to me")
+            }
+            ErrorType::ChanOPrivsNeeded => {
+                write!(fmt, "You're not channel operator")
+            }
+            ErrorType::UserOnChannel => {
+                write!(fmt, "is already on channel")
+            }
+            ErrorType::InviteOnlyChan => {
+                write!(fmt, "Cannot join channel (+i)")
+            }
+            ErrorType::BadChannelKey => {
+                write!(fmt, "Cannot join channel (+k)")
+            }
+            ErrorType::ChannelIsFull => {
+                write!(fmt, "Cannot join channel (+l)")
+            }
+            ErrorType::CannotSendToChan => {
+                write!(fmt, "Cannot send to channel")
+            }
+            ErrorType::BannedFromChan => {
+                write!(fmt, "Cannot join channel (+b)")
+            }
+            ErrorType::PasswdMismatch => {
+                write!(fmt, "Password incorrect")
+            }
+            ErrorType::NoPrivileges => {
+                write!(fmt, "Permission Denied- You're not an IRC operator")
+            }
+            ErrorType::UModeUnknownFlag => {
+                write!(fmt, "Unknown MODE flag")
+            }
+            ErrorType::UsersDontMatch => {
+                write!(fmt, "Cant change mode for other users")
+            }
+            ErrorType::InvalidCapCmd => {
+                write!(fmt, "Invalid CAP command")
             }
         }
     }
@@ -59,37 +215,84 @@ impl std::fmt::Display for ErrorType {
 /// Given an IRC command, this will split it up into component parts.
 /// Particularly, the prefix (optionally), then all space-separated args,
 /// then (optionally) the final argument.
+/// Runs of spaces between args count as a single separator,
+/// but spacing inside the final argument is kept as-is.
 fn split_command(cmd: &str) -> Vec<&str> {
     let stripped = cmd.strip_suffix("\r\n").unwrap_or(cmd);
 
-    match stripped.split_once(':') {
-        Some((before, after)) => {
-            let mut cmd_vec = before.split(' ').collect::<Vec<_>>();
+    // the prefix only matters between servers, so it is dropped
+    let stripped = match stripped.strip_prefix(':') {
+        Some(prefixed) => prefixed.split_once(' ').map_or("", |(_, rest)| rest),
+        None => stripped,
+    };
 
-            if cmd_vec.last() == Some(&"") {
-                let _ = cmd_vec.pop();
-            }
+    let (middle, trailing) = match stripped.split_once(" :") {
+        Some((middle, trailing)) => (middle, Some(trailing)),
+        None => (stripped, None),
+    };
 
-            cmd_vec.push(after.trim_start());
-            cmd_vec
-        }
-        None => stripped.split(' ').collect::<Vec<_>>(),
-    }
+    let mut cmd_vec = middle
+        .split(' ')
+        .filter(|arg| !arg.is_empty())
+        .collect::<Vec<_>>();
+    cmd_vec.extend(trailing);
+    cmd_vec
+}
+
+/// Whether a line has no command word at all, e.g. it is blank or only a prefix.
+/// Such lines should be skipped rather than answered with an error.
+pub fn is_empty_command(message: &str) -> bool {
+    split_command(message).is_empty()
+}
+
+/// Whether a line looks like it came from something other than an IRC client,
+/// such as an HTTP request or binary junk from a port scanner.
+pub fn looks_like_probe(message: &str) -> bool {
+    const HTTP_METHODS: [&str; 7] = ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "CONNECT"];
+
+    let is_http = message
+        .split_once(' ')
+        .is_some_and(|(method, rest)| HTTP_METHODS.contains(&method) && rest.contains(" HTTP/"));
+    let unprintable = message
+        .chars()
+        .filter(|c| c.is_control() && *c != '\t')
+        .count();
+
+    is_http || unprintable * 4 > message.chars().count()
 }
 
 /// A person or channel to whom a command is addressed.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Target {
     Channel(Channel),
+    /// The members of a channel with at least the status shown by the prefix,
+    /// e.g. `@#channel` for its operators (STATUSMSG).
+    ChannelStatus(char, Channel),
     User(Nick),
 }
 
+impl Target {
+    /// The member status prefix the target is limited to, if any.
+    pub fn status(&self) -> Option<char> {
+        match self {
+            Target::ChannelStatus(status, _) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
 impl From<String> for Target {
     fn from(value: String) -> Self {
-        if value.starts_with('#') {
-            Target::Channel(Channel(value))
-        } else {
-            Target::User(Nick(value))
+        let status = value
+            .chars()
+            .next()
+            .filter(|first| MEMBER_STATUSES.iter().any(|(_, prefix)| prefix == first));
+        match status {
+            Some(status) if value[1..].starts_with(['#', '&']) => {
+                Target::ChannelStatus(status, Channel(value[1..].to_string()))
+            }
+            _ if value.starts_with(['#', '&']) => Target::Channel(Channel(value)),
+            _ => Target::User(Nick(value)),
         }
     }
 }
@@ -98,24 +301,59 @@ impl std::fmt::Display for Target {
     fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
             Target::Channel(s) => write!(fmt, "{s}"),
+            Target::ChannelStatus(status, s) => write!(fmt, "{status}{s}"),
             Target::User(s) => write!(fmt, "{s}"),
         }
     }
 }
 
+/// Who a relayed message came from, written as its `nick!user@host` prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prefix {
+    pub nick: Nick,
+    pub user: String,
+    pub host: String,
+}
+
+impl std::fmt::Display for Prefix {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(fmt, "{}!{}@{}", self.nick, self.user, self.host)
+    }
+}
+
 /// A nickname.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Nicknames are compared case-insensitively, but keep the case they were written in.
+#[derive(Debug, Clone, Eq)]
 pub struct Nick(pub String);
 
+impl PartialEq for Nick {
+    fn eq(&self, other: &Self) -> bool {
+        irc_eq(&self.0, &other.0)
+    }
+}
+
+impl std::hash::Hash for Nick {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        irc_hash(&self.0, state);
+    }
+}
+
 impl TryFrom<String> for Nick {
     type Error = ErrorType;
 
+    /// Nicknames follow RFC 2812: a letter or special character,
+    /// then letters, digits, special characters or `-`.
+    /// How long they may be is up to the server, see `Config::nick_len`.
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        let value = value;
-        if (1..10).contains(&value.len())
-            && value.is_ascii()
-            && value.chars().next().unwrap_or('!').is_alphabetic()
-            && value.chars().all(char::is_alphanumeric)
+        let is_special = |c: char| "[]\\`_^{|}".contains(c);
+        let mut chars = value.chars();
+
+        if value.is_empty() {
+            Err(ErrorType::NoNickNameGiven)
+        } else if chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || is_special(c))
+            && chars.all(|c| c.is_ascii_alphanumeric() || is_special(c) || c == '-')
         {
             Ok(Nick(value))
         } else {
@@ -130,18 +368,116 @@ impl std::fmt::Display for Nick {
     }
 }
 
+/// Lowercase a character per RFC 1459 casemapping,
+/// where `[]\~` are the uppercase forms of `{}|^`.
+fn irc_to_lowercase(c: char) -> char {
+    match c {
+        '[' => '{',
+        ']' => '}',
+        '\\' => '|',
+        '~' => '^',
+        c => c.to_ascii_lowercase(),
+    }
+}
+
+/// Whether two names are equal under RFC 1459 casemapping.
+fn irc_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.chars()
+            .zip(b.chars())
+            .all(|(a, b)| irc_to_lowercase(a) == irc_to_lowercase(b))
+}
+
+/// Hash a name consistently with `irc_eq`.
+fn irc_hash<H: std::hash::Hasher>(name: &str, state: &mut H) {
+    name.chars()
+        .for_each(|c| std::hash::Hash::hash(&irc_to_lowercase(c), state));
+}
+
+/// Complete a ban mask to the full `nick!user@host` form, e.g. `alice` to `alice!*@*`.
+pub fn normalize_mask(mask: &str) -> String {
+    match (mask.contains('!'), mask.contains('@')) {
+        (false, false) => format!("{mask}!*@*"),
+        (false, true) => format!("*!{mask}"),
+        (true, false) => format!("{mask}@*"),
+        (true, true) => mask.to_string(),
+    }
+}
+
+/// Split a mute mask into how long it lasts and the mask itself, for `MODE #channel +q`.
+/// A mute lasts `seconds` if written as `seconds:mask`, e.g. `60:bob!*@*`, and forever if not.
+/// Nicknames can't start with a digit, so a plain mask is never mistaken for a timed one.
+pub fn split_timed_mask(param: &str) -> (Option<Duration>, &str) {
+    match param.split_once(':') {
+        Some((seconds, mask)) => match seconds.parse() {
+            Ok(seconds) => (Some(Duration::from_secs(seconds)), mask),
+            Err(_) => (None, param),
+        },
+        None => (None, param),
+    }
+}
+
+/// Whether `name` matches `mask`, where `*` matches any run of characters and `?` any one.
+/// Compared under RFC 1459 casemapping.
+pub fn mask_matches(mask: &str, name: &str) -> bool {
+    let mask = mask.chars().map(irc_to_lowercase).collect::<Vec<_>>();
+    let name = name.chars().map(irc_to_lowercase).collect::<Vec<_>>();
+    let (mut m, mut n) = (0, 0);
+    // where the last `*` was, and how much of the name it has taken
+    let mut star = None;
+
+    while n < name.len() {
+        match mask.get(m) {
+            Some('*') => {
+                star = Some((m, n));
+                m += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                m += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_m, star_n)) => {
+                    star = Some((star_m, star_n + 1));
+                    m = star_m + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    mask[m..].iter().all(|&c| c == '*')
+}
+
 /// An IRC channel.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Channel names are compared case-insensitively, but keep the case they were written in.
+#[derive(Debug, Clone, Eq)]
 pub struct Channel(pub String);
 
+impl PartialEq for Channel {
+    fn eq(&self, other: &Self) -> bool {
+        irc_eq(&self.0, &other.0)
+    }
+}
+
+impl std::hash::Hash for Channel {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        irc_hash(&self.0, state);
+    }
+}
+
 impl TryFrom<String> for Channel {
     type Error = ErrorType;
 
+    /// Channel names follow RFC 2812: `#` or `&`, then anything but spaces,
+    /// commas, colons and control characters.
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        if (1..200).contains(&value.len())
-            && value.chars().next().unwrap_or('!') == '#'
-            && value.is_ascii()
-            && value[1..].chars().all(char::is_alphanumeric)
+        if (2..=MAX_CHANNEL_LEN).contains(&value.len())
+            && value.starts_with(['#', '&'])
+            && value[1..]
+                .chars()
+                .all(|c| !c.is_control() && !matches!(c, ' ' | ',' | ':'))
         {
             Ok(Channel(value))
         } else {
@@ -171,240 +507,1694 @@ impl TryFrom<Vec<String>> for NickMsg {
             .into_iter()
             .nth(1)
             .ok_or(ErrorType::NoNickNameGiven)
-            .and_then(|value| Nick::try_from(value))
+            .and_then(Nick::try_from)
             .map(|nick| NickMsg { nick })
     }
 }
 
-/// A message to join a channel.
-/// For example: `JOIN #channel\r\n`
+/// A message to join one or more channels, with a key for each of the first few.
+/// For example: `JOIN #channel,#other key\r\n`
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct JoinMsg {
-    pub channel: Channel,
+    pub channels: Vec<Channel>,
+    pub keys: Vec<String>,
 }
 
 impl TryFrom<Vec<String>> for JoinMsg {
     type Error = ErrorType;
 
     fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
-        value
-            .into_iter()
-            .nth(1)
-            .ok_or(ErrorType::NeedMoreParams)
-            .and_then(|value| Channel::try_from(value))
-            .map(|channel| JoinMsg { channel })
+        let mut value = value.into_iter().skip(1);
+        let channels = value.next().ok_or(ErrorType::NeedMoreParams)?;
+        let mut keys = value
+            .next()
+            .map(|keys| keys.split(',').map(str::to_string).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter();
+
+        let mut message = JoinMsg {
+            channels: Vec::new(),
+            keys: Vec::new(),
+        };
+        for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+            let channel = Channel::try_from(channel.to_string())?;
+            let key = keys.next();
+
+            // a channel listed twice is only joined once, with its first key
+            if !message.channels.contains(&channel) {
+                message.channels.push(channel);
+                message.keys.extend(key);
+            }
+        }
+
+        if message.channels.is_empty() {
+            return Err(ErrorType::NeedMoreParams);
+        }
+        Ok(message)
     }
 }
 
-/// A message to leave a channel.
-/// For example: `PART #channel\r\n`
+/// A message to leave one or more channels, with an optional reason.
+/// For example: `PART #channel,#other :Goodbye\r\n`
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PartMsg {
-    pub channel: Channel,
+    pub channels: Vec<Channel>,
+    pub message: Option<String>,
 }
 
 impl TryFrom<Vec<String>> for PartMsg {
     type Error = ErrorType;
 
     fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
-        value
-            .into_iter()
-            .nth(1)
-            .ok_or(ErrorType::NeedMoreParams)
-            .and_then(|value| Channel::try_from(value))
-            .map(|channel| PartMsg { channel })
+        let mut value = value.into_iter().skip(1);
+        let channels = value.next().ok_or(ErrorType::NeedMoreParams)?;
+
+        let mut message = PartMsg {
+            channels: Vec::new(),
+            message: value.next(),
+        };
+        for channel in channels.split(',').filter(|channel| !channel.is_empty()) {
+            let channel = Channel::try_from(channel.to_string())?;
+            if !message.channels.contains(&channel) {
+                message.channels.push(channel);
+            }
+        }
+
+        if message.channels.is_empty() {
+            return Err(ErrorType::NeedMoreParams);
+        }
+        Ok(message)
     }
 }
 
-/// A message to register a new user.
-// For example: `USER ignored ignored ignored :Thomas Kunc\r\n`
+/// A message to remove someone from a channel, with an optional reason.
+/// For example: `KICK #channel nick :Behave\r\n`
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct UserMsg {
-    pub real_name: String,
+pub struct KickMsg {
+    pub channel: Channel,
+    pub target: Nick,
+    pub reason: Option<String>,
 }
 
-impl TryFrom<Vec<String>> for UserMsg {
+impl TryFrom<Vec<String>> for KickMsg {
     type Error = ErrorType;
 
     fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
-        value
-            .into_iter()
-            .nth(4)
-            .ok_or(ErrorType::NeedMoreParams)
-            .map(|real_name| UserMsg { real_name })
+        let mut value = value.into_iter().skip(1);
+        Ok(KickMsg {
+            channel: value
+                .next()
+                .ok_or(ErrorType::NeedMoreParams)
+                .and_then(Channel::try_from)?,
+            target: value
+                .next()
+                .filter(|target| !target.is_empty())
+                .map(Nick)
+                .ok_or(ErrorType::NeedMoreParams)?,
+            reason: value.next(),
+        })
     }
 }
 
-/// A private message.
-/// For example: `PRIVMSG tom :Hi Tom, how are you?\r\n`
+/// A message to make someone leave a channel as if they had parted, with an optional reason.
+/// Unlike a KICK, clients don't rejoin automatically.
+/// For example: `REMOVE #channel nick :Behave\r\n`
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct PrivMsg {
-    pub target: Target,
-    pub message: String,
+pub struct RemoveMsg {
+    pub channel: Channel,
+    pub target: Nick,
+    pub reason: Option<String>,
 }
 
-impl TryFrom<Vec<String>> for PrivMsg {
+impl TryFrom<Vec<String>> for RemoveMsg {
     type Error = ErrorType;
 
     fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
-        Ok(PrivMsg {
-            target: Target::from(value.get(1).ok_or(ErrorType::NoRecipient)?.to_string()),
-            // skip(2) here skips the PRIVMSG instruction and target.
-            message: value.into_iter().skip(2).last().ok_or(ErrorType::NoTextToSend)?,
+        KickMsg::try_from(value).map(|kick| RemoveMsg {
+            channel: kick.channel,
+            target: kick.target,
+            reason: kick.reason,
         })
     }
 }
 
-/// The last message a user will send before leaving.
-/// For example: `QUIT :Leaving now!`
+/// A message from a server operator to disconnect someone, with a reason.
+/// For example: `KILL nick :Flooding\r\n`
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct QuitMsg {
-    pub message: Option<String>,
+pub struct KillMsg {
+    pub target: Nick,
+    pub reason: Option<String>,
 }
 
-impl TryFrom<Vec<String>> for QuitMsg {
+impl TryFrom<Vec<String>> for KillMsg {
     type Error = ErrorType;
 
     fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
-        Ok(QuitMsg {
-            // skip(1) here skips the QUIT instruction.
-            message: value.into_iter().skip(1).last(),
+        let mut value = value.into_iter().skip(1);
+        Ok(KillMsg {
+            target: value
+                .next()
+                .filter(|target| !target.is_empty())
+                .map(Nick)
+                .ok_or(ErrorType::NeedMoreParams)?,
+            reason: value.next().filter(|reason| !reason.is_empty()),
         })
     }
 }
 
-/// A list of every possible message that can be sent.
+/// A message to invite someone to a channel.
+/// For example: `INVITE nick #channel\r\n`
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Message {
-    Nick(NickMsg),
-    User(UserMsg),
-    PrivMsg(PrivMsg),
-    Ping(String),
-    Join(JoinMsg),
-    Part(PartMsg),
-    Quit(QuitMsg),
+pub struct InviteMsg {
+    pub target: Nick,
+    pub channel: Channel,
 }
 
-/// To parse a message, construct this struct.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct UnparsedMessage<'a> {
-    pub sender_nick: Nick,
-    pub message: &'a str,
+impl TryFrom<Vec<String>> for InviteMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let mut value = value.into_iter().skip(1);
+        Ok(InviteMsg {
+            target: Nick(value.next().ok_or(ErrorType::NeedMoreParams)?),
+            channel: value
+                .next()
+                .ok_or(ErrorType::NeedMoreParams)
+                .and_then(Channel::try_from)?,
+        })
+    }
 }
 
-/// After parsing an `UnparsedMessage`, this struct will be created.
+/// A message to view or set a channel's topic.
+/// For example: `TOPIC #channel :New topic\r\n`
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ParsedMessage {
-    pub sender_nick: Nick,
-    pub message: Message,
+pub struct TopicMsg {
+    pub channel: Channel,
+    pub topic: Option<String>,
 }
 
-impl<'a> TryFrom<UnparsedMessage<'a>> for ParsedMessage {
+impl TryFrom<Vec<String>> for TopicMsg {
     type Error = ErrorType;
-    fn try_from(value: UnparsedMessage<'a>) -> Result<Self, Self::Error> {
-        let command = split_command(value.message)
-            .into_iter()
-            .map(str::to_string)
-            .collect::<Vec<_>>();
-
-        let message = match command[0].as_str() {
-            "PING" => Ok(Message::Ping(
-                // Skip here ignores the "PING".
-                command.iter().skip(1).last().ok_or(ErrorType::NoOrigin)?.to_string(),
-            )),
-            "PRIVMSG" => Ok(Message::PrivMsg(PrivMsg::try_from(command)?)),
-            "USER" => Ok(Message::User(UserMsg::try_from(command)?)),
-            "NICK" => Ok(Message::Nick(NickMsg::try_from(command)?)),
-            "JOIN" => Ok(Message::Join(JoinMsg::try_from(command)?)),
-            "PART" => Ok(Message::Part(PartMsg::try_from(command)?)),
-            "QUIT" => Ok(Message::Quit(QuitMsg::try_from(command)?)),
-            _ => Err(ErrorType::UnknownCommand),
-        }?;
 
-        Ok(ParsedMessage {
-            sender_nick: value.sender_nick,
-            message,
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let mut value = value.into_iter().skip(1);
+        Ok(TopicMsg {
+            channel: value
+                .next()
+                .ok_or(ErrorType::NeedMoreParams)
+                .and_then(Channel::try_from)?,
+            topic: value.next(),
         })
     }
 }
 
+/// A message to view or change a channel's modes.
+/// For example: `MODE #channel +o nick\r\n`, or `MODE #channel\r\n` to view them.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct PrivReply {
-    pub message: PrivMsg,
-    pub sender_nick: Nick,
+pub struct ModeMsg {
+    pub target: Target,
+    /// The changes requested, e.g. `+o-o`, or `None` to view the modes.
+    pub modes: Option<String>,
+    /// Parameters for the modes that take one, in order.
+    pub params: Vec<String>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct JoinReply {
-    pub message: JoinMsg,
-    pub sender_nick: Nick,
+impl TryFrom<Vec<String>> for ModeMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let mut value = value.into_iter().skip(1);
+        Ok(ModeMsg {
+            target: value
+                .next()
+                .filter(|target| !target.is_empty())
+                .ok_or(ErrorType::NeedMoreParams)?
+                .into(),
+            modes: value.next(),
+            params: value.collect(),
+        })
+    }
 }
 
+/// One change requested by a MODE, e.g. the `+o nick` of `MODE #channel +o nick`.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct PartReply {
-    pub message: PartMsg,
-    pub sender_nick: Nick,
+pub struct ModeChange {
+    /// Whether the mode is being set, rather than unset.
+    pub set: bool,
+    pub mode: char,
+    pub param: Option<String>,
+}
+
+impl ModeMsg {
+    /// Each change requested, paired up with its parameter if the mode takes one.
+    /// A missing parameter is left as `None` for the handler to refuse.
+    pub fn changes(&self) -> Vec<ModeChange> {
+        let mut params = self.params.iter().cloned();
+        let mut set = true;
+        let mut changes = Vec::new();
+
+        for mode in self.modes.as_deref().unwrap_or_default().chars() {
+            match mode {
+                '+' => set = true,
+                '-' => set = false,
+                mode => {
+                    let takes_param = MEMBER_STATUSES.iter().any(|(status, _)| *status == mode)
+                        || CHANNEL_MODES[0].contains(mode)
+                        || CHANNEL_MODES[1].contains(mode)
+                        || (set && CHANNEL_MODES[2].contains(mode));
+                    changes.push(ModeChange {
+                        set,
+                        mode,
+                        param: if takes_param { params.next() } else { None },
+                    });
+                }
+            }
+        }
+        changes
+    }
 }
 
+/// A message to list who is in some channels, or every channel if none are given.
+/// For example: `NAMES #channel,#other\r\n`
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct QuitReply {
-    pub message: QuitMsg,
-    pub sender_nick: Nick,
+pub struct NamesMsg {
+    pub channels: Vec<Channel>,
+}
+
+impl TryFrom<Vec<String>> for NamesMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let channels = value.into_iter().nth(1).unwrap_or_default();
+        let channels = channels
+            .split(',')
+            .filter(|channel| !channel.is_empty())
+            .map(|channel| Channel::try_from(channel.to_string()))
+            .collect::<Result<_, _>>()?;
+        Ok(NamesMsg { channels })
+    }
 }
 
+/// A message to list channels with their user counts and topics.
+/// For example: `LIST #channel,#other\r\n`, or `LIST\r\n` for every channel.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct WelcomeReply {
-    pub target_nick: Nick,
-    pub message: String,
+pub struct ListMsg {
+    pub channels: Option<Vec<Channel>>,
 }
 
-/// Every possible reply to a message.
+impl TryFrom<Vec<String>> for ListMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let channels = value
+            .into_iter()
+            .nth(1)
+            .filter(|channels| !channels.is_empty())
+            .map(|channels| {
+                channels
+                    .split(',')
+                    .filter(|channel| !channel.is_empty())
+                    .map(|channel| Channel::try_from(channel.to_string()))
+                    .collect::<Result<_, _>>()
+            })
+            .transpose()?;
+        Ok(ListMsg { channels })
+    }
+}
+
+/// A message to find out about another user.
+/// For example: `WHOIS nick\r\n`, or `WHOIS server nick\r\n` (the server is ignored).
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Reply {
-    Pong(String),
-    Welcome(WelcomeReply),
-    PrivMsg(PrivReply),
-    Join(JoinReply),
-    Part(PartReply),
-    Error(ErrorType),
-    Quit(QuitReply),
+pub struct WhoisMsg {
+    pub target: Nick,
 }
 
-impl std::fmt::Display for Reply {
-    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
-        match self {
-            Reply::Pong(p) => write!(fmt, "PONG :{p}\r\n"),
-            Reply::Welcome(r) => {
-                let nick = &r.target_nick;
-                let message = &r.message;
-                write!(fmt, ":{SERVER_NAME} 001 {nick} :{message}\r\n")
-            }
-            Reply::PrivMsg(r) => {
-                let nick = &r.message.target;
-                let message = &r.message.message;
-                let from = &r.sender_nick;
+impl TryFrom<Vec<String>> for WhoisMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        value
+            .into_iter()
+            .skip(1)
+            .last()
+            .ok_or(ErrorType::NoNickNameGiven)
+            .and_then(Nick::try_from)
+            .map(|target| WhoisMsg { target })
+    }
+}
+
+/// A message to list users matching a mask, typically a channel's members.
+/// For example: `WHO #channel\r\n`, or `WHO a*\r\n` for users whose nicknames start with `a`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhoMsg {
+    pub mask: String,
+}
+
+impl TryFrom<Vec<String>> for WhoMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        // no mask, or `0`, means everyone
+        let mask = value
+            .into_iter()
+            .nth(1)
+            .filter(|mask| !mask.is_empty() && mask != "0")
+            .unwrap_or_else(|| "*".to_string());
+        Ok(WhoMsg { mask })
+    }
+}
+
+/// A message to mark yourself as away, or back if there is no message.
+/// For example: `AWAY :Gone to lunch\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwayMsg {
+    pub message: Option<String>,
+}
+
+impl TryFrom<Vec<String>> for AwayMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let message = value
+            .into_iter()
+            .nth(1)
+            .filter(|message| !message.is_empty());
+        Ok(AwayMsg { message })
+    }
+}
+
+/// A request for server operator status.
+/// For example: `OPER alice hunter2\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperMsg {
+    pub name: String,
+    pub password: String,
+}
+
+impl TryFrom<Vec<String>> for OperMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let mut value = value.into_iter().skip(1);
+        value
+            .next()
+            .zip(value.next())
+            .map(|(name, password)| OperMsg { name, password })
+            .ok_or(ErrorType::NeedMoreParams)
+    }
+}
+
+/// A message to check which of some nicknames are online.
+/// For example: `ISON alice bob carol\r\n`, or `ISON :alice bob carol\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsonMsg {
+    pub nicks: Vec<Nick>,
+}
+
+impl TryFrom<Vec<String>> for IsonMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let nicks = value
+            .iter()
+            .skip(1)
+            .flat_map(|nicks| nicks.split_whitespace())
+            .map(|nick| Nick(nick.to_string()))
+            .collect();
+        Ok(IsonMsg { nicks })
+    }
+}
+
+/// A message to look up the user and host of up to five nicknames.
+/// For example: `USERHOST alice bob\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserhostMsg {
+    pub nicks: Vec<Nick>,
+}
+
+impl TryFrom<Vec<String>> for UserhostMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        // any more than five are ignored
+        let nicks = value
+            .iter()
+            .skip(1)
+            .flat_map(|nicks| nicks.split_whitespace())
+            .take(5)
+            .map(|nick| Nick(nick.to_string()))
+            .collect::<Vec<_>>();
+        if nicks.is_empty() {
+            return Err(ErrorType::NeedMoreParams);
+        }
+        Ok(UserhostMsg { nicks })
+    }
+}
+
+/// An operator's message to look up the user and IP address of up to five nicknames.
+/// For example: `USERIP alice\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UseripMsg {
+    pub nicks: Vec<Nick>,
+}
+
+impl TryFrom<Vec<String>> for UseripMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        UserhostMsg::try_from(value).map(|UserhostMsg { nicks }| UseripMsg { nicks })
+    }
+}
+
+/// A message from a server operator to everyone watching for them (user mode `+w`).
+/// For example: `WALLOPS :Restarting in 5 minutes\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WallopsMsg {
+    pub text: String,
+}
+
+impl TryFrom<Vec<String>> for WallopsMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        value
+            .into_iter()
+            .skip(1)
+            .last()
+            .filter(|text| !text.is_empty())
+            .map(|text| WallopsMsg { text })
+            .ok_or(ErrorType::NeedMoreParams)
+    }
+}
+
+/// A request from a server operator for diagnostics about the running server.
+/// For example: `DEBUG STATE\r\n` or `DEBUG LOGLEVEL trace\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugMsg {
+    pub subject: String,
+    pub argument: Option<String>,
+}
+
+impl TryFrom<Vec<String>> for DebugMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let mut value = value.into_iter().skip(1);
+        value
+            .next()
+            .map(|subject| DebugMsg {
+                subject: subject.to_ascii_uppercase(),
+                argument: value.next(),
+            })
+            .ok_or(ErrorType::NeedMoreParams)
+    }
+}
+
+/// The connection password, sent before registering.
+/// For example: `PASS secret\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PassMsg {
+    pub password: String,
+}
+
+impl TryFrom<Vec<String>> for PassMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        value
+            .into_iter()
+            .nth(1)
+            .filter(|password| !password.is_empty())
+            .map(|password| PassMsg { password })
+            .ok_or(ErrorType::NeedMoreParams)
+    }
+}
+
+/// Capability negotiation, which may happen before or after registering.
+/// For example: `CAP LS 302\r\n` or `CAP REQ :multi-prefix\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapMsg {
+    pub subcommand: String,
+    /// The capabilities asked for by REQ, each disabled rather than enabled if it starts with `-`.
+    pub caps: Vec<String>,
+}
+
+impl TryFrom<Vec<String>> for CapMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        let mut value = value.into_iter().skip(1);
+        let subcommand = value
+            .next()
+            .ok_or(ErrorType::NeedMoreParams)?
+            .to_ascii_uppercase();
+        let caps = value
+            .next()
+            .map(|caps| caps.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default();
+        Ok(CapMsg { subcommand, caps })
+    }
+}
+
+/// A message to register a new user.
+// For example: `USER tfpk ignored ignored :Thomas Kunc\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserMsg {
+    pub username: String,
+    pub real_name: String,
+}
+
+impl TryFrom<Vec<String>> for UserMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        // the hostname and servername in between are ignored, as is anything after the real name
+        let mut value = value.into_iter().skip(1);
+        let username = value.next().filter(|username| !username.is_empty());
+        let real_name = value.nth(2);
+
+        username
+            .zip(real_name)
+            .map(|(username, real_name)| UserMsg {
+                username,
+                real_name,
+            })
+            .ok_or(ErrorType::NeedMoreParams)
+    }
+}
+
+/// A private message.
+/// For example: `PRIVMSG tom :Hi Tom, how are you?\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivMsg {
+    pub target: Target,
+    pub message: String,
+}
+
+impl TryFrom<Vec<String>> for PrivMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        Ok(PrivMsg {
+            target: Target::from(
+                value
+                    .get(1)
+                    .filter(|target| !target.is_empty())
+                    .ok_or(ErrorType::NoRecipient)?
+                    .to_string(),
+            ),
+            // skip(2) here skips the PRIVMSG instruction and target.
+            message: value
+                .into_iter()
+                .skip(2)
+                .last()
+                .filter(|message| !message.is_empty())
+                .ok_or(ErrorType::NoTextToSend)?,
+        })
+    }
+}
+
+/// A notice, which unlike a private message never gets an automatic reply.
+/// For example: `NOTICE tom :Build finished\r\n`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoticeMsg {
+    pub target: Target,
+    pub message: String,
+}
+
+impl TryFrom<Vec<String>> for NoticeMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        // skip(1) here skips the NOTICE instruction
+        let mut value = value.into_iter().skip(1);
+        Ok(NoticeMsg {
+            target: value
+                .next()
+                .filter(|target| !target.is_empty())
+                .ok_or(ErrorType::NoRecipient)?
+                .into(),
+            message: value
+                .last()
+                .filter(|message| !message.is_empty())
+                .ok_or(ErrorType::NoTextToSend)?,
+        })
+    }
+}
+
+/// The last message a user will send before leaving.
+/// For example: `QUIT :Leaving now!`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuitMsg {
+    pub message: Option<String>,
+}
+
+impl TryFrom<Vec<String>> for QuitMsg {
+    type Error = ErrorType;
+
+    fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
+        Ok(QuitMsg {
+            // skip(1) here skips the QUIT instruction.
+            message: value.into_iter().skip(1).last(),
+        })
+    }
+}
+
+/// A list of every possible message that can be sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Nick(NickMsg),
+    User(UserMsg),
+    PrivMsg(PrivMsg),
+    Notice(NoticeMsg),
+    Ping(String),
+    Pong(String),
+    Join(JoinMsg),
+    Part(PartMsg),
+    Quit(QuitMsg),
+    Topic(TopicMsg),
+    Names(NamesMsg),
+    List(ListMsg),
+    Whois(WhoisMsg),
+    Who(WhoMsg),
+    Away(AwayMsg),
+    Motd,
+    Mode(ModeMsg),
+    Kick(KickMsg),
+    Invite(InviteMsg),
+    Pass(PassMsg),
+    Oper(OperMsg),
+    Kill(KillMsg),
+    Ison(IsonMsg),
+    Userhost(UserhostMsg),
+    Wallops(WallopsMsg),
+    Debug(DebugMsg),
+    Remove(RemoveMsg),
+    Userip(UseripMsg),
+    Cap(CapMsg),
+}
+
+/// The command word of each kind of message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Command {
+    Nick,
+    User,
+    PrivMsg,
+    Notice,
+    Ping,
+    Pong,
+    Join,
+    Part,
+    Quit,
+    Topic,
+    Names,
+    List,
+    Whois,
+    Who,
+    Away,
+    Motd,
+    Mode,
+    Kick,
+    Invite,
+    Pass,
+    Oper,
+    Kill,
+    Ison,
+    Userhost,
+    Wallops,
+    Debug,
+    Remove,
+    Userip,
+    Cap,
+}
+
+impl Command {
+    pub const ALL: [Command; 29] = [
+        Command::Nick,
+        Command::User,
+        Command::PrivMsg,
+        Command::Notice,
+        Command::Ping,
+        Command::Pong,
+        Command::Join,
+        Command::Part,
+        Command::Quit,
+        Command::Topic,
+        Command::Names,
+        Command::List,
+        Command::Whois,
+        Command::Who,
+        Command::Away,
+        Command::Motd,
+        Command::Mode,
+        Command::Kick,
+        Command::Invite,
+        Command::Pass,
+        Command::Oper,
+        Command::Kill,
+        Command::Ison,
+        Command::Userhost,
+        Command::Wallops,
+        Command::Debug,
+        Command::Remove,
+        Command::Userip,
+        Command::Cap,
+    ];
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let name = match self {
+            Command::Nick => "NICK",
+            Command::User => "USER",
+            Command::PrivMsg => "PRIVMSG",
+            Command::Notice => "NOTICE",
+            Command::Ping => "PING",
+            Command::Pong => "PONG",
+            Command::Join => "JOIN",
+            Command::Part => "PART",
+            Command::Quit => "QUIT",
+            Command::Topic => "TOPIC",
+            Command::Names => "NAMES",
+            Command::List => "LIST",
+            Command::Whois => "WHOIS",
+            Command::Who => "WHO",
+            Command::Away => "AWAY",
+            Command::Motd => "MOTD",
+            Command::Mode => "MODE",
+            Command::Kick => "KICK",
+            Command::Invite => "INVITE",
+            Command::Pass => "PASS",
+            Command::Oper => "OPER",
+            Command::Kill => "KILL",
+            Command::Ison => "ISON",
+            Command::Userhost => "USERHOST",
+            Command::Wallops => "WALLOPS",
+            Command::Debug => "DEBUG",
+            Command::Remove => "REMOVE",
+            Command::Userip => "USERIP",
+            Command::Cap => "CAP",
+        };
+        write!(fmt, "{name}")
+    }
+}
+
+impl Message {
+    pub fn command(&self) -> Command {
+        match self {
+            Message::Nick(_) => Command::Nick,
+            Message::User(_) => Command::User,
+            Message::PrivMsg(_) => Command::PrivMsg,
+            Message::Notice(_) => Command::Notice,
+            Message::Ping(_) => Command::Ping,
+            Message::Pong(_) => Command::Pong,
+            Message::Join(_) => Command::Join,
+            Message::Part(_) => Command::Part,
+            Message::Quit(_) => Command::Quit,
+            Message::Topic(_) => Command::Topic,
+            Message::Names(_) => Command::Names,
+            Message::List(_) => Command::List,
+            Message::Whois(_) => Command::Whois,
+            Message::Who(_) => Command::Who,
+            Message::Away(_) => Command::Away,
+            Message::Motd => Command::Motd,
+            Message::Mode(_) => Command::Mode,
+            Message::Kick(_) => Command::Kick,
+            Message::Invite(_) => Command::Invite,
+            Message::Pass(_) => Command::Pass,
+            Message::Oper(_) => Command::Oper,
+            Message::Kill(_) => Command::Kill,
+            Message::Ison(_) => Command::Ison,
+            Message::Userhost(_) => Command::Userhost,
+            Message::Wallops(_) => Command::Wallops,
+            Message::Debug(_) => Command::Debug,
+            Message::Remove(_) => Command::Remove,
+            Message::Userip(_) => Command::Userip,
+            Message::Cap(_) => Command::Cap,
+        }
+    }
+}
+
+/// To parse a message, construct this struct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnparsedMessage<'a> {
+    pub sender_nick: Nick,
+    pub message: &'a str,
+}
+
+/// After parsing an `UnparsedMessage`, this struct will be created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedMessage {
+    pub sender_nick: Nick,
+    pub message: Message,
+}
+
+impl<'a> TryFrom<UnparsedMessage<'a>> for ParsedMessage {
+    type Error = ErrorType;
+    fn try_from(value: UnparsedMessage<'a>) -> Result<Self, Self::Error> {
+        let command = split_command(value.message);
+
+        // refuse absurd parameter lists before copying anything;
+        // only middle parameters list targets, the trailing one is free text
+        let middle = value
+            .message
+            .split_once(" :")
+            .map_or(value.message, |(middle, _)| middle);
+        if command.len() > MAX_PARAMS + 1
+            || middle
+                .split(' ')
+                .any(|param| param.matches(',').count() >= MAX_TARGETS)
+        {
+            return Err(ErrorType::InputTooLong);
+        }
+
+        let command = command.into_iter().map(str::to_string).collect::<Vec<_>>();
+
+        // commands are case-insensitive, their parameters are not
+        let verb = command
+            .first()
+            .map(|verb| verb.to_ascii_uppercase())
+            .unwrap_or_default();
+
+        let message = match verb.as_str() {
+            "PING" => Ok(Message::Ping(
+                // Skip here ignores the "PING".
+                command.iter().skip(1).last().ok_or(ErrorType::NoOrigin)?.to_string(),
+            )),
+            "PONG" => Ok(Message::Pong(
+                command.into_iter().skip(1).last().ok_or(ErrorType::NoOrigin)?,
+            )),
+            "PRIVMSG" => Ok(Message::PrivMsg(PrivMsg::try_from(command)?)),
+            "NOTICE" => Ok(Message::Notice(NoticeMsg::try_from(command)?)),
+            "USER" => Ok(Message::User(UserMsg::try_from(command)?)),
+            "NICK" => Ok(Message::Nick(NickMsg::try_from(command)?)),
+            "JOIN" => Ok(Message::Join(JoinMsg::try_from(command)?)),
+            "PART" => Ok(Message::Part(PartMsg::try_from(command)?)),
+            "QUIT" => Ok(Message::Quit(QuitMsg::try_from(command)?)),
+            "TOPIC" => Ok(Message::Topic(TopicMsg::try_from(command)?)),
+            "NAMES" => Ok(Message::Names(NamesMsg::try_from(command)?)),
+            "LIST" => Ok(Message::List(ListMsg::try_from(command)?)),
+            "WHOIS" => Ok(Message::Whois(WhoisMsg::try_from(command)?)),
+            "WHO" => Ok(Message::Who(WhoMsg::try_from(command)?)),
+            "AWAY" => Ok(Message::Away(AwayMsg::try_from(command)?)),
+            "MOTD" => Ok(Message::Motd),
+            "MODE" => Ok(Message::Mode(ModeMsg::try_from(command)?)),
+            "KICK" => Ok(Message::Kick(KickMsg::try_from(command)?)),
+            "INVITE" => Ok(Message::Invite(InviteMsg::try_from(command)?)),
+            "PASS" => Ok(Message::Pass(PassMsg::try_from(command)?)),
+            "OPER" => Ok(Message::Oper(OperMsg::try_from(command)?)),
+            "KILL" => Ok(Message::Kill(KillMsg::try_from(command)?)),
+            "ISON" => Ok(Message::Ison(IsonMsg::try_from(command)?)),
+            "USERHOST" => Ok(Message::Userhost(UserhostMsg::try_from(command)?)),
+            "WALLOPS" => Ok(Message::Wallops(WallopsMsg::try_from(command)?)),
+            "DEBUG" => Ok(Message::Debug(DebugMsg::try_from(command)?)),
+            "REMOVE" => Ok(Message::Remove(RemoveMsg::try_from(command)?)),
+            "USERIP" => Ok(Message::Userip(UseripMsg::try_from(command)?)),
+            "CAP" => Ok(Message::Cap(CapMsg::try_from(command)?)),
+            _ => Err(ErrorType::UnknownCommand),
+        }?;
+
+        Ok(ParsedMessage {
+            sender_nick: value.sender_nick,
+            message,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivReply {
+    pub message: PrivMsg,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoticeReply {
+    pub message: NoticeMsg,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NickReply {
+    pub message: NickMsg,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinReply {
+    pub channel: Channel,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartReply {
+    pub channel: Channel,
+    pub message: Option<String>,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuitReply {
+    pub message: QuitMsg,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KickReply {
+    pub message: KickMsg,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WallopsReply {
+    pub message: WallopsMsg,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InviteReply {
+    pub message: InviteMsg,
+    pub sender: Prefix,
+}
+
+/// Tells `target_nick` that their invite of `nick` to `channel` was sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvitingReply {
+    pub target_nick: Nick,
+    pub nick: Nick,
+    pub channel: Channel,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicReply {
+    pub message: TopicMsg,
+    pub sender: Prefix,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelTopicReply {
+    pub target_nick: Nick,
+    pub channel: Channel,
+    pub topic: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamesReply {
+    pub target_nick: Nick,
+    pub channel: Channel,
+    /// Each member's nick, after the prefix for their status if they have one.
+    pub names: Vec<String>,
+}
+
+/// The end of a NAMES listing, for one channel or (if `None`) all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndOfNamesReply {
+    pub target_nick: Nick,
+    pub channel: Option<Channel>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListReply {
+    pub target_nick: Nick,
+    pub channel: Channel,
+    pub users: usize,
+    pub topic: Option<String>,
+}
+
+/// Everything WHOIS shows about `nick`, up to but not including the end of the list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhoisReply {
+    pub target_nick: Nick,
+    pub nick: Nick,
+    pub user: String,
+    pub host: String,
+    pub real_name: String,
+    /// The channels `nick` is in, each behind `nick`'s status prefix there, if any, e.g. `@#chan`.
+    pub channels: Vec<String>,
+    pub away: Option<String>,
+    /// Whether `nick` is a server operator.
+    pub oper: bool,
+    /// Seconds since `nick` last sent a PRIVMSG.
+    pub idle: u64,
+    /// When `nick` registered, in seconds since the Unix epoch.
+    pub signon: u64,
+    /// Where `nick` is really connected from, only shown to server operators.
+    pub actually: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndOfWhoisReply {
+    pub target_nick: Nick,
+    pub nick: Nick,
+}
+
+/// Which of the nicknames asked about with ISON are online.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsonReply {
+    pub target_nick: Nick,
+    pub nicks: Vec<Nick>,
+}
+
+/// One user found by USERHOST.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserhostEntry {
+    pub nick: Nick,
+    pub user: String,
+    pub host: String,
+    pub oper: bool,
+    pub away: bool,
+}
+
+/// The users found by USERHOST, written as `nick[*]=+user@host`,
+/// where `*` marks an operator and `-` rather than `+` someone away.
+/// USERIP answers the same way, with the IP address as the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserhostReply {
+    pub target_nick: Nick,
+    pub users: Vec<UserhostEntry>,
+}
+
+impl UserhostReply {
+    fn users(&self) -> String {
+        self.users
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{}{}={}{}@{}",
+                    entry.nick,
+                    if entry.oper { "*" } else { "" },
+                    if entry.away { '-' } else { '+' },
+                    entry.user,
+                    entry.host
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// One user matching a WHO, seen through `channel` if the mask was a channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhoReply {
+    pub target_nick: Nick,
+    pub channel: Option<Channel>,
+    pub user: String,
+    pub host: String,
+    pub nick: Nick,
+    pub flags: String,
+    pub real_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndOfWhoReply {
+    pub target_nick: Nick,
+    pub mask: String,
+}
+
+/// Tells `target_nick` that `nick` is away, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwayReply {
+    pub target_nick: Nick,
+    pub nick: Nick,
+    pub message: String,
+}
+
+/// The whole message of the day, from its start to its end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MotdReply {
+    pub target_nick: Nick,
+    pub lines: Vec<String>,
+}
+
+/// An error for `target_nick` (`*` if it has no nickname yet),
+/// about `subject` (the nickname, channel or command at fault) if there is one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorReply {
+    pub target_nick: Option<Nick>,
+    pub error: ErrorType,
+    pub subject: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreatedReply {
+    pub target_nick: Nick,
+    pub created: SystemTime,
+}
+
+/// A channel's current modes, e.g. `+nt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelModeReply {
+    pub target_nick: Nick,
+    pub channel: Channel,
+    pub modes: String,
+}
+
+/// A user's own modes, e.g. `+iw`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UModeIsReply {
+    pub target_nick: Nick,
+    pub modes: String,
+}
+
+/// One entry of a channel's ban or mute list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BanListReply {
+    pub target_nick: Nick,
+    pub channel: Channel,
+    pub mask: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndOfBanListReply {
+    pub target_nick: Nick,
+    pub channel: Channel,
+}
+
+/// Mode changes made to a channel or user by `sender`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModeReply {
+    /// Who made the changes, or `None` for the server itself, e.g. when a timed mute expires.
+    pub sender: Option<Prefix>,
+    pub target: Target,
+    pub changes: Vec<ModeChange>,
+}
+
+/// Features the server supports, as `NAME=value` or bare `NAME` tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ISupportReply {
+    pub target_nick: Nick,
+    pub tokens: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WelcomeReply {
+    pub target_nick: Nick,
+    pub message: String,
+}
+
+/// An answer to CAP, e.g. `CAP * ACK :multi-prefix`. Sent to `*` before registering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapReply {
+    pub target_nick: Option<Nick>,
+    pub subcommand: &'static str,
+    pub caps: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerNoticeReply {
+    pub target_nick: Nick,
+    pub message: String,
+}
+
+/// Every possible reply to a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    Ping(String),
+    Pong(String),
+    Welcome(WelcomeReply),
+    YourHost(Nick),
+    Created(CreatedReply),
+    MyInfo(Nick),
+    ISupport(ISupportReply),
+    PrivMsg(PrivReply),
+    Notice(NoticeReply),
+    Nick(NickReply),
+    Join(JoinReply),
+    Part(PartReply),
+    Error(ErrorReply),
+    Quit(QuitReply),
+    Kick(KickReply),
+    Wallops(WallopsReply),
+    Invite(InviteReply),
+    Inviting(InvitingReply),
+    Topic(TopicReply),
+    ChannelTopic(ChannelTopicReply),
+    Names(NamesReply),
+    EndOfNames(EndOfNamesReply),
+    ListStart(Nick),
+    List(ListReply),
+    ListEnd(Nick),
+    Whois(WhoisReply),
+    EndOfWhois(EndOfWhoisReply),
+    Ison(IsonReply),
+    Userhost(UserhostReply),
+    Userip(UserhostReply),
+    Who(WhoReply),
+    EndOfWho(EndOfWhoReply),
+    Away(AwayReply),
+    NowAway(Nick),
+    UnAway(Nick),
+    Motd(MotdReply),
+    ChannelModeIs(ChannelModeReply),
+    YoureOper(Nick),
+    UModeIs(UModeIsReply),
+    BanList(BanListReply),
+    EndOfBanList(EndOfBanListReply),
+    MuteList(BanListReply),
+    EndOfMuteList(EndOfBanListReply),
+    Mode(ModeReply),
+    Disconnect(String),
+    ServerNotice(ServerNoticeReply),
+    Cap(CapReply),
+}
+
+impl Reply {
+    /// The reply as sent by the server called `server_name`, ready to be written out.
+    pub fn format(&self, server_name: &str) -> String {
+        FromServer {
+            server_name,
+            reply: self,
+        }
+        .to_string()
+    }
+}
+
+/// A `Reply` along with the name of the server sending it.
+struct FromServer<'a> {
+    server_name: &'a str,
+    reply: &'a Reply,
+}
+
+impl std::fmt::Display for FromServer<'_> {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let server_name = self.server_name;
+        match self.reply {
+            Reply::Ping(token) => write!(fmt, "PING :{token}\r\n"),
+            Reply::Pong(p) => write!(fmt, ":{server_name} PONG {server_name} :{p}\r\n"),
+            Reply::Welcome(r) => {
+                let nick = &r.target_nick;
+                let message = &r.message;
+                write!(fmt, ":{server_name} 001 {nick} :{message}\r\n")
+            }
+            Reply::YourHost(nick) => write!(
+                fmt,
+                ":{server_name} 002 {nick} :Your host is {server_name}, running version {VERSION}\r\n"
+            ),
+            Reply::Created(r) => {
+                let nick = &r.target_nick;
+                let created = format_utc(r.created);
+                write!(
+                    fmt,
+                    ":{server_name} 003 {nick} :This server was created {created}\r\n"
+                )
+            }
+            Reply::MyInfo(nick) => {
+                let channel_modes = channel_mode_letters();
+                write!(
+                    fmt,
+                    ":{server_name} 004 {nick} {server_name} {VERSION} {USER_MODES} {channel_modes}\r\n"
+                )
+            }
+            Reply::ISupport(r) => {
+                let nick = &r.target_nick;
+                write_packed_lines(
+                    fmt,
+                    &format!(":{server_name} 005 {nick} "),
+                    r.tokens.iter().map(String::as_str),
+                    " :are supported by this server",
+                )
+            }
+            Reply::PrivMsg(r) => {
+                let nick = &r.message.target;
+                let message = &r.message.message;
+                let from = &r.sender;
                 write!(fmt, ":{from} PRIVMSG {nick} :{message}\r\n")
             }
-            Reply::Error(e) => {
-                write!(fmt, ":{SERVER_NAME} {e}\r\n")
+            Reply::Notice(r) => {
+                let target = &r.message.target;
+                let message = &r.message.message;
+                let from = &r.sender;
+                write!(fmt, ":{from} NOTICE {target} :{message}\r\n")
+            }
+            Reply::Error(r) => {
+                let code = r.error.code();
+                let nick = r.target_nick.as_ref().map_or("*", |nick| nick.0.as_str());
+                let error = &r.error;
+                match &r.subject {
+                    Some(subject) => {
+                        write!(
+                            fmt,
+                            ":{server_name} {code:03} {nick} {subject} :{error}\r\n"
+                        )
+                    }
+                    None => write!(fmt, ":{server_name} {code:03} {nick} :{error}\r\n"),
+                }
+            }
+            Reply::Nick(r) => {
+                let sender = &r.sender;
+                let nick = &r.message.nick;
+                write!(fmt, ":{sender} NICK {nick}\r\n")
+            }
+            Reply::Join(r) => {
+                let sender = &r.sender;
+                let channel = &r.channel;
+                write!(fmt, ":{sender} JOIN {channel}\r\n")
+            }
+            Reply::ChannelModeIs(r) => {
+                let nick = &r.target_nick;
+                let channel = &r.channel;
+                let modes = &r.modes;
+                write!(fmt, ":{server_name} 324 {nick} {channel} {modes}\r\n")
+            }
+            Reply::UModeIs(r) => {
+                let nick = &r.target_nick;
+                let modes = &r.modes;
+                write!(fmt, ":{server_name} 221 {nick} {modes}\r\n")
+            }
+            Reply::YoureOper(nick) => write!(
+                fmt,
+                ":{server_name} 381 {nick} :You are now an IRC operator\r\n"
+            ),
+            Reply::BanList(r) => {
+                let nick = &r.target_nick;
+                let channel = &r.channel;
+                let mask = &r.mask;
+                write!(fmt, ":{server_name} 367 {nick} {channel} {mask}\r\n")
+            }
+            Reply::EndOfBanList(r) => {
+                let nick = &r.target_nick;
+                let channel = &r.channel;
+                write!(
+                    fmt,
+                    ":{server_name} 368 {nick} {channel} :End of channel ban list\r\n"
+                )
+            }
+            Reply::MuteList(r) => {
+                let nick = &r.target_nick;
+                let channel = &r.channel;
+                let mask = &r.mask;
+                write!(fmt, ":{server_name} 728 {nick} {channel} q {mask}\r\n")
+            }
+            Reply::EndOfMuteList(r) => {
+                let nick = &r.target_nick;
+                let channel = &r.channel;
+                write!(
+                    fmt,
+                    ":{server_name} 729 {nick} {channel} q :End of channel mute list\r\n"
+                )
+            }
+            Reply::Mode(r) => {
+                let sender = match &r.sender {
+                    Some(sender) => sender.to_string(),
+                    None => server_name.to_string(),
+                };
+                let target = &r.target;
+                // consecutive changes in the same direction share a sign, e.g. `+oo-o`
+                let mut modes = String::new();
+                let mut set = None;
+                for change in &r.changes {
+                    if set != Some(change.set) {
+                        modes.push(if change.set { '+' } else { '-' });
+                        set = Some(change.set);
+                    }
+                    modes.push(change.mode);
+                }
+                write!(fmt, ":{sender} MODE {target} {modes}")?;
+                for param in r.changes.iter().filter_map(|change| change.param.as_ref()) {
+                    write!(fmt, " {param}")?;
+                }
+                write!(fmt, "\r\n")
+            }
+            Reply::Part(r) => {
+                let sender = &r.sender;
+                let channel = &r.channel;
+                match &r.message {
+                    Some(message) => write!(fmt, ":{sender} PART {channel} :{message}\r\n"),
+                    None => write!(fmt, ":{sender} PART {channel}\r\n"),
+                }
+            }
+            Reply::Quit(r) => {
+                let sender = &r.sender;
+                let nick = &sender.nick.to_string();
+                let message = &r.message.message.as_ref().unwrap_or(nick);
+                write!(fmt, ":{sender} QUIT :{message}\r\n")
+            }
+            Reply::Wallops(r) => {
+                let sender = &r.sender;
+                let text = &r.message.text;
+                write!(fmt, ":{sender} WALLOPS :{text}\r\n")
+            }
+            Reply::Kick(r) => {
+                let sender = &r.sender;
+                let channel = &r.message.channel;
+                let target = &r.message.target;
+                // without a reason, the kicker's nick stands in for one
+                let reason = r
+                    .message
+                    .reason
+                    .clone()
+                    .unwrap_or_else(|| sender.nick.to_string());
+                write!(fmt, ":{sender} KICK {channel} {target} :{reason}\r\n")
+            }
+            Reply::Invite(r) => {
+                let sender = &r.sender;
+                let target = &r.message.target;
+                let channel = &r.message.channel;
+                write!(fmt, ":{sender} INVITE {target} {channel}\r\n")
+            }
+            Reply::Inviting(r) => {
+                let target = &r.target_nick;
+                let nick = &r.nick;
+                let channel = &r.channel;
+                write!(fmt, ":{server_name} 341 {target} {nick} {channel}\r\n")
+            }
+            Reply::Topic(r) => {
+                let sender = &r.sender;
+                let channel = &r.message.channel;
+                let topic = r.message.topic.as_deref().unwrap_or_default();
+                write!(fmt, ":{sender} TOPIC {channel} :{topic}\r\n")
+            }
+            Reply::ChannelTopic(r) => {
+                let nick = &r.target_nick;
+                let channel = &r.channel;
+                match &r.topic {
+                    Some(topic) => write!(fmt, ":{server_name} 332 {nick} {channel} :{topic}\r\n"),
+                    None => write!(
+                        fmt,
+                        ":{server_name} 331 {nick} {channel} :No topic is set\r\n"
+                    ),
+                }
             }
-            Reply::Join(r) => {
-                let sender = &r.sender_nick;
-                let channel = &r.message.channel;
-                write!(fmt, ":{sender} JOIN {channel}\r\n")
+            Reply::Names(r) => {
+                let nick = &r.target_nick;
+                let channel = &r.channel;
+                write_packed_lines(
+                    fmt,
+                    &format!(":{server_name} 353 {nick} = {channel} :"),
+                    r.names.iter().map(String::as_str),
+                    "",
+                )
             }
-            Reply::Part(r) => {
-                let sender = &r.sender_nick;
-                let channel = &r.message.channel;
-                write!(fmt, ":{sender} PART {channel}\r\n")
+            Reply::EndOfNames(r) => {
+                let nick = &r.target_nick;
+                let channel = r.channel.as_ref().map_or("*", |channel| channel.0.as_str());
+                write!(
+                    fmt,
+                    ":{server_name} 366 {nick} {channel} :End of /NAMES list\r\n"
+                )
             }
-            Reply::Quit(r) => {
-                let sender = &r.sender_nick.to_string();
-                let message = &r.message.message.as_ref().unwrap_or(sender);
-                write!(fmt, ":{sender} QUIT :{message}\r\n")
+            Reply::ListStart(nick) => {
+                write!(fmt, ":{server_name} 321 {nick} Channel :Users  Name\r\n")
+            }
+            Reply::List(r) => {
+                let nick = &r.target_nick;
+                let channel = &r.channel;
+                let users = r.users;
+                let topic = r.topic.as_deref().unwrap_or_default();
+                write!(
+                    fmt,
+                    ":{server_name} 322 {nick} {channel} {users} :{topic}\r\n"
+                )
+            }
+            Reply::ListEnd(nick) => write!(fmt, ":{server_name} 323 {nick} :End of /LIST\r\n"),
+            Reply::Whois(r) => {
+                let target = &r.target_nick;
+                let nick = &r.nick;
+                let user = &r.user;
+                let host = &r.host;
+                let real_name = &r.real_name;
+                write!(
+                    fmt,
+                    ":{server_name} 311 {target} {nick} {user} {host} * :{real_name}\r\n"
+                )?;
+                if !r.channels.is_empty() {
+                    write_packed_lines(
+                        fmt,
+                        &format!(":{server_name} 319 {target} {nick} :"),
+                        r.channels.iter().map(String::as_str),
+                        "",
+                    )?;
+                }
+                write!(
+                    fmt,
+                    ":{server_name} 312 {target} {nick} {server_name} :Iris IRC server\r\n"
+                )?;
+                if let Some(message) = &r.away {
+                    write!(fmt, ":{server_name} 301 {target} {nick} :{message}\r\n")?;
+                }
+                let idle = r.idle;
+                let signon = r.signon;
+                write!(
+                    fmt,
+                    ":{server_name} 317 {target} {nick} {idle} {signon} :seconds idle, signon time\r\n"
+                )?;
+                if r.oper {
+                    write!(
+                        fmt,
+                        ":{server_name} 313 {target} {nick} :is an IRC operator\r\n"
+                    )?;
+                }
+                if let Some(addr) = &r.actually {
+                    write!(
+                        fmt,
+                        ":{server_name} 338 {target} {nick} {addr} :is actually using host\r\n"
+                    )?;
+                }
+                Ok(())
+            }
+            Reply::Ison(r) => {
+                let target = &r.target_nick;
+                write_packed_lines(
+                    fmt,
+                    &format!(":{server_name} 303 {target} :"),
+                    r.nicks.iter().map(|nick| nick.0.as_str()),
+                    "",
+                )
+            }
+            Reply::Userhost(r) => {
+                write!(fmt, ":{server_name} 302 {} :{}\r\n", r.target_nick, r.users())
+            }
+            Reply::Userip(r) => {
+                write!(fmt, ":{server_name} 340 {} :{}\r\n", r.target_nick, r.users())
             }
+            Reply::EndOfWhois(r) => {
+                let target = &r.target_nick;
+                let nick = &r.nick;
+                write!(
+                    fmt,
+                    ":{server_name} 318 {target} {nick} :End of /WHOIS list\r\n"
+                )
+            }
+            Reply::Who(r) => {
+                let target = &r.target_nick;
+                let channel = r.channel.as_ref().map_or("*", |channel| channel.0.as_str());
+                let user = &r.user;
+                let host = &r.host;
+                let nick = &r.nick;
+                let flags = &r.flags;
+                let real_name = &r.real_name;
+                write!(
+                    fmt,
+                    ":{server_name} 352 {target} {channel} {user} {host} {server_name} {nick} {flags} :0 {real_name}\r\n"
+                )
+            }
+            Reply::EndOfWho(r) => {
+                let target = &r.target_nick;
+                let mask = &r.mask;
+                write!(
+                    fmt,
+                    ":{server_name} 315 {target} {mask} :End of /WHO list\r\n"
+                )
+            }
+            Reply::Away(r) => {
+                let target = &r.target_nick;
+                let nick = &r.nick;
+                let message = &r.message;
+                write!(fmt, ":{server_name} 301 {target} {nick} :{message}\r\n")
+            }
+            Reply::NowAway(nick) => write!(
+                fmt,
+                ":{server_name} 306 {nick} :You have been marked as being away\r\n"
+            ),
+            Reply::UnAway(nick) => write!(
+                fmt,
+                ":{server_name} 305 {nick} :You are no longer marked as being away\r\n"
+            ),
+            Reply::Motd(r) => {
+                let nick = &r.target_nick;
+                write!(
+                    fmt,
+                    ":{server_name} 375 {nick} :- {server_name} Message of the day - \r\n"
+                )?;
+                for line in &r.lines {
+                    write!(fmt, ":{server_name} 372 {nick} :- {line}\r\n")?;
+                }
+                write!(fmt, ":{server_name} 376 {nick} :End of /MOTD command\r\n")
+            }
+            Reply::Disconnect(reason) => write!(fmt, "ERROR :{reason}\r\n"),
+            Reply::ServerNotice(r) => {
+                let nick = &r.target_nick;
+                let message = &r.message;
+                write!(fmt, ":{server_name} NOTICE {nick} :{message}\r\n")
+            }
+            Reply::Cap(r) => {
+                let nick = r.target_nick.as_ref().map_or("*", |nick| nick.0.as_str());
+                let subcommand = r.subcommand;
+                let caps = r.caps.join(" ");
+                write!(fmt, ":{server_name} CAP {nick} {subcommand} :{caps}\r\n")
+            }
+        }
+    }
+}
+
+/// `time` as a UTC date and time, e.g. `2024-01-31 09:05:00 UTC`.
+fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // convert days since the epoch to a Gregorian date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Write `items` as space-separated words between `head` and `tail`,
+/// starting a new line whenever one would exceed `MAX_LINE_LEN`.
+/// An item too long for a line of its own, e.g. a nickname allowed by a large
+/// `Config::max_nick_len`, is cut short rather than sent on an overlong line.
+/// At least one line is written, even if there are no items.
+fn write_packed_lines<'a>(
+    fmt: &mut std::fmt::Formatter<'_>,
+    head: &str,
+    items: impl IntoIterator<Item = &'a str>,
+    tail: &str,
+) -> Result<(), std::fmt::Error> {
+    let budget = MAX_LINE_LEN.saturating_sub(head.len() + tail.len() + "\r\n".len());
+    let mut line = String::new();
+
+    for item in items {
+        let mut end = item.len().min(budget);
+        while !item.is_char_boundary(end) {
+            end -= 1;
         }
+        let item = &item[..end];
+
+        if !line.is_empty() && line.len() + " ".len() + item.len() > budget {
+            write!(fmt, "{head}{line}{tail}\r\n")?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(item);
     }
+
+    write!(fmt, "{head}{line}{tail}\r\n")
 }
 
 mod tests {
@@ -453,12 +2243,580 @@ mod tests {
                 nick: Nick("tfpk".to_string())
             })
         );
+        // too long for the default limit, but that is checked against the configured one
         assert_eq!(
             ParsedMessage::try_from(UnparsedMessage {
                 message: "NICK tfpkasdfasdfasdf\r\n",
                 sender_nick: Nick("Person".to_string())
+            })
+            .unwrap()
+            .message,
+            Message::Nick(NickMsg {
+                nick: Nick("tfpkasdfasdfasdf".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_ping_pong_replies() {
+        assert_eq!(
+            Reply::Ping(SERVER_NAME.to_string()).format(SERVER_NAME),
+            format!("PING :{SERVER_NAME}\r\n")
+        );
+        assert_eq!(
+            Reply::Pong("host-name with space".to_string()).format(SERVER_NAME),
+            format!(":{SERVER_NAME} PONG {SERVER_NAME} :host-name with space\r\n")
+        );
+    }
+
+    #[test]
+    fn test_repeated_spaces() {
+        assert_eq!(
+            ParsedMessage::try_from(UnparsedMessage {
+                message: "JOIN    #a\r\n",
+                sender_nick: Nick("Person".to_string())
+            })
+            .unwrap()
+            .message,
+            Message::Join(JoinMsg {
+                channels: vec![Channel("#a".to_string())],
+                keys: vec![]
+            })
+        );
+        assert_eq!(
+            ParsedMessage::try_from(UnparsedMessage {
+                message: "PRIVMSG  #c   :spaced  text\r\n",
+                sender_nick: Nick("Person".to_string())
+            })
+            .unwrap()
+            .message,
+            Message::PrivMsg(PrivMsg {
+                target: Target::Channel(Channel("#c".to_string())),
+                message: "spaced  text".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_status_targets() {
+        assert_eq!(
+            Target::from("@#a".to_string()),
+            Target::ChannelStatus('@', Channel("#a".to_string()))
+        );
+        assert_eq!(
+            Target::from("+&b".to_string()),
+            Target::ChannelStatus('+', Channel("&b".to_string()))
+        );
+        assert_eq!(Target::from("@#a".to_string()).to_string(), "@#a");
+        // a status prefix before anything but a channel is just part of a nick
+        assert_eq!(
+            Target::from("+tom".to_string()),
+            Target::User(Nick("+tom".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_empty_command() {
+        for message in ["", "\r\n", ":prefix", ":prefix \r\n", "     "] {
+            assert!(is_empty_command(message));
+            assert_eq!(
+                ParsedMessage::try_from(UnparsedMessage {
+                    message,
+                    sender_nick: Nick("Person".to_string())
+                }),
+                Err(ErrorType::UnknownCommand)
+            );
+        }
+
+        assert_eq!(
+            ParsedMessage::try_from(UnparsedMessage {
+                message: ":tfpk!tfpk@host JOIN #a\r\n",
+                sender_nick: Nick("Person".to_string())
+            })
+            .unwrap()
+            .message,
+            Message::Join(JoinMsg {
+                channels: vec![Channel("#a".to_string())],
+                keys: vec![]
+            })
+        );
+    }
+
+    #[test]
+    fn test_case_insensitive_commands() {
+        let parse = |message| {
+            ParsedMessage::try_from(UnparsedMessage {
+                message,
+                sender_nick: Nick("Person".to_string()),
+            })
+            .unwrap()
+            .message
+        };
+
+        assert_eq!(
+            parse("join #x\r\n"),
+            Message::Join(JoinMsg {
+                channels: vec![Channel("#x".to_string())],
+                keys: vec![]
+            })
+        );
+        assert_eq!(
+            parse("PrIvMsg bob :hi\r\n"),
+            Message::PrivMsg(PrivMsg {
+                target: Target::User(Nick("bob".to_string())),
+                message: "hi".to_string()
+            })
+        );
+        assert_eq!(parse("quit\r\n"), Message::Quit(QuitMsg { message: None }));
+    }
+
+    #[test]
+    fn test_too_many_params() {
+        let targets = vec!["#a"; 1000].join(",");
+        assert_eq!(
+            ParsedMessage::try_from(UnparsedMessage {
+                message: &format!("JOIN {targets}\r\n"),
+                sender_nick: Nick("Person".to_string())
             }),
-            Err(ErrorType::ErroneousNickname)
+            Err(ErrorType::InputTooLong)
+        );
+
+        // commas in the trailing parameter are just text
+        let text = vec!["a"; 1000].join(",");
+        assert_eq!(
+            ParsedMessage::try_from(UnparsedMessage {
+                message: &format!("PRIVMSG #a :{text}\r\n"),
+                sender_nick: Nick("Person".to_string())
+            })
+            .map(|parsed| parsed.message),
+            Ok(Message::PrivMsg(PrivMsg {
+                target: Target::Channel(Channel("#a".to_string())),
+                message: text
+            }))
+        );
+
+        let params = vec!["x"; 100].join(" ");
+        assert_eq!(
+            ParsedMessage::try_from(UnparsedMessage {
+                message: &format!("USER {params}\r\n"),
+                sender_nick: Nick("Person".to_string())
+            }),
+            Err(ErrorType::InputTooLong)
         );
     }
+    #[test]
+    fn test_topic() {
+        let parse = |message| {
+            ParsedMessage::try_from(UnparsedMessage {
+                message,
+                sender_nick: Nick("Person".to_string()),
+            })
+        };
+
+        assert_eq!(
+            parse("TOPIC #a\r\n").unwrap().message,
+            Message::Topic(TopicMsg {
+                channel: Channel("#a".to_string()),
+                topic: None
+            })
+        );
+        assert_eq!(
+            parse("TOPIC #a :Rust and  more\r\n").unwrap().message,
+            Message::Topic(TopicMsg {
+                channel: Channel("#a".to_string()),
+                topic: Some("Rust and  more".to_string())
+            })
+        );
+        assert_eq!(parse("TOPIC\r\n"), Err(ErrorType::NeedMoreParams));
+    }
+    #[test]
+    fn test_casemapping() {
+        assert_eq!(Nick("Alice".to_string()), Nick("aLICE".to_string()));
+        assert_eq!(Nick("[a]\\~".to_string()), Nick("{a}|^".to_string()));
+        assert_ne!(Nick("alice".to_string()), Nick("alicia".to_string()));
+        assert_eq!(Channel("#Chan".to_string()), Channel("#chan".to_string()));
+
+        let nicks = std::collections::HashSet::from([Nick("Alice".to_string())]);
+        assert!(nicks.contains(&Nick("ALICE".to_string())));
+    }
+    #[test]
+    fn test_nick_validation() {
+        let parse = |message: &str| {
+            ParsedMessage::try_from(UnparsedMessage {
+                message,
+                sender_nick: Nick("Person".to_string()),
+            })
+            .map(|parsed| parsed.message)
+        };
+
+        for nick in ["tfpk", "[away]", "`x_y-z", "a|b^c{d}", "abcdefghi"] {
+            assert_eq!(
+                parse(&format!("NICK {nick}\r\n")),
+                Ok(Message::Nick(NickMsg {
+                    nick: Nick(nick.to_string())
+                }))
+            );
+        }
+        for nick in [":a b", "a,b", "a@b", "a!b", "1abc", "-abc", "é"] {
+            assert_eq!(
+                parse(&format!("NICK {nick}\r\n")),
+                Err(ErrorType::ErroneousNickname),
+                "{nick}"
+            );
+        }
+        assert_eq!(parse("NICK\r\n"), Err(ErrorType::NoNickNameGiven));
+        assert_eq!(parse("NICK :\r\n"), Err(ErrorType::NoNickNameGiven));
+    }
+    #[test]
+    fn test_user() {
+        let parse = |message| {
+            ParsedMessage::try_from(UnparsedMessage {
+                message,
+                sender_nick: Nick("Person".to_string()),
+            })
+            .map(|parsed| parsed.message)
+        };
+
+        assert_eq!(
+            parse("USER bob 0 * :Bob The Builder\r\n"),
+            Ok(Message::User(UserMsg {
+                username: "bob".to_string(),
+                real_name: "Bob The Builder".to_string()
+            }))
+        );
+        assert_eq!(
+            parse("USER bob 0 * Bob\r\n"),
+            Ok(Message::User(UserMsg {
+                username: "bob".to_string(),
+                real_name: "Bob".to_string()
+            }))
+        );
+        assert_eq!(parse("USER bob 0 *\r\n"), Err(ErrorType::NeedMoreParams));
+        assert_eq!(
+            parse("USER bob 0 * Bob The Builder\r\n"),
+            Ok(Message::User(UserMsg {
+                username: "bob".to_string(),
+                real_name: "Bob".to_string()
+            }))
+        );
+    }
+    #[test]
+    fn test_channel_validation() {
+        for channel in ["#a", "&local", "#rust-lang", "#c++", "#ünïcode"] {
+            assert_eq!(
+                Channel::try_from(channel.to_string()),
+                Ok(Channel(channel.to_string()))
+            );
+        }
+        for channel in ["#", "a", "!a", "#a b", "#a,#b", "#a:b", "#a\x07", "#a\x00b"] {
+            assert_eq!(
+                Channel::try_from(channel.to_string()),
+                Err(ErrorType::NoSuchChannel),
+                "{channel:?}"
+            );
+        }
+        assert!(Channel::try_from(format!("#{}", "a".repeat(MAX_CHANNEL_LEN - 1))).is_ok());
+        assert!(Channel::try_from(format!("#{}", "a".repeat(MAX_CHANNEL_LEN))).is_err());
+    }
+    #[test]
+    fn test_multi_join() {
+        assert_eq!(
+            ParsedMessage::try_from(UnparsedMessage {
+                message: "JOIN #a,#b,#A,,#c k1,k2,k3,k4\r\n",
+                sender_nick: Nick("Person".to_string())
+            })
+            .unwrap()
+            .message,
+            Message::Join(JoinMsg {
+                channels: vec![
+                    Channel("#a".to_string()),
+                    Channel("#b".to_string()),
+                    Channel("#c".to_string())
+                ],
+                keys: vec!["k1".to_string(), "k2".to_string(), "k4".to_string()]
+            })
+        );
+        assert_eq!(
+            ParsedMessage::try_from(UnparsedMessage {
+                message: "JOIN #a,b\r\n",
+                sender_nick: Nick("Person".to_string())
+            }),
+            Err(ErrorType::NoSuchChannel)
+        );
+    }
+    #[test]
+    fn test_multi_part() {
+        let parse = |message| {
+            ParsedMessage::try_from(UnparsedMessage {
+                message,
+                sender_nick: Nick("Person".to_string()),
+            })
+            .unwrap()
+            .message
+        };
+
+        assert_eq!(
+            parse("PART #a\r\n"),
+            Message::Part(PartMsg {
+                channels: vec![Channel("#a".to_string())],
+                message: None
+            })
+        );
+        assert_eq!(
+            parse("PART #a,#b,#A :see you\r\n"),
+            Message::Part(PartMsg {
+                channels: vec![Channel("#a".to_string()), Channel("#b".to_string())],
+                message: Some("see you".to_string())
+            })
+        );
+    }
+    #[test]
+    fn test_names_split() {
+        let names = (0..200).map(|i| format!("user{i}")).collect::<Vec<_>>();
+        let reply = Reply::Names(NamesReply {
+            target_nick: Nick("alice".to_string()),
+            channel: Channel("#big".to_string()),
+            names: names.clone(),
+        })
+        .format(SERVER_NAME);
+
+        let lines = reply.split_inclusive("\r\n").collect::<Vec<_>>();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() <= MAX_LINE_LEN));
+
+        let prefix = format!(":{SERVER_NAME} 353 alice = #big :");
+        let listed = lines
+            .iter()
+            .flat_map(|line| {
+                line.strip_prefix(&prefix)
+                    .and_then(|line| line.strip_suffix("\r\n"))
+                    .unwrap()
+                    .split(' ')
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(listed, names);
+
+        let long = "a".repeat(MAX_LINE_LEN);
+        let reply = Reply::Names(NamesReply {
+            target_nick: Nick("alice".to_string()),
+            channel: Channel("#big".to_string()),
+            names: vec!["bob".to_string(), long.clone(), "carol".to_string()],
+        })
+        .format(SERVER_NAME);
+        let lines = reply.split_inclusive("\r\n").collect::<Vec<_>>();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].len(), MAX_LINE_LEN);
+        assert!(long.starts_with(&lines[1][prefix.len()..MAX_LINE_LEN - 2]));
+        assert!(lines[2].ends_with(" :carol\r\n"));
+    }
+
+    #[test]
+    fn test_mask_matches() {
+        assert!(mask_matches("*", "alice"));
+        assert!(mask_matches("a*", "Alice"));
+        assert!(mask_matches("*ic?", "alice"));
+        assert!(mask_matches("a*c*e", "alice"));
+        assert!(mask_matches("[x]", "{X}"));
+        assert!(!mask_matches("a*b", "alice"));
+        assert!(!mask_matches("alic", "alice"));
+        assert!(!mask_matches("?", ""));
+    }
+
+    #[test]
+    fn test_normalize_mask() {
+        assert_eq!(normalize_mask("alice"), "alice!*@*");
+        assert_eq!(normalize_mask("*@10.0.0.1"), "*!*@10.0.0.1");
+        assert_eq!(normalize_mask("alice!alice"), "alice!alice@*");
+        assert_eq!(normalize_mask("a!b@c"), "a!b@c");
+    }
+
+    #[test]
+    fn test_split_timed_mask() {
+        assert_eq!(
+            split_timed_mask("60:bob!*@*"),
+            (Some(Duration::from_secs(60)), "bob!*@*")
+        );
+        assert_eq!(split_timed_mask("bob"), (None, "bob"));
+        assert_eq!(split_timed_mask("*!*@::1"), (None, "*!*@::1"));
+    }
+
+    #[test]
+    fn test_need_more_params() {
+        let parse = |message: &str| {
+            ParsedMessage::try_from(UnparsedMessage {
+                sender_nick: Nick("alice".to_string()),
+                message,
+            })
+            .map(|parsed| parsed.message)
+        };
+
+        for message in [
+            "USER\r\n",
+            "USER alice 0 *\r\n",
+            "JOIN\r\n",
+            "JOIN :\r\n",
+            "PART\r\n",
+            "TOPIC\r\n",
+            "MODE\r\n",
+            "MODE :\r\n",
+            "KICK #a\r\n",
+            "KICK #a :\r\n",
+            "INVITE alice\r\n",
+            "PASS\r\n",
+            "OPER alice\r\n",
+            "KILL\r\n",
+            "KILL :\r\n",
+            "USERHOST\r\n",
+            "WALLOPS\r\n",
+            "DEBUG\r\n",
+            "REMOVE #a\r\n",
+            "USERIP\r\n",
+        ] {
+            assert_eq!(parse(message), Err(ErrorType::NeedMoreParams), "{message}");
+        }
+
+        // commands with their own numerics for what's missing
+        assert_eq!(parse("NICK\r\n"), Err(ErrorType::NoNickNameGiven));
+        assert_eq!(parse("WHOIS\r\n"), Err(ErrorType::NoNickNameGiven));
+        assert_eq!(parse("PRIVMSG\r\n"), Err(ErrorType::NoRecipient));
+        assert_eq!(parse("PRIVMSG :\r\n"), Err(ErrorType::NoRecipient));
+        assert_eq!(parse("PRIVMSG #a\r\n"), Err(ErrorType::NoTextToSend));
+        assert_eq!(parse("PRIVMSG bob :\r\n"), Err(ErrorType::NoTextToSend));
+        assert_eq!(parse("PING\r\n"), Err(ErrorType::NoOrigin));
+    }
+
+    #[test]
+    fn test_error_replies() {
+        let errors = [
+            ErrorType::NoNickNameGiven,
+            ErrorType::ErroneousNickname,
+            ErrorType::NicknameInUse,
+            ErrorType::NickCollision,
+            ErrorType::NoRecipient,
+            ErrorType::NoTextToSend,
+            ErrorType::NoOrigin,
+            ErrorType::UnknownCommand,
+            ErrorType::NeedMoreParams,
+            ErrorType::AlreadyRegistered,
+            ErrorType::NoSuchNick,
+            ErrorType::NoSuchChannel,
+            ErrorType::TooManyChannels,
+            ErrorType::InputTooLong,
+            ErrorType::NotOnChannel,
+            ErrorType::NoMotd,
+        ];
+
+        for error in errors {
+            let reply = Reply::Error(ErrorReply {
+                target_nick: Some(Nick("alice".to_string())),
+                error,
+                subject: Some("#chan".to_string()),
+            })
+            .format(SERVER_NAME);
+
+            // `:server <numeric> <nick> <subject> :<text>`, and nothing after the text
+            let (head, text) = reply.split_once(" :").unwrap();
+            let code = format!("{:03}", error.code());
+            assert_eq!(
+                head.split(' ').collect::<Vec<_>>(),
+                [":iris-server", code.as_str(), "alice", "#chan"]
+            );
+            assert_eq!(text, format!("{error}\r\n"));
+            assert!(!text.trim_end().is_empty());
+        }
+
+        assert_eq!(
+            Reply::Error(ErrorReply {
+                target_nick: None,
+                error: ErrorType::NoNickNameGiven,
+                subject: None,
+            })
+            .format(SERVER_NAME),
+            ":iris-server 431 * :No nickname given.\r\n"
+        );
+    }
+
+    #[test]
+    fn test_format_utc() {
+        let at = |secs| SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        assert_eq!(format_utc(at(0)), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_utc(at(951_782_400)), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_utc(at(1_706_691_900)), "2024-01-31 09:05:00 UTC");
+    }
+
+    #[test]
+    fn test_ison() {
+        let parse = |message: &str| {
+            ParsedMessage::try_from(UnparsedMessage {
+                sender_nick: Nick("alice".to_string()),
+                message,
+            })
+            .map(|parsed| parsed.message)
+        };
+        let nicks = |nicks: &[&str]| {
+            Message::Ison(IsonMsg {
+                nicks: nicks.iter().map(|nick| Nick(nick.to_string())).collect(),
+            })
+        };
+
+        assert_eq!(parse("ISON alice bob\r\n"), Ok(nicks(&["alice", "bob"])));
+        assert_eq!(parse("ISON :alice  bob\r\n"), Ok(nicks(&["alice", "bob"])));
+        assert_eq!(parse("ISON\r\n"), Ok(nicks(&[])));
+    }
+
+    #[test]
+    fn test_mode() {
+        let parse = |message| {
+            ParsedMessage::try_from(UnparsedMessage {
+                message,
+                sender_nick: Nick("Person".to_string()),
+            })
+        };
+
+        let Ok(ParsedMessage {
+            message: Message::Mode(mode),
+            ..
+        }) = parse("MODE #a +o-xo alice bob\r\n")
+        else {
+            panic!("expected a MODE");
+        };
+        assert_eq!(mode.target, Target::Channel(Channel("#a".to_string())));
+        let change = |set, mode, param: Option<&str>| ModeChange {
+            set,
+            mode,
+            param: param.map(str::to_string),
+        };
+        assert_eq!(
+            mode.changes(),
+            [
+                change(true, 'o', Some("alice")),
+                change(false, 'x', None),
+                change(false, 'o', Some("bob")),
+            ]
+        );
+        assert_eq!(
+            Reply::Mode(ModeReply {
+                sender: Some(Prefix {
+                    nick: Nick("alice".to_string()),
+                    user: "alice".to_string(),
+                    host: "127.0.0.1".to_string(),
+                }),
+                target: Target::Channel(Channel("#a".to_string())),
+                changes: mode.changes(),
+            })
+            .format(SERVER_NAME),
+            ":alice!alice@127.0.0.1 MODE #a +o-xo alice bob\r\n"
+        );
+        assert_eq!(
+            Reply::Mode(ModeReply {
+                sender: None,
+                target: Target::Channel(Channel("#a".to_string())),
+                changes: vec![change(false, 'q', Some("bob!*@*"))],
+            })
+            .format(SERVER_NAME),
+            format!(":{SERVER_NAME} MODE #a -q bob!*@*\r\n")
+        );
+
+        assert_eq!(parse("MODE\r\n"), Err(ErrorType::NeedMoreParams));
+    }
 }
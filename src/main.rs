@@ -1,7 +1,8 @@
 use clap::Parser;
 use env_logger::Env;
 use iris_lib::Iris;
-use std::net::IpAddr;
+use log::LevelFilter;
+use std::{net::IpAddr, path::PathBuf, sync::Arc};
 
 #[derive(Parser)]
 struct Arguments {
@@ -10,14 +11,45 @@ struct Arguments {
 
     #[clap(default_value = "6991")]
     port: u16,
+
+    /// A file holding the message of the day
+    #[clap(long)]
+    motd: Option<PathBuf>,
+
+    /// The password clients must give to connect
+    #[clap(long)]
+    password: Option<String>,
 }
 
 fn main() {
-    // init env_logger
-    let env = Env::default().filter_or("RUST_LOG", "debug");
+    // init env_logger, letting everything through unless RUST_LOG says otherwise,
+    // so the level can be raised as far as trace while running (DEBUG LOGLEVEL)
+    let rust_log_set = std::env::var_os("RUST_LOG").is_some();
+    let env = Env::default().filter_or("RUST_LOG", "trace");
     env_logger::init_from_env(env);
+    if !rust_log_set {
+        log::set_max_level(LevelFilter::Debug);
+    }
 
     // start iris
     let arguments = Arguments::parse();
-    Iris::new(arguments.ip_address, arguments.port).start();
+    let mut builder = Iris::builder().listen(arguments.ip_address, arguments.port);
+    if let Some(motd) = arguments.motd {
+        builder = builder.motd_path(motd);
+    }
+    if let Some(password) = arguments.password {
+        builder = builder.password(password);
+    }
+    let iris = Arc::new(builder.build());
+
+    // stop cleanly on Ctrl-C or SIGTERM
+    let stopping = iris.clone();
+    if let Err(err) = ctrlc::set_handler(move || stopping.stop()) {
+        log::warn!("Failed to set signal handler: {err}");
+    }
+
+    if let Err(err) = iris.start() {
+        log::error!("{err}");
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,771 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc, thread, time::Duration};
+
+use common::{replies, test_client};
+use iris_lib::{
+    client::{reap_channels, ChannelState, Channels, Clients},
+    config::{hash_password, Config},
+    errors::LoopControlError,
+    events::IrcEvent,
+    types::{Channel, Nick, SERVER_NAME},
+};
+
+#[test]
+fn test_restrict_channel_creation() {
+    let config = Arc::new(Config {
+        restrict_channel_creation: true,
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    channels
+        .lock()
+        .unwrap()
+        .insert(Channel("#lobby".to_string()), ChannelState::default());
+
+    let (mut alice, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #new\r\nJOIN #lobby\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    replies(&rx);
+
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx)[0].numeric(), Some(403));
+    assert!(!channels
+        .lock()
+        .unwrap()
+        .contains_key(&Channel("#new".to_string())));
+
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx)[0].command, "JOIN");
+}
+
+#[test]
+fn test_topic() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nTOPIC #a\r\nTOPIC #a :Rust talk\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nTOPIC #a :Hijacked\r\nTOPIC #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    assert!(alice.step().is_ok());
+    let reply = &replies(&alice_rx)[0];
+    assert_eq!(reply.numeric(), Some(331));
+    assert_eq!(reply.params[..2], ["alice", "#a"]);
+
+    assert!(alice.step().is_ok());
+    let reply = &replies(&alice_rx)[0];
+    assert_eq!(reply.prefix.as_deref(), Some("alice!alice@127.0.0.1"));
+    assert_eq!(reply.command, "TOPIC");
+    assert_eq!(reply.params, ["#a", "Rust talk"]);
+
+    // setting the topic of a channel you're not in is refused
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(442));
+    assert!(replies(&alice_rx).is_empty());
+
+    assert!(bob.step().is_ok());
+    let reply = &replies(&bob_rx)[0];
+    assert_eq!(reply.numeric(), Some(332));
+    assert_eq!(reply.params, ["bob", "#a", "Rust talk"]);
+}
+
+#[test]
+fn test_topic_cooldown() {
+    let config = Arc::new(Config {
+        topic_cooldown: Some(Duration::from_millis(100)),
+        opers: HashMap::from([("admin".to_string(), hash_password("hunter2"))]),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nTOPIC #a :One\r\nTOPIC #a :Two\r\n\
+         TOPIC #a :Three\r\nOPER admin hunter2\r\nTOPIC #a :Four\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "One"]);
+
+    // a second change straight away is refused
+    assert!(alice.step().is_ok());
+    let refused = replies(&alice_rx);
+    assert_eq!(refused.len(), 1);
+    assert_eq!(refused[0].command, "NOTICE");
+    let topic = |channels: &Channels| {
+        channels
+            .lock()
+            .unwrap()
+            .get(&Channel("#a".to_string()))
+            .unwrap()
+            .topic
+            .clone()
+    };
+    assert_eq!(topic(&channels).as_deref(), Some("One"));
+
+    // but fine once the cooldown is over
+    thread::sleep(Duration::from_millis(150));
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "Three"]);
+
+    // and operators needn't wait
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "Four"]);
+    assert_eq!(topic(&channels).as_deref(), Some("Four"));
+}
+
+#[test]
+fn test_join_greeting() {
+    let config = Arc::new(Config {
+        join_greetings: HashMap::from([(
+            Channel("#welcome".to_string()),
+            "Please read the rules".to_string(),
+        )]),
+        ..Config::default()
+    });
+    let (mut alice, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #welcome\r\nJOIN #other\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &Clients::default(),
+        &Channels::default(),
+    );
+    alice.login();
+    replies(&rx);
+
+    // the greeting comes from the server, once the joiner has the member list
+    assert!(alice.step().is_ok());
+    let joined = replies(&rx);
+    let end_of_names = joined
+        .iter()
+        .position(|line| line.numeric() == Some(366))
+        .unwrap();
+    let greeting = &joined[end_of_names + 1..];
+    assert_eq!(greeting.len(), 1);
+    assert_eq!(greeting[0].prefix.as_deref(), Some(SERVER_NAME));
+    assert_eq!(greeting[0].command, "NOTICE");
+    assert_eq!(greeting[0].params, ["alice", "Please read the rules"]);
+
+    // other channels have none
+    assert!(alice.step().is_ok());
+    assert!(replies(&rx).iter().all(|line| line.command != "NOTICE"));
+}
+
+#[test]
+fn test_invalid_channel_name() {
+    let config = Arc::new(Config::default());
+    let channels = Channels::default();
+    let (mut alice, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN chan\r\nJOIN #bell\x07\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &Clients::default(),
+        &channels,
+    );
+    alice.login();
+    replies(&rx);
+
+    for _ in 0..2 {
+        assert!(matches!(alice.step(), Err(LoopControlError::Continue)));
+        assert_eq!(replies(&rx)[0].numeric(), Some(403));
+    }
+    assert!(channels.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_multi_join() {
+    let config = Arc::new(Config::default());
+    let channels = Channels::default();
+    let (mut alice, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nJOIN #a,#b,#B,#c\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &Clients::default(),
+        &channels,
+    );
+    alice.login();
+    assert!(alice.step().is_ok());
+    replies(&rx);
+
+    // #a is already joined and #B is #b again, so only #b and #c are joined
+    assert!(alice.step().is_ok());
+    let joined = replies(&rx)
+        .into_iter()
+        .filter(|line| line.command == "JOIN")
+        .map(|line| (line.command, line.params[0].clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        joined,
+        [
+            ("JOIN".to_string(), "#b".to_string()),
+            ("JOIN".to_string(), "#c".to_string())
+        ]
+    );
+    assert_eq!(channels.lock().unwrap().len(), 3);
+}
+
+#[test]
+fn test_max_channels() {
+    let config = Arc::new(Config {
+        max_channels: Some(2),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a,#b\r\nJOIN #c\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #c,#a,#d,#b\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // a third channel is refused
+    assert!(alice.step().is_ok());
+    let refused = replies(&alice_rx);
+    assert_eq!(refused.len(), 1);
+    assert_eq!(refused[0].numeric(), Some(405));
+    assert_eq!(refused[0].params[1], "#c");
+
+    // but the existing two can still be joined, even after a refusal in the same JOIN
+    assert!(bob.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    let errors = replies_to_bob
+        .iter()
+        .filter_map(|line| line.numeric().filter(|numeric| *numeric >= 400))
+        .collect::<Vec<_>>();
+    assert_eq!(errors, [405]);
+    let joined = replies_to_bob
+        .iter()
+        .filter(|line| line.command == "JOIN")
+        .map(|line| line.params[0].as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(joined, ["#a", "#b"]);
+    assert_eq!(channels.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_join_flood() {
+    let config = Arc::new(Config {
+        max_channels: Some(5),
+        ..Config::default()
+    });
+    let channels = Channels::default();
+    let targets = (0..20).map(|i| format!("#{i}")).collect::<Vec<_>>();
+    let (mut alice, rx) = test_client(
+        &format!(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN {}\r\n",
+            targets.join(",")
+        ),
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &Clients::default(),
+        &channels,
+    );
+    alice.login();
+    replies(&rx);
+
+    // joins up to the limit, refuses the first channel over it and skips the rest
+    assert!(alice.step().is_ok());
+    let replies = replies(&rx);
+    assert_eq!(
+        replies.iter().filter(|line| line.command == "JOIN").count(),
+        5
+    );
+    assert_eq!(replies.last().unwrap().numeric(), Some(405));
+    assert_eq!(channels.lock().unwrap().len(), 5);
+}
+
+#[test]
+fn test_multi_part() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a,#b\r\nPART #a,#x,#c,#b :bye\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a,#c\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // the errors for #x and #c don't stop alice leaving #b
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice.len(), 4);
+    assert_eq!(replies_to_alice[0].command, "PART");
+    assert_eq!(replies_to_alice[0].params, ["#a", "bye"]);
+    assert_eq!(replies_to_alice[1].numeric(), Some(403));
+    assert_eq!(replies_to_alice[2].numeric(), Some(442));
+    assert_eq!(replies_to_alice[3].params, ["#b", "bye"]);
+
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob.len(), 1);
+    assert_eq!(
+        replies_to_bob[0].prefix.as_deref(),
+        Some("alice!alice@127.0.0.1")
+    );
+    assert_eq!(replies_to_bob[0].params, ["#a", "bye"]);
+
+    // #b was left empty, so it is gone
+    assert!(!channels
+        .lock()
+        .unwrap()
+        .contains_key(&Channel("#b".to_string())));
+}
+
+#[test]
+fn test_concurrent_join_part() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let churn = "JOIN #a,#b,#c\r\nPART #b,#c\r\nJOIN #c,#a\r\nPART #a,#c\r\n".repeat(50);
+    // the receivers outlive every client's thread, or one that finished early would look
+    // gone to the rest and be pruned from its channels
+    let (users, _rxs): (Vec<_>, Vec<_>) = (0..8u8)
+        .map(|i| {
+            // everyone ends up in #a, and only the odd ones in #b
+            let last = if i % 2 == 1 { "JOIN #a,#b" } else { "JOIN #a" };
+            let (mut client, rx) = test_client(
+                &format!("NICK user{i}\r\nUSER user{i} 0 * :User\r\n{churn}{last}\r\n"),
+                ([127, 0, 0, 1 + i], 6991),
+                &config,
+                &clients,
+                &channels,
+            );
+            client.login();
+            (client, rx)
+        })
+        .unzip();
+
+    thread::scope(|scope| {
+        for mut client in users {
+            scope.spawn(move || while !matches!(client.step(), Err(LoopControlError::Break)) {});
+        }
+    });
+
+    // no channel was left behind empty, and nobody is in one twice or by mistake
+    let channels = channels.lock().unwrap();
+    assert_eq!(channels.len(), 2);
+    let members = |name: &str| {
+        let mut members = channels[&Channel(name.to_string())]
+            .members
+            .keys()
+            .map(|nick| nick.to_string())
+            .collect::<Vec<_>>();
+        members.sort();
+        members
+    };
+    assert_eq!(
+        members("#a"),
+        (0..8).map(|i| format!("user{i}")).collect::<Vec<_>>()
+    );
+    assert_eq!(members("#b"), ["user1", "user3", "user5", "user7"]);
+}
+
+#[test]
+fn test_names() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nNAMES #a,#none\r\nNAMES\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a,#b\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+
+    replies(&bob_rx);
+
+    // the joiner is sent the member list, themselves included
+    assert!(bob.step().is_ok());
+    let names = replies(&bob_rx)
+        .into_iter()
+        .filter(|line| line.numeric().is_some())
+        .map(|line| (line.numeric().unwrap(), line.params))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names[0],
+        (
+            353,
+            vec!["bob".into(), "=".into(), "#a".into(), "@alice bob".into()]
+        )
+    );
+    assert_eq!(names[1].0, 366);
+    assert_eq!(
+        names[2],
+        (
+            353,
+            vec!["bob".into(), "=".into(), "#b".into(), "@bob".into()]
+        )
+    );
+    assert_eq!(names[3].0, 366);
+    replies(&alice_rx);
+
+    // a channel that doesn't exist just ends its list
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice.len(), 3);
+    assert_eq!(replies_to_alice[0].params[3], "@alice bob");
+    assert_eq!(replies_to_alice[1].params[1], "#a");
+    assert_eq!(replies_to_alice[2].numeric(), Some(366));
+    assert_eq!(replies_to_alice[2].params[1], "#none");
+
+    // no channels lists every channel, then ends once
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(
+        replies_to_alice
+            .iter()
+            .map(|line| (line.numeric(), line.params[1].as_str()))
+            .collect::<Vec<_>>(),
+        [(Some(353), "="), (Some(353), "="), (Some(366), "*")]
+    );
+}
+
+#[test]
+fn test_list() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #b,#a\r\nTOPIC #a :Apples\r\nLIST\r\nLIST #b,#none\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // every channel, sorted, with its user count and topic
+    assert!(alice.step().is_ok());
+    assert_eq!(
+        replies(&alice_rx)
+            .into_iter()
+            .map(|line| (line.numeric().unwrap(), line.params[1..].to_vec()))
+            .collect::<Vec<_>>(),
+        [
+            (321, vec!["Channel".to_string(), "Users  Name".to_string()]),
+            (322, vec!["#a".into(), "2".into(), "Apples".into()]),
+            (322, vec!["#b".into(), "1".into(), "".into()]),
+            (323, vec!["End of /LIST".to_string()]),
+        ]
+    );
+
+    // only the named channels that exist
+    assert!(alice.step().is_ok());
+    assert_eq!(
+        replies(&alice_rx)
+            .into_iter()
+            .map(|line| (line.numeric().unwrap(), line.params[1].clone()))
+            .collect::<Vec<_>>(),
+        [
+            (321, "Channel".to_string()),
+            (322, "#b".to_string()),
+            (323, "End of /LIST".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_kick() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nKICK #a carol\r\nKICK #a bob :Behave\r\nKICK #a alice\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nKICK #a alice\r\nJOIN #a\r\nKICK #a alice\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // only an operator on the channel may kick
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(442));
+    assert!(bob.step().is_ok());
+    replies(&bob_rx);
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(482));
+    replies(&alice_rx);
+
+    // and only someone on it may be kicked
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice[0].numeric(), Some(441));
+    assert_eq!(replies_to_alice[0].params[1..3], ["carol", "#a"]);
+
+    // the channel and the one kicked see it
+    assert!(alice.step().is_ok());
+    for rx in [&alice_rx, &bob_rx] {
+        let replies = replies(rx);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].prefix.as_deref(), Some("alice!alice@127.0.0.1"));
+        assert_eq!(replies[0].command, "KICK");
+        assert_eq!(replies[0].params, ["#a", "bob", "Behave"]);
+    }
+    let channel = Channel("#a".to_string());
+    assert_eq!(channels.lock().unwrap()[&channel].members.len(), 1);
+
+    // kicking the last member deletes the channel
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "alice", "alice"]);
+    assert!(!channels.lock().unwrap().contains_key(&channel));
+}
+
+#[test]
+fn test_remove() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nREMOVE #a bob :Behave\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nREMOVE #a carol\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut carol, carol_rx) = test_client(
+        "NICK carol\r\nUSER carol 0 * :Carol\r\nJOIN #a\r\n",
+        ([127, 0, 0, 3], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    carol.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    assert!(carol.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+    replies(&carol_rx);
+
+    // only an operator on the channel may remove
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(482));
+
+    // everyone sees the one removed part, rather than get kicked
+    assert!(alice.step().is_ok());
+    for rx in [&alice_rx, &bob_rx, &carol_rx] {
+        let replies = replies(rx);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].prefix.as_deref(), Some("bob!bob@127.0.0.2"));
+        assert_eq!(replies[0].command, "PART");
+        assert_eq!(replies[0].params, ["#a", "Removed by alice: Behave"]);
+    }
+    assert!(!channels.lock().unwrap()[&Channel("#a".to_string())]
+        .members
+        .contains_key(&Nick("bob".to_string())));
+}
+
+#[test]
+fn test_channel_grace() {
+    let config = Arc::new(Config {
+        channel_grace: Duration::from_millis(200),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nTOPIC #a :Rules\r\nPART #a\r\nJOIN #a\r\nTOPIC #a\r\nPART #a\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    for _ in 0..3 {
+        assert!(alice.step().is_ok());
+    }
+    let channel = Channel("#a".to_string());
+    assert!(channels.lock().unwrap()[&channel].members.is_empty());
+
+    // coming back within the grace period finds the channel as it was left
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    assert!(alice.step().is_ok());
+    let topic = &replies(&alice_rx)[0];
+    assert_eq!(topic.numeric(), Some(332));
+    assert_eq!(topic.params, ["alice", "#a", "Rules"]);
+
+    // once the grace period is over, the reaper deletes it
+    assert!(alice.step().is_ok());
+    reap_channels(&channels, config.channel_grace);
+    assert!(channels.lock().unwrap().contains_key(&channel));
+    thread::sleep(config.channel_grace);
+    reap_channels(&channels, config.channel_grace);
+    assert!(!channels.lock().unwrap().contains_key(&channel));
+}
+
+#[test]
+fn test_names_split() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (members, _rxs): (Vec<_>, Vec<_>) = (0..100)
+        .map(|i| {
+            test_client(
+                &format!("NICK member{i:03}\r\nUSER member 0 * :Member\r\nJOIN #big\r\n"),
+                ([10, 0, 0, 1], 50000 + i),
+                &config,
+                &clients,
+                &channels,
+            )
+        })
+        .unzip();
+    for mut member in members {
+        member.login();
+        assert!(member.step().is_ok());
+    }
+
+    let (mut alice, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nNAMES #big\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    replies(&rx);
+    assert!(alice.step().is_ok());
+
+    let lines = rx
+        .try_iter()
+        .filter_map(|event| match event {
+            IrcEvent::Send(message) => Some(message),
+            IrcEvent::Terminate => None,
+        })
+        .flat_map(|message| {
+            message
+                .split_inclusive("\r\n")
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let names = lines
+        .iter()
+        .filter(|line| line.contains(" 353 "))
+        .collect::<Vec<_>>();
+    assert!(names.len() > 1);
+    assert!(lines.iter().all(|line| line.len() <= 512));
+
+    // every member is listed exactly once, across the lines
+    let mut listed = names
+        .iter()
+        .flat_map(|line| {
+            line.strip_prefix(&format!(":{SERVER_NAME} 353 alice = #big :"))
+                .and_then(|line| line.strip_suffix("\r\n"))
+                .unwrap()
+                .split(' ')
+        })
+        .collect::<Vec<_>>();
+    listed.sort_unstable();
+    let expected = (0..100)
+        .map(|i| format!("member{i:03}"))
+        .collect::<Vec<_>>();
+    assert_eq!(listed[0], "@member000");
+    assert_eq!(listed[1..], expected[1..]);
+    assert!(lines.last().unwrap().contains(" 366 alice #big "));
+}
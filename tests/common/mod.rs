@@ -0,0 +1,183 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use iris_lib::{
+    client::{Channels, Client, Clients, HeldNicks},
+    config::Config,
+    connect::ConnectionRead,
+    events::IrcEvent,
+    metrics::Metrics,
+};
+
+/// A line sent by the server, split into its parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl Line {
+    pub fn parse(line: &str) -> Self {
+        let line = line.strip_suffix("\r\n").unwrap_or(line);
+
+        let (prefix, line) = match line.strip_prefix(':') {
+            Some(prefixed) => {
+                let (prefix, rest) = prefixed.split_once(' ').unwrap_or((prefixed, ""));
+                (Some(prefix.to_string()), rest)
+            }
+            None => (None, line),
+        };
+
+        let (middle, trailing) = match line.split_once(" :") {
+            Some((middle, trailing)) => (middle, Some(trailing)),
+            None => (line, None),
+        };
+
+        let mut words = middle.split(' ').filter(|word| !word.is_empty());
+        let command = words.next().unwrap_or_default().to_string();
+        let mut params = words.map(str::to_string).collect::<Vec<_>>();
+        params.extend(trailing.map(str::to_string));
+
+        Self {
+            prefix,
+            command,
+            params,
+        }
+    }
+
+    /// The reply code, if this is a numeric reply.
+    pub fn numeric(&self) -> Option<u16> {
+        match self.command.len() {
+            3 => self.command.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A client reading `input` from memory, sharing `clients` and `channels`.
+/// It shares no held nicks with other clients, see `test_client_holding`.
+#[allow(dead_code)]
+pub fn test_client(
+    input: &str,
+    addr: impl Into<SocketAddr>,
+    config: &Arc<Config>,
+    clients: &Clients,
+    channels: &Channels,
+) -> (Client, Receiver<IrcEvent>) {
+    test_client_holding(
+        input,
+        addr,
+        config,
+        clients,
+        channels,
+        &HeldNicks::default(),
+    )
+}
+
+/// Like `test_client`, but also sharing `held_nicks`.
+#[allow(dead_code)]
+pub fn test_client_holding(
+    input: &str,
+    addr: impl Into<SocketAddr>,
+    config: &Arc<Config>,
+    clients: &Clients,
+    channels: &Channels,
+    held_nicks: &HeldNicks,
+) -> (Client, Receiver<IrcEvent>) {
+    let (tx, rx) = mpsc::channel();
+    let client = Client::new(
+        ConnectionRead::from_bytes(input, addr.into()),
+        tx,
+        config.clone(),
+        Arc::new(Metrics::default()),
+        clients.clone(),
+        channels.clone(),
+        held_nicks.clone(),
+        Arc::default(),
+    );
+    (client, rx)
+}
+
+/// Every message logged since `capture_logs` was first called, as `LEVEL message`.
+static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct CapturingLogger;
+
+impl Log for CapturingLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        LOGS.lock()
+            .unwrap()
+            .push(format!("{} {}", record.level(), record.args()));
+    }
+
+    fn flush(&self) {}
+}
+
+/// Start keeping what is logged, for `logged`. Tests run in parallel and share the logger,
+/// so they should look for messages only they could have caused.
+#[allow(dead_code)]
+pub fn capture_logs() {
+    let _ = log::set_logger(&CapturingLogger);
+    log::set_max_level(LevelFilter::Info);
+}
+
+/// Whether a message containing `text` has been logged since `capture_logs` was called.
+#[allow(dead_code)]
+pub fn logged(text: &str) -> bool {
+    LOGS.lock().unwrap().iter().any(|line| line.contains(text))
+}
+
+/// Every line sent to a client so far, decoded.
+#[allow(dead_code)]
+pub fn replies(rx: &Receiver<IrcEvent>) -> Vec<Line> {
+    rx.try_iter()
+        .filter_map(|event| match event {
+            IrcEvent::Send(message) => Some(message),
+            IrcEvent::Terminate => None,
+        })
+        .flat_map(|message| {
+            message
+                .split_inclusive("\r\n")
+                .map(Line::parse)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_numeric() {
+    let line = Line::parse(":iris-server 353 alice = #chan :@alice bob\r\n");
+    assert_eq!(line.prefix.as_deref(), Some("iris-server"));
+    assert_eq!(line.numeric(), Some(353));
+    assert_eq!(line.params, ["alice", "=", "#chan", "@alice bob"]);
+}
+
+#[test]
+fn test_parse_command() {
+    let line = Line::parse(":alice PRIVMSG #chan :hi  there\r\n");
+    assert_eq!(line.prefix.as_deref(), Some("alice"));
+    assert_eq!(line.command, "PRIVMSG");
+    assert_eq!(line.numeric(), None);
+    assert_eq!(line.params, ["#chan", "hi  there"]);
+
+    let line = Line::parse("PING :iris-server\r\n");
+    assert_eq!(line.prefix, None);
+    assert_eq!(line.command, "PING");
+    assert_eq!(line.params, ["iris-server"]);
+
+    let line = Line::parse("ERROR :Idle timeout");
+    assert_eq!(line.command, "ERROR");
+    assert_eq!(line.params, ["Idle timeout"]);
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc};
+
+use common::{replies, test_client};
+use iris_lib::{
+    client::{Channels, Clients},
+    config::{hash_password, Config},
+    Iris,
+};
+use log::LevelFilter;
+
+// The log level is global, so these tests have a binary of their own.
+
+#[test]
+fn test_log_level() {
+    let config = Arc::new(Config {
+        opers: HashMap::from([("admin".to_string(), hash_password("hunter2"))]),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nDEBUG LOGLEVEL trace\r\nOPER admin hunter2\r\n\
+         DEBUG LOGLEVEL trace\r\nDEBUG loglevel loud\r\nDEBUG LOGLEVEL\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    Iris::new([127, 0, 0, 1].into(), 0).set_log_level(LevelFilter::Warn);
+    assert_eq!(log::max_level(), LevelFilter::Warn);
+
+    // only operators may change it
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx).last().unwrap().numeric(), Some(481));
+    assert_eq!(log::max_level(), LevelFilter::Warn);
+
+    assert!(alice.step().is_ok());
+    replies(&rx);
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx)[0].params[1], "Log level: TRACE");
+    assert_eq!(log::max_level(), LevelFilter::Trace);
+
+    // an unknown level changes nothing
+    assert!(alice.step().is_ok());
+    assert!(replies(&rx)[0].params[1].starts_with("Unknown log level loud"));
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx)[0].params[1], "Log level: TRACE");
+}
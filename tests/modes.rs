@@ -0,0 +1,592 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc, thread, time::Instant};
+
+use common::{replies, test_client};
+use iris_lib::{
+    client::{expire_mutes, Channels, Clients},
+    config::{hash_password, Config},
+    types::{Channel, Nick, SERVER_NAME},
+};
+
+#[test]
+fn test_channel_operators() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +o bob\r\nMODE #a -o+x alice carol\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nMODE #a\r\nWHO #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+
+    // the creator of a channel is its operator, later joiners aren't
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    let channel = Channel("#a".to_string());
+    let is_op = |nick: &str| channels.lock().unwrap()[&channel].members[&Nick(nick.to_string())].op;
+    assert!(is_op("alice"));
+    assert!(!is_op("bob"));
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // an operator can op someone else, which the whole channel sees
+    assert!(alice.step().is_ok());
+    assert!(is_op("bob"));
+    for rx in [&alice_rx, &bob_rx] {
+        let replies = replies(rx);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].prefix.as_deref(), Some("alice!alice@127.0.0.1"));
+        assert_eq!(replies[0].command, "MODE");
+        assert_eq!(replies[0].params, ["#a", "+o", "bob"]);
+    }
+
+    // changes that can't be made are refused one by one
+    assert!(alice.step().is_ok());
+    assert!(!is_op("alice"));
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice.len(), 2);
+    assert_eq!(replies_to_alice[0].numeric(), Some(472));
+    assert_eq!(replies_to_alice[0].params[1], "x");
+    assert_eq!(replies_to_alice[1].params, ["#a", "-o", "alice"]);
+    replies(&bob_rx);
+
+    // viewing the modes
+    assert!(bob.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob[0].numeric(), Some(324));
+    assert_eq!(replies_to_bob[0].params, ["bob", "#a", "+n"]);
+
+    // WHO shows who is an operator
+    assert!(bob.step().is_ok());
+    let flags = replies(&bob_rx)
+        .into_iter()
+        .filter(|line| line.numeric() == Some(352))
+        .map(|line| (line.params[5].clone(), line.params[6].clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        flags,
+        [
+            ("alice".to_string(), "H".to_string()),
+            ("bob".to_string(), "H@".to_string())
+        ]
+    );
+}
+
+#[test]
+fn test_mode_errors() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +o carol\r\nMODE #none +o bob\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nMODE #a +o bob\r\nJOIN #a\r\nMODE #a +o bob\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    let errors = |rx| {
+        replies(rx)
+            .into_iter()
+            .filter_map(|line| line.numeric().map(|numeric| (numeric, line.params)))
+            .collect::<Vec<_>>()
+    };
+
+    // a non-member isn't on the channel, a member who isn't an operator lacks the privileges
+    assert!(bob.step().is_ok());
+    assert_eq!(
+        errors(&bob_rx),
+        [(
+            442,
+            vec![
+                "bob".into(),
+                "#a".into(),
+                "You're not on that channel".into()
+            ]
+        )]
+    );
+    assert!(bob.step().is_ok());
+    replies(&bob_rx);
+    assert!(bob.step().is_ok());
+    assert_eq!(
+        errors(&bob_rx),
+        [(
+            482,
+            vec![
+                "bob".into(),
+                "#a".into(),
+                "You're not channel operator".into()
+            ]
+        )]
+    );
+
+    // only members can be given a status, and only in channels that exist
+    assert!(alice.step().is_ok());
+    assert_eq!(errors(&alice_rx)[0].0, 441);
+    assert!(alice.step().is_ok());
+    assert_eq!(errors(&alice_rx)[0].0, 403);
+}
+
+#[test]
+fn test_invite_only() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +i\r\nMODE #a\r\nINVITE carol #a\r\nINVITE bob #a\r\nINVITE bob #a\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nJOIN #a\r\nPART #a\r\nJOIN #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+    assert!(alice.step().is_ok());
+    assert!(alice.step().is_ok());
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice[3].params, ["#a", "+i"]);
+    assert_eq!(replies_to_alice[4].params, ["alice", "#a", "+in"]);
+    replies(&bob_rx);
+
+    // without an invite, an invite-only channel can't be joined
+    assert!(bob.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob[0].numeric(), Some(473));
+    assert_eq!(replies_to_bob[0].params[1], "#a");
+
+    // only those connected can be invited
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].numeric(), Some(401));
+
+    // the invited hear who invited them, the inviter that it was sent
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice[0].numeric(), Some(341));
+    assert_eq!(replies_to_alice[0].params, ["alice", "bob", "#a"]);
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(
+        replies_to_bob[0].prefix.as_deref(),
+        Some("alice!alice@127.0.0.1")
+    );
+    assert_eq!(replies_to_bob[0].command, "INVITE");
+    assert_eq!(replies_to_bob[0].params, ["bob", "#a"]);
+
+    // an invite lets them in once
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].command, "JOIN");
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx).last().unwrap().numeric(), Some(443));
+    assert!(bob.step().is_ok());
+    replies(&bob_rx);
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(473));
+}
+
+#[test]
+fn test_channel_key() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +k\r\nMODE #a +k secret\r\nMODE #a\r\nMODE #a -k\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nMODE #a +k mine\r\nMODE #a\r\nJOIN #a\r\nJOIN #a wrong\r\nJOIN #b,#a new,secret\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+    replies(&bob_rx);
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+
+    // a key has to be given
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].numeric(), Some(461));
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "+k", "secret"]);
+
+    // only operators set it, and only members see it
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(442));
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].params, ["bob", "#a", "+nk"]);
+    assert!(alice.step().is_ok());
+    assert_eq!(
+        replies(&alice_rx)[0].params,
+        ["alice", "#a", "+nk", "secret"]
+    );
+
+    // joining needs the key
+    for _ in 0..2 {
+        assert!(bob.step().is_ok());
+        let replies_to_bob = replies(&bob_rx);
+        assert_eq!(replies_to_bob[0].numeric(), Some(475));
+        assert_eq!(replies_to_bob[0].params[1], "#a");
+    }
+    assert!(bob.step().is_ok());
+    let joined = replies(&bob_rx)
+        .into_iter()
+        .filter(|line| line.command == "JOIN")
+        .map(|line| line.params[0].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(joined, ["#b", "#a"]);
+    replies(&alice_rx);
+
+    // unsetting it doesn't need the key
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "-k", "*"]);
+    assert_eq!(
+        channels.lock().unwrap()[&Channel("#a".to_string())].key,
+        None
+    );
+}
+
+#[test]
+fn test_channel_limit() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +l many\r\nMODE #a +l 2\r\nMODE #a\r\nMODE #a -l\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+
+    // a limit has to be a number
+    assert!(alice.step().is_ok());
+    assert!(replies(&alice_rx).is_empty());
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "+l", "2"]);
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["alice", "#a", "+nl", "2"]);
+
+    // however many join at once, only one more fits
+    let joiners = (0..8)
+        .map(|i| {
+            let (mut client, rx) = test_client(
+                &format!("NICK user{i}\r\nUSER user{i} 0 * :User\r\nJOIN #a\r\n"),
+                ([127, 0, 0, 2 + i], 6991),
+                &config,
+                &clients,
+                &channels,
+            );
+            client.login();
+            replies(&rx);
+            (client, rx)
+        })
+        .collect::<Vec<_>>();
+    let replies_to_joiners = thread::scope(|scope| {
+        joiners
+            .into_iter()
+            .map(|(mut client, rx)| {
+                scope.spawn(move || {
+                    assert!(client.step().is_ok());
+                    replies(&rx)
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+    let channel = Channel("#a".to_string());
+    assert_eq!(channels.lock().unwrap()[&channel].members.len(), 2);
+    let full = replies_to_joiners
+        .iter()
+        .filter(|replies| replies[0].numeric() == Some(471))
+        .count();
+    assert_eq!(full, 7);
+
+    assert!(alice.step().is_ok());
+    assert!(replies(&alice_rx)
+        .iter()
+        .any(|line| line.params == ["#a", "-l"]));
+    assert_eq!(channels.lock().unwrap()[&channel].limit, None);
+}
+
+#[test]
+fn test_moderated() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +m\r\nMODE #a +v bob\r\nNAMES #a\r\nPRIVMSG #a :hi\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nPRIVMSG #a :hi\r\nPRIVMSG #a :hi again\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // only operators and voiced members may speak
+    assert!(bob.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob[0].numeric(), Some(404));
+    assert_eq!(replies_to_bob[0].params[1], "#a");
+    assert!(replies(&alice_rx).is_empty());
+
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].params, ["#a", "+v", "bob"]);
+    replies(&alice_rx);
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "hi again"]);
+
+    // voiced members are listed with a +
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params[3], "@alice +bob");
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].params, ["#a", "hi"]);
+}
+
+#[test]
+fn test_bans() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +b bob\r\nMODE #a +b\r\nMODE #a -b bob!*@*\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nMODE #a -b bob\r\nMODE #a +b\r\nJOIN #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // bans are completed to a full mask
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "+b", "bob!*@*"]);
+
+    // anyone matching a ban can't join
+    assert!(bob.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob.len(), 1);
+    assert_eq!(replies_to_bob[0].numeric(), Some(474));
+    assert_eq!(replies_to_bob[0].params[1], "#a");
+
+    // only operators may change the list, but anyone may view it
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(442));
+    assert!(bob.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob.len(), 2);
+    assert_eq!(replies_to_bob[0].numeric(), Some(367));
+    assert_eq!(replies_to_bob[0].params, ["bob", "#a", "bob!*@*"]);
+    assert_eq!(replies_to_bob[1].numeric(), Some(368));
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx).len(), 2);
+
+    // once unbanned, they can
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "-b", "bob!*@*"]);
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].command, "JOIN");
+}
+
+#[test]
+fn test_timed_mute() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +q 60:bob\r\nMODE #a +q\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nPRIVMSG #a :hi\r\nPRIVMSG #a :hi again\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // the duration is left off the mask everyone is shown
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "+q", "bob!*@*"]);
+    assert_eq!(replies(&bob_rx)[0].params, ["#a", "+q", "bob!*@*"]);
+
+    // a muted member stays in the channel but can't speak
+    assert!(bob.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob[0].numeric(), Some(404));
+    assert_eq!(replies_to_bob[0].params[1], "#a");
+    assert!(replies(&alice_rx).is_empty());
+
+    assert!(alice.step().is_ok());
+    let list = replies(&alice_rx);
+    assert_eq!(list.len(), 2);
+    assert_eq!(list[0].numeric(), Some(728));
+    assert_eq!(list[0].params, ["alice", "#a", "q", "bob!*@*"]);
+    assert_eq!(list[1].numeric(), Some(729));
+
+    // once the mute runs out the server lifts it
+    channels
+        .lock()
+        .unwrap()
+        .get_mut(&Channel("#a".to_string()))
+        .unwrap()
+        .mute_expiries
+        .insert("bob!*@*".to_string(), Instant::now());
+    expire_mutes(&channels, SERVER_NAME);
+    for rx in [&alice_rx, &bob_rx] {
+        let lifted = replies(rx);
+        assert_eq!(lifted.len(), 1);
+        assert_eq!(lifted[0].prefix.as_deref(), Some(SERVER_NAME));
+        assert_eq!(lifted[0].command, "MODE");
+        assert_eq!(lifted[0].params, ["#a", "-q", "bob!*@*"]);
+    }
+
+    assert!(bob.step().is_ok());
+    assert!(replies(&bob_rx).is_empty());
+    assert_eq!(replies(&alice_rx)[0].params, ["#a", "hi again"]);
+}
+
+#[test]
+fn test_user_modes() {
+    let config = Arc::new(Config {
+        opers: HashMap::from([("admin".to_string(), hash_password("hunter2"))]),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nMODE alice\r\nMODE alice +iwx\r\nMODE alice +o\r\nMODE bob +i\r\nOPER admin hunter2\r\nMODE ALICE -o\r\nMODE alice\r\nJOIN #a\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nWHO alice\r\nJOIN #a\r\nWHO alice\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice[0].numeric(), Some(221));
+    assert_eq!(replies_to_alice[0].params, ["alice", "+"]);
+
+    // unknown modes are refused, the rest applied
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice.len(), 2);
+    assert_eq!(replies_to_alice[0].numeric(), Some(501));
+    assert_eq!(replies_to_alice[1].command, "MODE");
+    assert_eq!(replies_to_alice[1].params, ["alice", "+iw"]);
+
+    // nobody can make themselves an operator, or change anyone else's modes
+    assert!(alice.step().is_ok());
+    assert!(replies(&alice_rx).is_empty());
+    assert!(!alice.is_oper);
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].numeric(), Some(502));
+
+    // but operators can give it up
+    assert!(alice.step().is_ok());
+    assert!(alice.is_oper);
+    replies(&alice_rx);
+    assert!(alice.step().is_ok());
+    assert!(!alice.is_oper);
+    assert_eq!(replies(&alice_rx)[0].params, ["alice", "-o"]);
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["alice", "+iw"]);
+
+    // invisible users are only listed by WHO to those sharing a channel
+    assert!(bob.step().is_ok());
+    let who = replies(&bob_rx);
+    assert_eq!(who.len(), 1);
+    assert_eq!(who[0].numeric(), Some(315));
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&bob_rx);
+    assert!(bob.step().is_ok());
+    let who = replies(&bob_rx);
+    assert_eq!(who.len(), 2);
+    assert_eq!(who[0].params[5], "alice");
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use std::{sync::Arc, thread, time::Duration};
+
+use common::{replies, test_client, test_client_holding};
+use iris_lib::{
+    client::{Channels, Clients, HeldNicks},
+    config::Config,
+    types::{Channel, Nick},
+};
+
+#[test]
+fn test_nick_change() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nNICK bob\r\nNICK alicia\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // taken nicks are refused and the old nick is kept
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].numeric(), Some(433));
+    assert_eq!(alice.nick, Some(Nick("alice".to_string())));
+
+    assert!(alice.step().is_ok());
+    for rx in [&alice_rx, &bob_rx] {
+        let replies = replies(rx);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].prefix.as_deref(), Some("alice!alice@127.0.0.1"));
+        assert_eq!(replies[0].command, "NICK");
+        assert_eq!(replies[0].params, ["alicia"]);
+    }
+
+    let alicia = Nick("alicia".to_string());
+    assert!(clients.lock().unwrap().contains_key(&alicia));
+    assert!(!clients
+        .lock()
+        .unwrap()
+        .contains_key(&Nick("alice".to_string())));
+    assert!(channels.lock().unwrap()[&Channel("#a".to_string())]
+        .members
+        .contains_key(&alicia));
+}
+
+#[test]
+fn test_same_nick() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nNICK alice\r\nNICK Alice\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    replies(&alice_rx);
+
+    // exactly the same nick is a no-op
+    assert!(alice.step().is_ok());
+    assert!(replies(&alice_rx).is_empty());
+
+    // a change of case is a real change, not a collision
+    assert!(alice.step().is_ok());
+    let replies = replies(&alice_rx);
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].command, "NICK");
+    assert_eq!(replies[0].params, ["Alice"]);
+    // nicks compare case-insensitively, so check the spelling itself
+    assert_eq!(alice.nick.map(|nick| nick.0).as_deref(), Some("Alice"));
+}
+
+#[test]
+fn test_nick_case_insensitive() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK ALICE\r\nNICK bob\r\nUSER bob 0 * :Bob\r\nPRIVMSG Alice :hi\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    replies(&alice_rx);
+
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(433));
+
+    bob.login();
+    assert!(bob.step().is_ok());
+    let replies = replies(&alice_rx);
+    assert_eq!(replies[0].command, "PRIVMSG");
+    assert_eq!(replies[0].params, ["Alice", "hi"]);
+}
+
+#[test]
+fn test_nick_hold() {
+    let config = Arc::new(Config {
+        nick_hold: Duration::from_millis(200),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let held_nicks = HeldNicks::default();
+    let client = |input, addr: [u8; 4]| {
+        test_client_holding(
+            input,
+            (addr, 6991),
+            &config,
+            &clients,
+            &channels,
+            &held_nicks,
+        )
+    };
+
+    // alice drops her connection without a QUIT
+    let (mut alice, _alice_rx) = client("NICK alice\r\nUSER alice 0 * :Alice\r\n", [10, 0, 0, 1]);
+    alice.run();
+
+    let (mut mallory, mallory_rx) = client("NICK alice\r\n", [10, 0, 0, 2]);
+    assert!(mallory.step().is_ok());
+    assert_eq!(replies(&mallory_rx)[0].numeric(), Some(433));
+    assert_eq!(mallory.nick, None);
+
+    // alice can come back from the same address straight away
+    let (mut alice, _alice_rx) = client("NICK alice\r\nUSER alice 0 * :Alice\r\n", [10, 0, 0, 1]);
+    assert_eq!(alice.login(), Some(Nick("alice".to_string())));
+    alice.run();
+
+    thread::sleep(Duration::from_millis(250));
+    let (mut mallory, _mallory_rx) =
+        client("NICK alice\r\nUSER mal 0 * :Mallory\r\n", [10, 0, 0, 2]);
+    assert_eq!(mallory.login(), Some(Nick("alice".to_string())));
+}
+
+#[test]
+fn test_no_nick_hold_after_quit() {
+    let config = Arc::new(Config {
+        nick_hold: Duration::from_secs(60),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let held_nicks = HeldNicks::default();
+
+    let (mut alice, _alice_rx) = test_client_holding(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nQUIT\r\n",
+        ([10, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+        &held_nicks,
+    );
+    alice.run();
+
+    let (mut bob, _bob_rx) = test_client_holding(
+        "NICK alice\r\nUSER bob 0 * :Bob\r\n",
+        ([10, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+        &held_nicks,
+    );
+    assert_eq!(bob.login(), Some(Nick("alice".to_string())));
+}
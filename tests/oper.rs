@@ -0,0 +1,401 @@
+mod common;
+
+use std::{collections::HashMap, net::IpAddr, sync::Arc, time::Duration};
+
+use common::{capture_logs, logged, replies, test_client, test_client_holding};
+use iris_lib::{
+    client::{Channels, Clients, HeldNicks},
+    config::{hash_password, Config},
+    events::IrcEvent,
+    types::{Channel, Nick},
+};
+
+fn oper_config() -> Config {
+    Config {
+        opers: HashMap::from([("admin".to_string(), hash_password("hunter2"))]),
+        ..Config::default()
+    }
+}
+
+#[test]
+fn test_oper() {
+    let config = Arc::new(oper_config());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nOPER admin hunter3\r\nOPER alice hunter2\r\nOPER admin hunter2\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nWHOIS alice\r\nWHO alice\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // a wrong password or an unknown name is refused
+    for _ in 0..2 {
+        assert!(alice.step().is_ok());
+        assert_eq!(replies(&alice_rx)[0].numeric(), Some(464));
+        assert!(!alice.is_oper);
+    }
+
+    assert!(alice.step().is_ok());
+    assert!(alice.is_oper);
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice.len(), 2);
+    assert_eq!(replies_to_alice[0].numeric(), Some(381));
+    assert_eq!(replies_to_alice[1].command, "MODE");
+    assert_eq!(replies_to_alice[1].params, ["alice", "+o"]);
+
+    // others can see who is an operator
+    assert!(bob.step().is_ok());
+    let whois = replies(&bob_rx);
+    let oper_line = whois.iter().find(|line| line.numeric() == Some(313));
+    assert_eq!(oper_line.unwrap().params[1], "alice");
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].params[6], "H*");
+}
+
+#[test]
+fn test_oper_creates_channels() {
+    let config = Arc::new(Config {
+        restrict_channel_creation: true,
+        ..oper_config()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nOPER admin hunter2\r\nJOIN #new\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #other\r\nJOIN #new\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&bob_rx);
+
+    // restricted mode stops regular users creating channels, but not operators
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(403));
+    assert!(alice.step().is_ok());
+    assert!(alice.step().is_ok());
+    assert!(channels
+        .lock()
+        .unwrap()
+        .contains_key(&Channel("#new".to_string())));
+    replies(&alice_rx);
+
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].command, "JOIN");
+}
+
+#[test]
+fn test_kill() {
+    let config = Arc::new(Config {
+        nick_hold: Duration::from_secs(60),
+        ..oper_config()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let held_nicks = HeldNicks::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nKILL bob\r\nOPER admin hunter2\r\nKILL nobody\r\nKILL BOB :Flooding\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client_holding(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nPRIVMSG #a :too late\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+        &held_nicks,
+    );
+    let (mut carol, carol_rx) = test_client(
+        "NICK carol\r\nUSER carol 0 * :Carol\r\nJOIN #a\r\n",
+        ([127, 0, 0, 3], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    carol.login();
+    assert!(bob.step().is_ok());
+    assert!(carol.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+    replies(&carol_rx);
+
+    // only operators may kill
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].numeric(), Some(481));
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice[0].numeric(), Some(401));
+    assert_eq!(replies_to_alice[0].params[1], "nobody");
+
+    // the target is disconnected, then reaps itself and tells its channels why
+    assert!(alice.step().is_ok());
+    let events = bob_rx.try_iter().collect::<Vec<_>>();
+    assert!(matches!(
+        &events[..],
+        [IrcEvent::Send(error), IrcEvent::Terminate] if error.starts_with("ERROR :Killed (alice (Flooding))")
+    ));
+    assert!(replies(&carol_rx).is_empty());
+    bob.run();
+    let replies_to_carol = replies(&carol_rx);
+    assert_eq!(replies_to_carol.len(), 1);
+    assert_eq!(
+        replies_to_carol[0].prefix.as_deref(),
+        Some("bob!bob@127.0.0.2")
+    );
+    assert_eq!(replies_to_carol[0].command, "QUIT");
+    assert_eq!(replies_to_carol[0].params, ["Killed (alice (Flooding))"]);
+    assert!(!clients
+        .lock()
+        .unwrap()
+        .contains_key(&Nick("bob".to_string())));
+    assert!(!channels.lock().unwrap()[&Channel("#a".to_string())]
+        .members
+        .contains_key(&Nick("bob".to_string())));
+
+    // the nickname is free again, even from another address
+    assert!(held_nicks.lock().unwrap().is_empty());
+    let (mut bob, _bob_rx) = test_client_holding(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\n",
+        ([127, 0, 0, 4], 6991),
+        &config,
+        &clients,
+        &channels,
+        &held_nicks,
+    );
+    assert!(bob.login().is_some());
+    assert_eq!(
+        clients.lock().unwrap()[&Nick("bob".to_string())].addr.ip(),
+        IpAddr::from([127, 0, 0, 4])
+    );
+}
+
+#[test]
+fn test_wallops() {
+    let config = Arc::new(oper_config());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nWALLOPS :hello\r\nOPER admin hunter2\r\nWALLOPS :Restarting soon\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut carol, carol_rx) = test_client(
+        "NICK carol\r\nUSER carol 0 * :Carol\r\n",
+        ([127, 0, 0, 3], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    carol.login();
+    clients
+        .lock()
+        .unwrap()
+        .get_mut(&Nick("bob".to_string()))
+        .unwrap()
+        .wallops = true;
+    replies(&alice_rx);
+    replies(&bob_rx);
+    replies(&carol_rx);
+
+    // only operators may send them
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].numeric(), Some(481));
+    assert!(replies(&bob_rx).is_empty());
+
+    // and only those with +w get them
+    assert!(alice.step().is_ok());
+    assert!(alice.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob.len(), 1);
+    assert_eq!(
+        replies_to_bob[0].prefix.as_deref(),
+        Some("alice!alice@127.0.0.1")
+    );
+    assert_eq!(replies_to_bob[0].command, "WALLOPS");
+    assert_eq!(replies_to_bob[0].params, ["Restarting soon"]);
+    assert!(replies(&carol_rx).is_empty());
+}
+
+#[test]
+fn test_userip() {
+    let config = Arc::new(oper_config());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nUSERIP bob\r\nOPER admin hunter2\r\nUSERIP bob nobody\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bobby 0 * :Bob\r\n",
+        ([10, 1, 2, 3], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // only operators may look up addresses
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].numeric(), Some(481));
+
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    assert!(alice.step().is_ok());
+    let userip = &replies(&alice_rx)[0];
+    assert_eq!(userip.numeric(), Some(340));
+    assert_eq!(userip.params, ["alice", "bob=+bobby@10.1.2.3"]);
+}
+
+#[test]
+fn test_debug_state() {
+    let config = Arc::new(oper_config());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nOPER admin hunter2\r\nJOIN #a\r\nDEBUG state\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nDEBUG STATE\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // only operators may look
+    assert!(bob.step().is_ok());
+    let replies_to_bob = replies(&bob_rx);
+    assert_eq!(replies_to_bob.len(), 1);
+    assert_eq!(replies_to_bob[0].numeric(), Some(481));
+
+    assert!(alice.step().is_ok());
+    let state = replies(&alice_rx)
+        .into_iter()
+        .map(|line| {
+            assert_eq!(line.command, "NOTICE");
+            line.params[1].clone()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        state[..3],
+        ["Clients: 2", "Channels: 1", "Channel #a: 1 members"]
+    );
+    assert!(state.iter().any(|line| line.starts_with("Commands: ")
+        && line.contains("JOIN=1")
+        && line.contains("DEBUG=")));
+    assert_eq!(state.last().unwrap(), "End of state");
+}
+
+#[test]
+fn test_oper_override() {
+    capture_logs();
+    for oper_override in [false, true] {
+        let config = Arc::new(Config {
+            oper_override,
+            ..oper_config()
+        });
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nOPER admin hunter2\r\nJOIN #override\r\nKICK #override bob\r\n",
+            ([127, 0, 0, 1], 6991),
+            &config,
+            &clients,
+            &channels,
+        );
+        let (mut bob, _bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #override\r\nMODE #override +iklb secret 1 alice!*@*\r\n",
+            ([127, 0, 0, 2], 6991),
+            &config,
+            &clients,
+            &channels,
+        );
+        alice.login();
+        bob.login();
+        assert!(alice.step().is_ok());
+        assert!(bob.step().is_ok());
+        assert!(bob.step().is_ok());
+        replies(&alice_rx);
+
+        // an operator only gets past the channel's restrictions when overriding is allowed
+        alice.step().ok();
+        let joined = channels.lock().unwrap()[&Channel("#override".to_string())]
+            .members
+            .contains_key(&Nick("alice".to_string()));
+        assert_eq!(joined, oper_override);
+        if !oper_override {
+            assert_eq!(replies(&alice_rx)[0].numeric(), Some(474));
+        }
+        for restriction in ["+b", "+i", "+k", "+l"] {
+            assert_eq!(
+                logged(&format!(
+                    "Oper override: alice got past {restriction} on #override"
+                )),
+                oper_override
+            );
+        }
+
+        // or acts as a channel operator without being one
+        assert!(alice.step().is_ok());
+        let kicked = !channels.lock().unwrap()[&Channel("#override".to_string())]
+            .members
+            .contains_key(&Nick("bob".to_string()));
+        assert_eq!(kicked, oper_override);
+    }
+}
@@ -0,0 +1,415 @@
+mod common;
+
+use std::{
+    sync::{mpsc, Arc},
+    time::Duration,
+};
+
+use common::{capture_logs, logged, replies, test_client};
+use iris_lib::{
+    client::{Channels, Clients, Member},
+    config::Config,
+    types::{Channel, Nick},
+};
+
+#[test]
+fn test_privmsg_dedup() {
+    let config = Arc::new(Config {
+        privmsg_dedup_window: Some(Duration::from_secs(60)),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nPRIVMSG bob :hi\r\nPRIVMSG bob :hi\r\n\
+         PRIVMSG bob :bye\r\nPRIVMSG bob :hi\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    for _ in 0..4 {
+        assert!(alice.step().is_ok());
+    }
+
+    // only the immediate repeat is dropped, and alice is told about it
+    let texts = replies(&bob_rx)
+        .into_iter()
+        .map(|line| line.params[1].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(texts, ["hi", "bye", "hi"]);
+
+    let notices = replies(&alice_rx);
+    assert_eq!(notices.len(), 1);
+    assert_eq!(notices[0].command, "NOTICE");
+}
+
+#[test]
+fn test_notice() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nNOTICE bob :hi\r\nNOTICE #a :all\r\n\
+         NOTICE nobody :hi\r\nNOTICE #none :hi\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    for _ in 0..4 {
+        assert!(alice.step().is_ok());
+    }
+
+    let notices = replies(&bob_rx);
+    assert_eq!(notices.len(), 2);
+    assert!(notices
+        .iter()
+        .all(|line| line.command == "NOTICE"
+            && line.prefix.as_deref() == Some("alice!alice@127.0.0.1")));
+    assert_eq!(notices[0].params, ["bob", "hi"]);
+    assert_eq!(notices[1].params, ["#a", "all"]);
+
+    // no errors for unknown targets, and no echo of the channel notice
+    assert!(replies(&alice_rx).is_empty());
+}
+
+#[test]
+fn test_malformed_notice() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nNOTICE\r\nNOTICE bob\r\nNOTICE bob :\r\nnotice :\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // dropped without the 411 or 412 a PRIVMSG would get
+    for _ in 0..4 {
+        assert!(alice.step().is_err());
+        assert!(replies(&alice_rx).is_empty());
+    }
+    assert!(replies(&bob_rx).is_empty());
+}
+
+#[test]
+fn test_large_broadcast() {
+    capture_logs();
+    let config = Arc::new(Config {
+        broadcast_warn_threshold: Some(100),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #big\r\nPRIVMSG #big :hello everyone\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+
+    // far more members than real clients could be set up for
+    let members = (0..500)
+        .map(|i| {
+            let (tx, rx) = mpsc::channel();
+            channels
+                .lock()
+                .unwrap()
+                .get_mut(&Channel("#big".to_string()))
+                .unwrap()
+                .members
+                .insert(Nick(format!("user{i}")), Member::new(tx));
+            rx
+        })
+        .collect::<Vec<_>>();
+
+    assert!(alice.step().is_ok());
+    assert!(logged("Large broadcast to #big: 500 recipients"));
+    for rx in &members {
+        let received = replies(rx);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].params, ["#big", "hello everyone"]);
+    }
+}
+
+#[test]
+fn test_away() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nPRIVMSG bob :hi\r\nWHO bob\r\nWHOIS bob\r\n\
+         PRIVMSG bob :hi again\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nAWAY :Gone to lunch\r\nAWAY\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    assert!(bob.step().is_ok());
+    let away = replies(&bob_rx);
+    assert_eq!(away.len(), 1);
+    assert_eq!(away[0].numeric(), Some(306));
+
+    // the message is still delivered, and the sender told why it may go unread
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].params[1], "hi");
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice.len(), 1);
+    assert_eq!(replies_to_alice[0].numeric(), Some(301));
+    assert_eq!(
+        replies_to_alice[0].params,
+        ["alice", "bob", "Gone to lunch"]
+    );
+
+    // WHO shows bob as gone, WHOIS shows why
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params[6], "G");
+    assert!(alice.step().is_ok());
+    assert!(replies(&alice_rx)
+        .iter()
+        .any(|line| line.numeric() == Some(301)));
+
+    // back again
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&bob_rx)[0].numeric(), Some(305));
+    assert!(alice.step().is_ok());
+    assert!(replies(&alice_rx).is_empty());
+}
+
+#[test]
+fn test_external_messages() {
+    for require_channel_membership in [false, true] {
+        let config = Arc::new(Config {
+            require_channel_membership,
+            ..Config::default()
+        });
+        let clients = Clients::default();
+        let channels = Channels::default();
+        let (mut alice, alice_rx) = test_client(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a -n\r\n",
+            ([127, 0, 0, 1], 6991),
+            &config,
+            &clients,
+            &channels,
+        );
+        let (mut bob, bob_rx) = test_client(
+            "NICK bob\r\nUSER bob 0 * :Bob\r\nPRIVMSG #a :hi\r\nNOTICE #a :hi\r\nPRIVMSG #a :hi again\r\n",
+            ([127, 0, 0, 2], 6991),
+            &config,
+            &clients,
+            &channels,
+        );
+        alice.login();
+        bob.login();
+        assert!(alice.step().is_ok());
+        replies(&alice_rx);
+        replies(&bob_rx);
+
+        // new channels don't take messages from outside
+        assert!(bob.step().is_ok());
+        let replies_to_bob = replies(&bob_rx);
+        assert_eq!(replies_to_bob[0].numeric(), Some(404));
+        assert_eq!(replies_to_bob[0].params[1], "#a");
+        assert!(bob.step().is_ok());
+        assert!(replies(&bob_rx).is_empty());
+        assert!(replies(&alice_rx).is_empty());
+
+        // unless the channel allows them and the server doesn't override it
+        assert!(alice.step().is_ok());
+        replies(&alice_rx);
+        assert!(bob.step().is_ok());
+        if require_channel_membership {
+            assert_eq!(replies(&bob_rx)[0].numeric(), Some(404));
+            assert!(replies(&alice_rx).is_empty());
+        } else {
+            assert!(replies(&bob_rx).is_empty());
+            assert_eq!(replies(&alice_rx)[0].params, ["#a", "hi again"]);
+        }
+    }
+}
+
+#[test]
+fn test_privmsg_errors() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nPRIVMSG #a :\r\nPRIVMSG bob :\r\nPRIVMSG :\r\nPRIVMSG\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // empty text to a channel or a user
+    for _ in 0..2 {
+        assert!(alice.step().is_err());
+        let error = &replies(&alice_rx)[0];
+        assert_eq!(error.numeric(), Some(412));
+        assert_eq!(error.params, ["alice", "No text to send"]);
+    }
+
+    // no target, empty or missing
+    for _ in 0..2 {
+        assert!(alice.step().is_err());
+        let error = &replies(&alice_rx)[0];
+        assert_eq!(error.numeric(), Some(411));
+        assert_eq!(error.params[0], "alice");
+    }
+
+    assert!(replies(&bob_rx).is_empty());
+}
+
+#[test]
+fn test_privmsg_with_commas() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let text = vec!["a"; 100].join(",");
+    let (mut alice, alice_rx) = test_client(
+        &format!("NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nPRIVMSG #a :{text}\r\n"),
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // the target cap only counts commas in the middle parameters
+    assert!(alice.step().is_ok());
+    assert!(replies(&alice_rx).is_empty());
+    assert_eq!(replies(&bob_rx)[0].params, ["#a", text.as_str()]);
+}
+
+#[test]
+fn test_statusmsg() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +v carol\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nPRIVMSG @#a :ops only\r\nNOTICE +#a :voiced too\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut carol, carol_rx) = test_client(
+        "NICK carol\r\nUSER carol 0 * :Carol\r\nJOIN #a\r\n",
+        ([127, 0, 0, 3], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    carol.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    assert!(carol.step().is_ok());
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+    replies(&carol_rx);
+
+    // the operator gets it, addressed as sent, but a voiced member doesn't
+    assert!(bob.step().is_ok());
+    let to_alice = replies(&alice_rx);
+    assert_eq!(to_alice.len(), 1);
+    assert_eq!(to_alice[0].command, "PRIVMSG");
+    assert_eq!(to_alice[0].params, ["@#a", "ops only"]);
+    assert!(replies(&carol_rx).is_empty());
+
+    // voiced members and anyone above them get `+` messages
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["+#a", "voiced too"]);
+    assert_eq!(replies(&carol_rx)[0].params, ["+#a", "voiced too"]);
+    assert!(replies(&bob_rx).is_empty());
+}
@@ -0,0 +1,306 @@
+mod common;
+
+use std::sync::{mpsc, Arc};
+
+use common::{replies, test_client};
+use iris_lib::{
+    client::{Channels, Client, Clients, HeldNicks, ServerInfo},
+    config::{Config, HostnameProvider},
+    connect::ConnectionRead,
+    metrics::Metrics,
+    types::{Nick, SERVER_NAME},
+};
+
+#[test]
+fn test_unique_user_per_ip() {
+    let config = Arc::new(Config {
+        unique_user_per_ip: true,
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+
+    let (mut first, _first_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\n",
+        ([10, 0, 0, 1], 50000),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut second, second_rx) = test_client(
+        "NICK alice2\r\nUSER alice 0 * :Alice\r\n",
+        ([10, 0, 0, 1], 50001),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut elsewhere, _elsewhere_rx) = test_client(
+        "NICK alice3\r\nUSER alice 0 * :Alice\r\n",
+        ([10, 0, 0, 2], 50000),
+        &config,
+        &clients,
+        &channels,
+    );
+
+    assert_eq!(first.login(), Some(Nick("alice".to_string())));
+    assert_eq!(second.login(), None);
+    assert_eq!(elsewhere.login(), Some(Nick("alice3".to_string())));
+
+    assert_eq!(replies(&second_rx).last().unwrap().command, "ERROR");
+    assert!(!clients
+        .lock()
+        .unwrap()
+        .contains_key(&Nick("alice2".to_string())));
+}
+
+#[test]
+fn test_password() {
+    let config = Arc::new(Config {
+        password: Some("secret".to_string()),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+
+    let (mut right, _right_rx) = test_client(
+        "PASS secret\r\nNICK alice\r\nUSER alice 0 * :Alice\r\n",
+        ([10, 0, 0, 1], 50000),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut wrong, wrong_rx) = test_client(
+        "PASS guess\r\nNICK bob\r\nUSER bob 0 * :Bob\r\n",
+        ([10, 0, 0, 2], 50000),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut late, late_rx) = test_client(
+        "NICK carol\r\nPASS secret\r\nUSER carol 0 * :Carol\r\n",
+        ([10, 0, 0, 3], 50000),
+        &config,
+        &clients,
+        &channels,
+    );
+
+    assert_eq!(right.login(), Some(Nick("alice".to_string())));
+    for (client, rx) in [(&mut wrong, &wrong_rx), (&mut late, &late_rx)] {
+        assert_eq!(client.login(), None);
+        let replies = replies(rx);
+        assert_eq!(replies[0].numeric(), Some(464));
+        assert_eq!(replies[1].command, "ERROR");
+    }
+    assert_eq!(clients.lock().unwrap().len(), 1);
+
+    // without a password configured, PASS is ignored
+    let (mut anyone, _anyone_rx) = test_client(
+        "PASS guess\r\nNICK dave\r\nUSER dave 0 * :Dave\r\n",
+        ([10, 0, 0, 4], 50000),
+        &Arc::new(Config::default()),
+        &clients,
+        &channels,
+    );
+    assert_eq!(anyone.login(), Some(Nick("dave".to_string())));
+}
+
+#[test]
+fn test_user_after_registration() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let (mut bob, rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob The Builder\r\nUSER robert 0 * :Robert\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &Channels::default(),
+    );
+
+    bob.login();
+    assert_eq!(
+        replies(&rx)[0].params,
+        ["bob", "Hi Bob The Builder, welcome to IRC"]
+    );
+
+    assert!(bob.step().is_ok());
+    assert_eq!(replies(&rx)[0].numeric(), Some(462));
+    assert_eq!(bob.user.as_deref(), Some("bob"));
+    assert_eq!(bob.real_name.as_deref(), Some("Bob The Builder"));
+}
+
+#[test]
+fn test_motd() {
+    let (tx, rx) = mpsc::channel();
+    let mut client = Client::new(
+        ConnectionRead::from_bytes(
+            "NICK alice\r\nUSER alice 0 * :Alice\r\nMOTD\r\n",
+            ([127, 0, 0, 1], 6991).into(),
+        ),
+        tx,
+        Arc::new(Config::default()),
+        Arc::new(Metrics::default()),
+        Clients::default(),
+        Channels::default(),
+        HeldNicks::default(),
+        Arc::new(ServerInfo {
+            motd: vec!["Welcome to iris".to_string(), "Be nice".to_string()],
+            ..ServerInfo::default()
+        }),
+    );
+
+    // sent straight after the welcome
+    client.login();
+    let motd = replies(&rx);
+    assert_eq!(
+        motd.iter()
+            .map(|line| line.numeric().unwrap())
+            .collect::<Vec<_>>(),
+        [1, 2, 3, 4, 5, 375, 372, 372, 376]
+    );
+    assert_eq!(motd[6].params, ["alice", "- Welcome to iris"]);
+
+    // and again on request
+    assert!(client.step().is_ok());
+    assert_eq!(replies(&rx).len(), 4);
+}
+
+#[test]
+fn test_no_motd() {
+    let config = Arc::new(Config::default());
+    let (mut client, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nMOTD\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &Clients::default(),
+        &Channels::default(),
+    );
+
+    client.login();
+    assert_eq!(replies(&rx)[5].numeric(), Some(422));
+    assert!(client.step().is_ok());
+    assert_eq!(replies(&rx)[0].numeric(), Some(422));
+}
+
+struct FakeHostname(Option<&'static str>);
+
+impl HostnameProvider for FakeHostname {
+    fn hostname(&self) -> Option<String> {
+        self.0.map(str::to_string)
+    }
+}
+
+#[test]
+fn test_server_name() {
+    let config = Arc::new(Config {
+        detect_server_name: true,
+        ..Config::default()
+    });
+
+    for (hostname, server_name) in [
+        (Some("irc.example.com"), "irc.example.com"),
+        (None, SERVER_NAME),
+    ] {
+        let (tx, rx) = mpsc::channel();
+        let mut client = Client::new(
+            ConnectionRead::from_bytes(
+                "NICK alice\r\nUSER alice 0 * :Alice\r\n",
+                ([127, 0, 0, 1], 6991).into(),
+            ),
+            tx,
+            config.clone(),
+            Arc::new(Metrics::default()),
+            Clients::default(),
+            Channels::default(),
+            HeldNicks::default(),
+            Arc::new(ServerInfo {
+                name: config.resolve_server_name(&FakeHostname(hostname)),
+                ..ServerInfo::default()
+            }),
+        );
+
+        client.login();
+        let welcome = &replies(&rx)[0];
+        assert_eq!(welcome.numeric(), Some(1));
+        assert_eq!(welcome.prefix.as_deref(), Some(server_name));
+    }
+}
+
+#[test]
+fn test_cap() {
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, rx) = test_client(
+        "CAP LS 302\r\nCAP REQ :multi-prefix\r\nCAP END\r\nNICK alice\r\nUSER alice 0 * :Alice\r\n\
+         CAP REQ :-multi-prefix away-notify\r\nCAP LIST\r\nCAP REQ :-multi-prefix\r\nCAP LIST\r\n\
+         CAP FOO\r\n",
+        ([127, 0, 0, 1], 6991),
+        &Arc::new(Config::default()),
+        &clients,
+        &channels,
+    );
+
+    // before registering, replies go to `*`
+    assert!(alice.step().is_ok());
+    let ls = &replies(&rx)[0];
+    assert_eq!(ls.command, "CAP");
+    assert_eq!(ls.params, ["*", "LS", "multi-prefix"]);
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx)[0].params, ["*", "ACK", "multi-prefix"]);
+    assert!(alice.step().is_ok());
+    assert!(replies(&rx).is_empty());
+    alice.login();
+    replies(&rx);
+
+    // one unsupported capability refuses the whole request, leaving what was enabled
+    assert!(alice.step().is_ok());
+    assert_eq!(
+        replies(&rx)[0].params,
+        ["alice", "NAK", "-multi-prefix away-notify"]
+    );
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx)[0].params, ["alice", "LIST", "multi-prefix"]);
+
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx)[0].params, ["alice", "ACK", "-multi-prefix"]);
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&rx)[0].params, ["alice", "LIST", ""]);
+
+    assert!(alice.step().is_ok());
+    let error = &replies(&rx)[0];
+    assert_eq!(error.numeric(), Some(410));
+    assert_eq!(error.params[..2], ["alice", "FOO"]);
+}
+
+#[test]
+fn test_cap_multi_prefix() {
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nMODE #a +v alice\r\nNAMES #a\r\n\
+         CAP REQ multi-prefix\r\nNAMES #a\r\nWHO #a\r\nWHOIS alice\r\n",
+        ([127, 0, 0, 1], 6991),
+        &Arc::new(Config::default()),
+        &clients,
+        &channels,
+    );
+    alice.login();
+    for _ in 0..2 {
+        assert!(alice.step().is_ok());
+    }
+    replies(&alice_rx);
+
+    // only the highest status until multi-prefix is enabled
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params[3], "@alice");
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params[3], "@+alice");
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params[6], "H@+");
+    assert!(alice.step().is_ok());
+    assert!(replies(&alice_rx)
+        .iter()
+        .any(|line| line.numeric() == Some(319) && line.params[2] == "@+#a"));
+}
@@ -0,0 +1,166 @@
+mod common;
+
+use std::sync::Arc;
+
+use common::{replies, test_client};
+use iris_lib::{
+    client::{Channels, Clients},
+    config::Config,
+    types::MAX_NICK_LEN,
+};
+
+#[test]
+fn test_welcome_numeric() {
+    let (mut client, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\n",
+        ([127, 0, 0, 1], 6991),
+        &Arc::new(Config::default()),
+        &Clients::default(),
+        &Channels::default(),
+    );
+    client.login();
+
+    let replies = replies(&rx);
+    assert_eq!(replies[0].numeric(), Some(1));
+    assert_eq!(replies[0].params[0], "alice");
+}
+
+#[test]
+fn test_banner() {
+    let config = Config {
+        banner: true,
+        ..Config::default()
+    };
+    let (mut client, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\n",
+        ([127, 0, 0, 1], 6991),
+        &Arc::new(config),
+        &Clients::default(),
+        &Channels::default(),
+    );
+    client.login();
+
+    assert!(replies(&rx).iter().any(|line| line.command == "NOTICE"
+        && line.params[1] == format!("Maximum nickname length: {MAX_NICK_LEN}")));
+}
+
+#[test]
+fn test_error_numerics() {
+    let (mut client, rx) = test_client(
+        "NICK 1alice\r\nFOO bar\r\nNICK alice\r\nUSER alice 0 * :Alice\r\nJOIN\r\nPART #none\r\n\
+         PRIVMSG bob :hi\r\n",
+        ([127, 0, 0, 1], 6991),
+        &Arc::new(Config::default()),
+        &Clients::default(),
+        &Channels::default(),
+    );
+
+    // before registration errors go to `*`
+    assert!(client.step().is_err());
+    let error = &replies(&rx)[0];
+    assert_eq!(error.prefix.as_deref(), Some("iris-server"));
+    assert_eq!(error.numeric(), Some(432));
+    assert_eq!(error.params, ["*", "1alice", "Erroneus nickname"]);
+    assert!(client.step().is_err());
+    assert_eq!(replies(&rx)[0].params, ["*", "FOO", "Unknown command"]);
+
+    client.login();
+    replies(&rx);
+
+    // afterwards, to the client's nickname, naming what they're about
+    assert!(client.step().is_err());
+    assert_eq!(
+        replies(&rx)[0].params,
+        ["alice", "JOIN", "Not enough parameters"]
+    );
+    assert!(client.step().is_ok());
+    assert_eq!(
+        replies(&rx)[0].params,
+        ["alice", "#none", "No such channel"]
+    );
+    assert!(client.step().is_ok());
+    let error = &replies(&rx)[0];
+    assert_eq!(error.numeric(), Some(401));
+    assert_eq!(error.params, ["alice", "bob", "No such nick/channel"]);
+}
+
+#[test]
+fn test_registration_burst() {
+    let config = Config {
+        network_name: Some("IrisNet".to_string()),
+        ..Config::default()
+    };
+    let (mut client, rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\n",
+        ([127, 0, 0, 1], 6991),
+        &Arc::new(config),
+        &Clients::default(),
+        &Channels::default(),
+    );
+    client.login();
+
+    let replies = replies(&rx);
+    assert_eq!(
+        replies[..5]
+            .iter()
+            .map(|line| line.numeric().unwrap())
+            .collect::<Vec<_>>(),
+        [1, 2, 3, 4, 5]
+    );
+    assert!(replies[1].params[1].contains("iris-server"));
+    assert!(replies[2].params[1].ends_with(" UTC"));
+    assert_eq!(replies[3].params[..2], ["alice", "iris-server"]);
+    assert_eq!(replies[3].params[3..], ["iow", "biklmnoqv"]);
+
+    let isupport = &replies[4].params;
+    assert_eq!(isupport.last().unwrap(), "are supported by this server");
+    for token in [
+        "CHANTYPES=#&",
+        "PREFIX=(ov)@+",
+        "STATUSMSG=@+",
+        "CHANMODES=bq,k,l,imn",
+        &format!("NICKLEN={MAX_NICK_LEN}"),
+        "CHANNELLEN=200",
+        "NETWORK=IrisNet",
+    ] {
+        assert!(isupport.iter().any(|param| param == token), "{token}");
+    }
+}
+
+#[test]
+fn test_nick_len() {
+    // ten characters is one too many by default
+    let (mut client, rx) = test_client(
+        "NICK abcdefghij\r\n",
+        ([127, 0, 0, 1], 6991),
+        &Arc::new(Config::default()),
+        &Clients::default(),
+        &Channels::default(),
+    );
+    let _ = client.step();
+    let error = &replies(&rx)[0];
+    assert_eq!(error.numeric(), Some(432));
+    assert_eq!(error.params[..2], ["*", "abcdefghij"]);
+
+    // but fine once the limit is raised, which NICKLEN then advertises
+    let config = Config {
+        max_nick_len: Some(12),
+        ..Config::default()
+    };
+    let (mut client, rx) = test_client(
+        "NICK abcdefghij\r\nUSER alice 0 * :Alice\r\nNICK abcdefghijklm\r\n",
+        ([127, 0, 0, 1], 6991),
+        &Arc::new(config),
+        &Clients::default(),
+        &Channels::default(),
+    );
+    client.login();
+    let burst = replies(&rx);
+    assert_eq!(burst[0].params[0], "abcdefghij");
+    assert!(burst[4].params.iter().any(|param| param == "NICKLEN=12"));
+
+    let _ = client.step();
+    let error = &replies(&rx)[0];
+    assert_eq!(error.numeric(), Some(432));
+    assert_eq!(error.params[..2], ["abcdefghij", "abcdefghijklm"]);
+}
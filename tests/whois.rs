@@ -0,0 +1,370 @@
+mod common;
+
+use std::{collections::HashMap, sync::Arc};
+
+use common::{replies, test_client};
+use iris_lib::{
+    client::{Channels, Clients},
+    config::{hash_password, Config},
+};
+
+#[test]
+fn test_whois() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice Smith\r\nWHOIS BOB\r\nWHOIS carol\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK Bob\r\nUSER bobby 0 * :Bob Jones\r\nJOIN #b,#a\r\nWHOIS bob\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // user, channels, server, idle, then the end of the list
+    assert!(alice.step().is_ok());
+    let whois = replies(&alice_rx);
+    assert_eq!(
+        whois
+            .iter()
+            .map(|line| line.numeric().unwrap())
+            .collect::<Vec<_>>(),
+        [311, 319, 312, 317, 318]
+    );
+    assert_eq!(
+        whois[0].params,
+        ["alice", "Bob", "bobby", "127.0.0.2", "*", "Bob Jones"]
+    );
+    assert_eq!(whois[1].params, ["alice", "Bob", "@#a @#b"]);
+    assert_eq!(whois[3].params[2], "0");
+    assert_eq!(whois[4].params[1], "Bob");
+
+    // nobody by that name
+    assert!(alice.step().is_ok());
+    let whois = replies(&alice_rx);
+    assert_eq!(
+        whois
+            .iter()
+            .map(|line| line.numeric().unwrap())
+            .collect::<Vec<_>>(),
+        [401, 318]
+    );
+    assert_eq!(whois[1].params[1], "carol");
+
+    // asking about yourself works the same way
+    assert!(bob.step().is_ok());
+    let whois = replies(&bob_rx);
+    assert_eq!(
+        whois
+            .iter()
+            .map(|line| line.numeric().unwrap())
+            .collect::<Vec<_>>(),
+        [311, 319, 312, 317, 318]
+    );
+}
+
+#[test]
+fn test_who() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice Smith\r\nJOIN #a\r\nWHO #A\r\nWHO b*\r\nWHO #none\r\nWHO\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK Bob\r\nUSER bobby 0 * :Bob Jones\r\nJOIN #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut carol, carol_rx) = test_client(
+        "NICK carol\r\nUSER carol 0 * :Carol\r\n",
+        ([127, 0, 0, 3], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    carol.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+    replies(&carol_rx);
+
+    // a channel lists its members
+    assert!(alice.step().is_ok());
+    let who = replies(&alice_rx);
+    assert_eq!(who.len(), 3);
+    assert_eq!(
+        who[0].params,
+        [
+            "alice",
+            "#A",
+            "bobby",
+            "127.0.0.2",
+            "iris-server",
+            "Bob",
+            "H",
+            "0 Bob Jones"
+        ]
+    );
+    assert_eq!(who[1].params[5], "alice");
+    assert_eq!(who[2].numeric(), Some(315));
+    assert_eq!(who[2].params[1], "#A");
+
+    // anything else is matched against nicknames
+    assert!(alice.step().is_ok());
+    let who = replies(&alice_rx);
+    assert_eq!(who.len(), 2);
+    assert_eq!(who[0].params[1], "*");
+    assert_eq!(who[0].params[5], "Bob");
+
+    // a channel nobody is in just ends the list
+    assert!(alice.step().is_ok());
+    let who = replies(&alice_rx);
+    assert_eq!(who.len(), 1);
+    assert_eq!(who[0].numeric(), Some(315));
+
+    // no mask lists everyone
+    assert!(alice.step().is_ok());
+    let nicks = replies(&alice_rx)
+        .into_iter()
+        .filter(|line| line.numeric() == Some(352))
+        .map(|line| line.params[5].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(nicks, ["Bob", "alice", "carol"]);
+}
+
+#[test]
+fn test_ison() {
+    let config = Arc::new(Config::default());
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nISON BOB carol alice\r\nISON\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, _bob_rx) = test_client(
+        "NICK Bob\r\nUSER bob 0 * :Bob\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    replies(&alice_rx);
+
+    // nicknames match whatever their case, and are shown as registered
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice.len(), 1);
+    assert_eq!(replies_to_alice[0].numeric(), Some(303));
+    assert_eq!(replies_to_alice[0].params, ["alice", "Bob alice"]);
+
+    assert!(alice.step().is_ok());
+    assert_eq!(replies(&alice_rx)[0].params, ["alice", ""]);
+}
+
+#[test]
+fn test_userhost() {
+    let config = Arc::new(Config {
+        opers: HashMap::from([("admin".to_string(), hash_password("hunter2"))]),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nOPER admin hunter2\r\nUSERHOST nobody BOB alice x y bob\r\nUSERHOST\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, _bob_rx) = test_client(
+        "NICK bob\r\nUSER robert 0 * :Bob\r\nAWAY :Lunch\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+
+    // only the first five nicknames count, and unknown ones are left out
+    assert!(alice.step().is_ok());
+    let replies_to_alice = replies(&alice_rx);
+    assert_eq!(replies_to_alice.len(), 1);
+    assert_eq!(replies_to_alice[0].numeric(), Some(302));
+    assert_eq!(
+        replies_to_alice[0].params,
+        ["alice", "bob=-robert@127.0.0.2 alice*=+alice@127.0.0.1"]
+    );
+
+    assert!(alice.step().is_err());
+    assert_eq!(replies(&alice_rx)[0].numeric(), Some(461));
+}
+
+#[test]
+fn test_whois_order() {
+    let config = Arc::new(Config {
+        opers: HashMap::from([("admin".to_string(), hash_password("hunter2"))]),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nOPER admin hunter2\r\nAWAY :Lunch\r\nJOIN #b\r\nJOIN #a,#c\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a,#c\r\nMODE #a +v alice\r\nWHOIS alice\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(bob.step().is_ok());
+    for _ in 0..4 {
+        assert!(alice.step().is_ok());
+    }
+    assert!(bob.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // user, channels, server, away, idle, operator, then the end of the list
+    assert!(bob.step().is_ok());
+    let whois = replies(&bob_rx);
+    assert_eq!(
+        whois
+            .iter()
+            .map(|line| line.numeric().unwrap())
+            .collect::<Vec<_>>(),
+        [311, 319, 312, 301, 317, 313, 318]
+    );
+    assert_eq!(whois[1].params, ["bob", "alice", "+#a @#b #c"]);
+    assert_eq!(whois[3].params, ["bob", "alice", "Lunch"]);
+}
+
+#[test]
+fn test_whois_actually() {
+    let config = Arc::new(Config {
+        opers: HashMap::from([("admin".to_string(), hash_password("hunter2"))]),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nOPER admin hunter2\r\nWHOIS bob\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nWHOIS alice\r\n",
+        ([127, 0, 0, 2], 50123),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // operators see the real address, source port included
+    assert!(alice.step().is_ok());
+    let whois = replies(&alice_rx);
+    let actually = whois
+        .iter()
+        .find(|line| line.numeric() == Some(338))
+        .unwrap();
+    assert_eq!(actually.params[..3], ["alice", "bob", "127.0.0.2:50123"]);
+    assert_eq!(whois.last().unwrap().numeric(), Some(318));
+
+    // others don't
+    assert!(bob.step().is_ok());
+    assert!(replies(&bob_rx)
+        .iter()
+        .all(|line| line.numeric() != Some(338)));
+}
+
+#[test]
+fn test_who_away_op() {
+    let config = Arc::new(Config {
+        opers: HashMap::from([("admin".to_string(), hash_password("hunter2"))]),
+        ..Config::default()
+    });
+    let clients = Clients::default();
+    let channels = Channels::default();
+    let (mut alice, alice_rx) = test_client(
+        "NICK alice\r\nUSER alice 0 * :Alice\r\nJOIN #a\r\nAWAY :Lunch\r\nOPER admin hunter2\r\n",
+        ([127, 0, 0, 1], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    let (mut bob, bob_rx) = test_client(
+        "NICK bob\r\nUSER bob 0 * :Bob\r\nJOIN #a\r\nWHO #a\r\nWHO #a\r\n",
+        ([127, 0, 0, 2], 6991),
+        &config,
+        &clients,
+        &channels,
+    );
+    alice.login();
+    bob.login();
+    assert!(alice.step().is_ok());
+    assert!(bob.step().is_ok());
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    replies(&bob_rx);
+
+    // away first, then the channel status
+    let alice_flags = |who: Vec<common::Line>| {
+        who.into_iter()
+            .find(|line| line.numeric() == Some(352) && line.params[5] == "alice")
+            .unwrap()
+            .params[6]
+            .clone()
+    };
+    assert!(bob.step().is_ok());
+    assert_eq!(alice_flags(replies(&bob_rx)), "G@");
+
+    // with operator status in between
+    assert!(alice.step().is_ok());
+    replies(&alice_rx);
+    assert!(bob.step().is_ok());
+    assert_eq!(alice_flags(replies(&bob_rx)), "G*@");
+}